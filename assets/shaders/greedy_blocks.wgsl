@@ -0,0 +1,100 @@
+// The fragment shader of greedy chunk meshes, in the main pass and in the prepass. The uvs of their
+// vertices hold the atlas tile of the face times `tile_stride`, plus how many blocks along the quad
+// the vertex is. The tile is repeated over the quad, before the standard material samples it.
+#import bevy_pbr::{
+    pbr_bindings::material,
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions,
+    pbr_types,
+}
+
+#ifdef PREPASS_PIPELINE
+#import bevy_pbr::{
+    prepass_io::{VertexOutput, FragmentOutput},
+    pbr_prepass_functions,
+}
+#else
+#import bevy_pbr::forward_io::{VertexOutput, FragmentOutput}
+#endif
+
+struct AtlasTiling {
+    atlas_size: vec2<f32>,
+    padding: f32,
+    tile_stride: f32,
+}
+
+@group(1) @binding(100) var<uniform> tiling: AtlasTiling;
+
+fn tile_uv(in: VertexOutput) -> VertexOutput {
+    var out = in;
+#ifdef VERTEX_UVS
+    // The quads are at most half the stride long, so the uvs on their edges that were interpolated
+    // a bit past the tile still round to it.
+    let tile = floor(in.uv / tiling.tile_stride + 0.25);
+    let local = fract(in.uv - tile * tiling.tile_stride);
+    out.uv = (tile + tiling.padding + local * (1.0 - 2.0 * tiling.padding)) / tiling.atlas_size;
+#endif
+    return out;
+}
+
+#ifdef PREPASS_PIPELINE
+#ifdef PREPASS_FRAGMENT
+@fragment
+fn fragment(
+    vertex: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    let in = tile_uv(vertex);
+    pbr_prepass_functions::prepass_alpha_discard(in);
+
+    var out: FragmentOutput;
+
+#ifdef DEPTH_CLAMP_ORTHO
+    out.frag_depth = in.clip_position_unclamped.z;
+#endif
+
+#ifdef NORMAL_PREPASS
+    let double_sided = (material.flags & pbr_types::STANDARD_MATERIAL_FLAGS_DOUBLE_SIDED_BIT) != 0u;
+    let normal = pbr_functions::prepare_world_normal(in.world_normal, double_sided, is_front);
+    out.normal = vec4(normal * 0.5 + vec3(0.5), 1.0);
+#endif
+
+#ifdef MOTION_VECTOR_PREPASS
+    out.motion_vector = pbr_prepass_functions::calculate_motion_vector(
+        in.world_position,
+        in.previous_world_position,
+    );
+#endif
+
+    return out;
+}
+#else
+@fragment
+fn fragment(vertex: VertexOutput) {
+    pbr_prepass_functions::prepass_alpha_discard(tile_uv(vertex));
+}
+#endif
+
+#else
+@fragment
+fn fragment(
+    vertex: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    let in = tile_uv(vertex);
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+    pbr_input.material.base_color = pbr_functions::alpha_discard(
+        pbr_input.material,
+        pbr_input.material.base_color,
+    );
+
+    var out: FragmentOutput;
+    if (pbr_input.material.flags & pbr_types::STANDARD_MATERIAL_FLAGS_UNLIT_BIT) == 0u {
+        out.color = pbr_functions::apply_pbr_lighting(pbr_input);
+    } else {
+        out.color = pbr_input.material.base_color;
+    }
+    out.color = pbr_functions::main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
+#endif
@@ -0,0 +1,48 @@
+// Meshes the chunks in the render distance around the origin with culling and greedily, and prints
+// how many vertices the meshes have and how long they took to make. Run it with
+// `cargo bench --bench meshing`.
+use bevy::utils::Instant;
+use minecraft_bevy::*;
+
+fn main() {
+    let world_settings = WorldSettings::default();
+    let breg = BlockRegistry::default();
+    let generator = TerrainGenerator::new(world_settings.seed, world_settings.terrain());
+    let render_distance = world_settings.render_distance;
+    let mut grids = vec![];
+    for x in -render_distance..=render_distance {
+        for z in -render_distance..=render_distance {
+            for y in 0..WORLD_HEIGHT_CHUNKS {
+                grids.push(([x, y, z], generate_chunk([x, y, z], &generator).to_grid()));
+            }
+        }
+    }
+    println!(
+        "Meshing {} chunks (seed {}, render distance {})",
+        grids.len(),
+        world_settings.seed,
+        render_distance
+    );
+    println!(
+        "{:<10}{:>12}{:>12}{:>12}{:>16}",
+        "meshing", "vertices", "triangles", "time (ms)", "per chunk (us)"
+    );
+    for meshing in [MeshingMode::Culling, MeshingMode::Greedy] {
+        let (mut vertices, mut triangles) = (0, 0);
+        let start = Instant::now();
+        for (cords, grid) in grids.iter() {
+            let (mesh, _) = mesh_chunk_with(meshing, *cords, grid, &breg);
+            vertices += mesh.count_vertices();
+            triangles += mesh.indices().map_or(0, |indices| indices.len() / 3);
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{:<10}{:>12}{:>12}{:>12.1}{:>16.1}",
+            format!("{:?}", meshing).to_lowercase(),
+            vertices,
+            triangles,
+            elapsed.as_secs_f64() * 1000.0,
+            elapsed.as_secs_f64() * 1_000_000.0 / grids.len() as f64
+        );
+    }
+}
@@ -0,0 +1,62 @@
+// Generates the chunks in the render distance around the origin, and prints how much memory their
+// blocks take as plain arrays and compressed, and how long reading all of the blocks takes. Run it
+// with `cargo bench --bench storage`.
+use bevy::utils::Instant;
+use minecraft_bevy::*;
+use std::mem::size_of;
+
+fn main() {
+    let world_settings = WorldSettings::default();
+    let generator = TerrainGenerator::new(world_settings.seed, world_settings.terrain());
+    let render_distance = world_settings.render_distance;
+    let mut chunks = vec![];
+    for x in -render_distance..=render_distance {
+        for z in -render_distance..=render_distance {
+            for y in 0..WORLD_HEIGHT_CHUNKS {
+                chunks.push(generate_chunk([x, y, z], &generator));
+            }
+        }
+    }
+    let grids: Vec<[Block; CHUNK_LEN]> = chunks.iter().map(|chunk| chunk.to_grid()).collect();
+    let uniform = chunks.iter().filter(|c| c.as_uniform().is_some()).count();
+    let largest_palette = chunks.iter().map(|c| c.palette().len()).max().unwrap_or(0);
+    println!(
+        "Storing {} chunks (seed {}, render distance {}), {} of a single block, at most {} blocks in a palette",
+        chunks.len(),
+        world_settings.seed,
+        render_distance,
+        uniform,
+        largest_palette
+    );
+    println!(
+        "{:<10}{:>14}{:>16}{:>18}",
+        "storage", "memory (KiB)", "per chunk (B)", "read (ns/block)"
+    );
+    let array_bytes = grids.len() * size_of::<[Block; CHUNK_LEN]>();
+    let start = Instant::now();
+    let mut sum = 0u64;
+    for grid in grids.iter() {
+        sum += grid.iter().map(|b| *b as u64).sum::<u64>();
+    }
+    let array_time = start.elapsed();
+    let palette_bytes: usize = chunks.iter().map(|c| c.memory_usage()).sum();
+    let start = Instant::now();
+    for chunk in chunks.iter() {
+        sum -= chunk.iter().map(|b| b as u64).sum::<u64>();
+    }
+    let palette_time = start.elapsed();
+    assert_eq!(sum, 0, "Both storages hold the same blocks");
+    let blocks = (chunks.len() * CHUNK_LEN) as f64;
+    for (name, bytes, time) in [
+        ("array", array_bytes, array_time),
+        ("palette", palette_bytes, palette_time),
+    ] {
+        println!(
+            "{:<10}{:>14.1}{:>16}{:>18.2}",
+            name,
+            bytes as f64 / 1024.0,
+            bytes / chunks.len(),
+            time.as_secs_f64() * 1e9 / blocks
+        );
+    }
+}
@@ -1,162 +1,174 @@
 use crate::*;
 use bevy::prelude::*;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
 use bevy_meshem::prelude::*;
 
-const RAY_FORWARD_STEP: f32 = 0.01;
-const NANO_STEP_FACTOR: f32 = 15.0;
 pub const REACH_DISTANCE: u8 = 5;
 
 #[derive(Event)]
 pub struct BlockChange {
     // Only if we are placing a block, we need to know what block is against the block we are
     // placing, because we can't place blocks in the air. If change is `Broken` then this is None.
-    pub blocks: Vec<([i32; 2], usize, Option<([i32; 2], usize)>)>,
+    #[allow(clippy::type_complexity)]
+    pub blocks: Vec<([i32; 3], usize, Option<([i32; 3], usize)>)>,
     pub change: VoxelChange,
+    // The block that is placed, AIR when breaking.
+    pub block: Block,
 }
 
+// A block in the world that was changed (in world coordinates), sent after the change was made.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BlockEdited {
+    pub pos: [i32; 3],
+    pub old: Block,
+    pub new: Block,
+    // Whether a player made the change, or it happened on its own (like flowing water).
+    pub by_player: bool,
+}
+
+// The block the player is looking at, if there is one in reach.
+#[derive(Resource, Default)]
+pub struct TargetedBlock(pub Option<RaycastHit>);
+
+// The block the player is breaking, and for how long it has been breaking it.
+#[derive(Resource, Default)]
+pub struct BreakProgress {
+    pub block: Option<[i32; 3]>,
+    pub elapsed: f32,
+    pub hardness: f32,
+}
+
+impl BreakProgress {
+    // How much of the block has been broken, from 0.0 to 1.0.
+    pub fn fraction(&self) -> f32 {
+        match self.block {
+            None => 0.0,
+            Some(_) if self.hardness <= 0.0 => 1.0,
+            Some(_) => (self.elapsed / self.hardness).min(1.0),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.block = None;
+        self.elapsed = 0.0;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn add_break_detector(
     mut block_change_event_writer: EventWriter<BlockChange>,
     player_query: Query<(&CurrentChunk, &Transform), With<FlyCam>>,
     buttons: Res<Input<MouseButton>>,
+    world: VoxelWorld,
+    breg: Res<BlockRegistry>,
+    time: Res<Time>,
+    mut target: ResMut<TargetedBlock>,
+    mut progress: ResMut<BreakProgress>,
+    inv: Res<Inventory>,
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    game_mode: Res<State<GameMode>>,
 ) {
+    // The mouse is used for the menus while the cursor isn't grabbed.
+    if !game_mode.get().can_interact()
+        || primary_window
+            .get_single()
+            .is_ok_and(|window| window.cursor.grab_mode == CursorGrabMode::None)
+    {
+        target.0 = None;
+        progress.reset();
+        return;
+    }
     if let Ok((_, tran)) = player_query.get_single() {
-        let forward = tran.forward();
-        let pos = tran.translation;
+        // Blocks above the top or below the bottom of the world can't be interacted with.
+        target.0 = raycast_voxels(
+            tran.translation,
+            tran.forward(),
+            REACH_DISTANCE as f32,
+            // The ray goes through water, so the blocks under it can be reached.
+            |pos| {
+                world
+                    .get_block(pos.into())
+                    .is_some_and(|block| block != AIR && water_level(block).is_none())
+            },
+        )
+        .filter(|hit| is_chunk_in_world(block_to_chunk_position(hit.block).0));
+        let Some(hit) = target.0 else {
+            progress.reset();
+            return;
+        };
+        let (hit_chunk, hit_block) = block_to_chunk_position(hit.block);
+        let hit_block = one_d_cords(hit_block, CHUNK_DIMS);
 
-        if buttons.just_pressed(MouseButton::Left) {
-            block_change_event_writer.send(BlockChange {
-                blocks: blocks_in_the_way(pos, forward, REACH_DISTANCE)
-                    .iter()
-                    .filter_map(|(x, y, _z)| {
-                        if let Some(tmp) = one_d_cords_safe(*y, CHUNK_DIMS) {
-                            Some((*x, tmp, None))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect(),
-                change: VoxelChange::Broken,
-            });
+        // Blocks break after the button is held on them for as long as their hardness, looking
+        // away or letting go of the button starts over. In creative, and blocks without hardness,
+        // break with every click, so holding the button doesn't clear a whole field of them.
+        let hardness = world
+            .get_block(hit.block.into())
+            .map_or(0.0, |block| breg.hardness(block));
+        if game_mode.get().breaks_instantly() || hardness <= 0.0 {
+            progress.reset();
+            if buttons.just_pressed(MouseButton::Left) {
+                block_change_event_writer.send(BlockChange {
+                    blocks: vec![(hit_chunk, hit_block, None)],
+                    change: VoxelChange::Broken,
+                    block: AIR,
+                });
+            }
+        } else if buttons.pressed(MouseButton::Left) {
+            if progress.block != Some(hit.block) {
+                progress.block = Some(hit.block);
+                progress.elapsed = 0.0;
+                progress.hardness = hardness;
+            }
+            progress.elapsed += time.delta_seconds();
+            if progress.fraction() >= 1.0 {
+                block_change_event_writer.send(BlockChange {
+                    blocks: vec![(hit_chunk, hit_block, None)],
+                    change: VoxelChange::Broken,
+                    block: AIR,
+                });
+                progress.reset();
+            }
+        } else {
+            progress.reset();
         }
 
-        if buttons.just_pressed(MouseButton::Right) {
+        if let (true, Some(selected)) = (
+            buttons.just_pressed(MouseButton::Right),
+            inv.selected_block(),
+        ) {
+            // The block is placed against the face of the block that was hit, which might be in a
+            // different chunk.
+            let (chunk, block) = block_to_chunk_position(adjacent_cords(hit.block, hit.face));
+            if !is_chunk_in_world(chunk) {
+                match hit.face {
+                    Top => {
+                        warn!("\nIn-Game Error: \nMaximum build limit has been reached")
+                    }
+                    _ => {
+                        warn!("\nIn-Game Error: \nMinimum build limit has been reached")
+                    }
+                }
+                return;
+            }
+            // Don't place blocks where the player is standing, it would get stuck inside of them.
+            let player = Hitbox::player(tran.translation);
+            let placed = chunk_position_to_block(chunk, block);
+            if (0..3).all(|axis| {
+                placed[axis] as f32 - 0.5 < player.max[axis]
+                    && placed[axis] as f32 + 0.5 > player.min[axis]
+            }) {
+                return;
+            }
             block_change_event_writer.send(BlockChange {
                 change: VoxelChange::Added,
-                blocks: blocks_in_the_way(pos, forward, REACH_DISTANCE)
-                    .iter()
-                    .skip(1)
-                    .filter_map(|&(x, y, z)| {
-                        let tmp = one_d_cords_safe(y, CHUNK_DIMS)?;
-                        if let Some(block) = get_neighbor(tmp, z, CHUNK_DIMS) {
-                            Some((x, block, Some((x, tmp))))
-                        } else {
-                            match z {
-                                Top => {
-                                    warn!(
-                                        "\nIn-Game Error: \nMaximum build limit has been reached"
-                                    );
-                                    None
-                                }
-                                Bottom => {
-                                    warn!(
-                                        "\nIn-Game Error: \nMinimum build limit has been reached"
-                                    );
-                                    None
-                                }
-                                Right => Some(([x[0] + 1, x[1]], tmp - WIDTH + 1, Some((x, tmp)))),
-                                Left => Some(([x[0] - 1, x[1]], tmp + WIDTH - 1, Some((x, tmp)))),
-                                Back => Some((
-                                    [x[0], x[1] + 1],
-                                    tmp - WIDTH * (LENGTH - 1),
-                                    Some((x, tmp)),
-                                )),
-                                Forward => Some((
-                                    [x[0], x[1] - 1],
-                                    tmp + WIDTH * (LENGTH - 1),
-                                    Some((x, tmp)),
-                                )),
-                            }
-                            // (
-                            //     x,
-                            //     one_d_cords(y, CHUNK_DIMS),
-                            //     Some(one_d_cords(y, CHUNK_DIMS)),
-                            // )
-                        }
-                    })
-                    .collect(),
+                block: selected,
+                blocks: vec![(
+                    chunk,
+                    one_d_cords(block, CHUNK_DIMS),
+                    Some((hit_chunk, hit_block)),
+                )],
             });
         }
     }
 }
-
-fn blocks_in_the_way(pos: Vec3, forward: Vec3, distance: u8) -> Vec<([i32; 2], [usize; 3], Face)> {
-    let step = forward * RAY_FORWARD_STEP;
-    let possible_faces: [Face; 3] = [
-        if forward.x > 0.0 { Left } else { Right },
-        if forward.y > 0.0 { Bottom } else { Top },
-        if forward.z > 0.0 { Forward } else { Back },
-    ];
-    // let mut point = pos + Vec3::new(0.5, 0.5, 0.5);
-    let mut point = pos;
-    let mut current_block = [point.x.round(), point.y.round(), point.z.round()];
-    let mut to_return: Vec<([i32; 2], [usize; 3], Face)> = vec![];
-
-    while point.distance(pos) < distance as f32 {
-        point += step;
-        let tmp = [point.x.round(), point.y.round(), point.z.round()];
-        if tmp != current_block {
-            current_block = tmp;
-            let face = {
-                let r: Face;
-                let mut p = point - step;
-                let nano_step = step / NANO_STEP_FACTOR;
-                loop {
-                    p += nano_step;
-                    let tmp = [p.x.round(), p.y.round(), p.z.round()];
-                    if tmp == current_block {
-                        r = closest_face(p, possible_faces);
-                        break;
-                    }
-                }
-                r
-            };
-            let block_pos = position_to_chunk_position(point, CHUNK_DIMS);
-            to_return.push((block_pos.0, block_pos.1, face));
-        }
-    }
-    to_return
-}
-
-#[allow(non_snake_case)]
-fn closest_face(p: Vec3, possible_faces: [Face; 3]) -> Face {
-    let mut min = f32::MAX;
-    let mut face = Bottom;
-
-    for f in possible_faces {
-        let d = distance_from_face(p, f);
-        if d < min {
-            face = f;
-            min = d;
-        }
-    }
-
-    return face;
-}
-
-#[allow(non_snake_case)]
-fn distance_from_face(p: Vec3, face: Face) -> f32 {
-    let x = p.x;
-    let z = p.z;
-    let y = p.y;
-    let X = p.x.round();
-    let Z = p.z.round();
-    let Y = p.y.round();
-    match face {
-        Top => (Y + 0.5 - y).abs(),
-        Bottom => (Y - 0.5 - y).abs(),
-        Right => (X + 0.5 - x).abs(),
-        Left => (X - 0.5 - x).abs(),
-        Back => (Z + 0.5 - z).abs(),
-        Forward => (Z - 0.5 - z).abs(),
-    }
-}
@@ -1,6 +1,11 @@
+use bevy::asset::io::file::FileAssetReader;
 use bevy::prelude::*;
 use bevy::render::mesh::MeshVertexAttribute;
 use bevy_meshem::prelude::*;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 pub type Block = u16;
 
@@ -15,195 +20,349 @@ pub const LEAVES: Block = 7;
 pub const GLASS: Block = 8;
 pub const GLOWSTONE: Block = 9;
 pub const WATER: Block = 10;
+pub const SAND: Block = 11;
+pub const SNOW: Block = 12;
+// Water that flowed out of a water source, there is a block for each level from 1 to 7, with ids
+// FLOWING_WATER + level - 1.
+pub const FLOWING_WATER: Block = 13;
+// The blocks the code uses by their id, the terrain, the water and the inventory need all of them.
+pub const BUILTIN_BLOCKS: std::ops::RangeInclusive<Block> = DIRT..=FLOWING_WATER + 6;
 
 pub const VOXEL_DIMS: [f32; 3] = [1.0, 1.0, 1.0];
 pub const VOXEL_CENTER: [f32; 3] = [0.0, 0.0, 0.0];
 
-const ATLAS_CORDS: [u32; 2] = [24, 24];
-const PADDING: f32 = 0.0625;
+// How much of each side of an atlas tile is left out, so the tiles around it don't bleed in.
+pub const PADDING: f32 = 0.0625;
+pub const BLOCK_DEFINITIONS_FILE: &str = "blocks.ron";
 
-#[derive(Resource, Clone)]
-pub struct BlockRegistry {
-    grass_block: Mesh,
-    dirt_block: Mesh,
-    stone_block: Mesh,
-    bricks_block: Mesh,
-    log_block: Mesh,
-    wood_block: Mesh,
-    leaves_block: Mesh,
-    glass_block: Mesh,
-    glowstone_block: Mesh,
-    water_block: Mesh,
+// The layout of the block definitions file.
+#[derive(Deserialize, Clone, Debug)]
+pub struct BlockDefinitions {
+    pub atlas_size: [u32; 2],
+    pub blocks: Vec<BlockDefinition>,
 }
 
-impl Default for BlockRegistry {
-    fn default() -> Self {
-        BlockRegistry {
-            water_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [11, 0]),
-                    (Bottom, [11, 0]),
-                    (Right, [11, 0]),
-                    (Left, [11, 0]),
-                    (Forward, [11, 0]),
-                    (Back, [11, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.75),
-                0.8,
-            ),
+#[derive(Deserialize, Clone, Debug)]
+pub struct BlockDefinition {
+    pub id: Block,
+    pub name: String,
+    pub textures: BlockTextures,
+    #[serde(default = "default_ambient_occlusion")]
+    pub ambient_occlusion: f32,
+    #[serde(default = "default_alpha")]
+    pub alpha: f32,
+    // Transparent blocks don't cover the faces of the blocks next to them.
+    #[serde(default)]
+    pub transparent: bool,
+    #[serde(default)]
+    pub light_emission: u8,
+    // Blocks lower than a full block (like flowing water) sit on the bottom of their space.
+    #[serde(default = "default_height")]
+    pub height: f32,
+    // How many seconds it takes to break the block, blocks with a hardness of 0 break instantly.
+    #[serde(default = "default_hardness")]
+    pub hardness: f32,
+}
 
-            grass_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [0, 0]),
-                    (Bottom, [2, 0]),
-                    (Right, [1, 0]),
-                    (Left, [1, 0]),
-                    (Forward, [1, 0]),
-                    (Back, [1, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.75),
-                1.0,
-            ),
-            dirt_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [2, 0]),
-                    (Bottom, [2, 0]),
-                    (Right, [2, 0]),
-                    (Left, [2, 0]),
-                    (Forward, [2, 0]),
-                    (Back, [2, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.75),
-                1.0,
-            ),
-            stone_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [3, 0]),
-                    (Bottom, [3, 0]),
-                    (Right, [3, 0]),
-                    (Left, [3, 0]),
-                    (Forward, [3, 0]),
-                    (Back, [3, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.75),
-                1.0,
-            ),
-            bricks_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [4, 0]),
-                    (Bottom, [4, 0]),
-                    (Right, [4, 0]),
-                    (Left, [4, 0]),
-                    (Forward, [4, 0]),
-                    (Back, [4, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.75),
-                1.0,
-            ),
-            log_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [5, 0]),
-                    (Bottom, [5, 0]),
-                    (Right, [6, 0]),
-                    (Left, [6, 0]),
-                    (Forward, [6, 0]),
-                    (Back, [6, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.90),
-                1.0,
-            ),
-            wood_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [7, 0]),
-                    (Bottom, [7, 0]),
-                    (Right, [7, 0]),
-                    (Left, [7, 0]),
-                    (Forward, [7, 0]),
-                    (Back, [7, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.75),
-                1.0,
+// The atlas tile of each face. A face takes its own tile if it is set, otherwise `sides` (for the
+// horizontal faces) and otherwise `all`.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct BlockTextures {
+    pub all: Option<[u32; 2]>,
+    pub sides: Option<[u32; 2]>,
+    pub top: Option<[u32; 2]>,
+    pub bottom: Option<[u32; 2]>,
+    pub right: Option<[u32; 2]>,
+    pub left: Option<[u32; 2]>,
+    pub forward: Option<[u32; 2]>,
+    pub back: Option<[u32; 2]>,
+}
+
+fn default_ambient_occlusion() -> f32 {
+    0.75
+}
+
+fn default_alpha() -> f32 {
+    1.0
+}
+
+fn default_height() -> f32 {
+    1.0
+}
+
+fn default_hardness() -> f32 {
+    1.0
+}
+
+impl BlockTextures {
+    fn resolve(&self) -> [(Face, &'static str, Option<[u32; 2]>); 6] {
+        let side = |face: Option<[u32; 2]>| face.or(self.sides).or(self.all);
+        [
+            (Top, "top", self.top.or(self.all)),
+            (Bottom, "bottom", self.bottom.or(self.all)),
+            (Right, "right", side(self.right)),
+            (Left, "left", side(self.left)),
+            (Back, "back", side(self.back)),
+            (Forward, "forward", side(self.forward)),
+        ]
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockRegistryError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    ReservedId {
+        name: String,
+    },
+    DuplicateId {
+        id: Block,
+        first: String,
+        second: String,
+    },
+    MissingTexture {
+        name: String,
+        face: &'static str,
+    },
+    TileOutOfAtlas {
+        name: String,
+        face: &'static str,
+        tile: [u32; 2],
+    },
+    InvalidHeight {
+        name: String,
+        height: f32,
+    },
+    MissingBuiltin(Block),
+}
+
+impl std::fmt::Display for BlockRegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Couldn't read the block definitions: {}", e),
+            Self::Parse(e) => write!(f, "Couldn't parse the block definitions: {}", e),
+            Self::ReservedId { name } => {
+                write!(
+                    f,
+                    "Block \"{}\" uses id {}, which is reserved for air",
+                    name, AIR
+                )
+            }
+            Self::DuplicateId { id, first, second } => write!(
+                f,
+                "Blocks \"{}\" and \"{}\" both use the id {}",
+                first, second, id
             ),
-            leaves_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [8, 0]),
-                    (Bottom, [8, 0]),
-                    (Right, [8, 0]),
-                    (Left, [8, 0]),
-                    (Forward, [8, 0]),
-                    (Back, [8, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.75),
-                1.0,
+            Self::MissingTexture { name, face } => {
+                write!(f, "Block \"{}\" has no texture for its {} face", name, face)
+            }
+            Self::TileOutOfAtlas { name, face, tile } => write!(
+                f,
+                "The {} face of block \"{}\" uses the tile {:?}, which is outside the atlas",
+                face, name, tile
             ),
-            glass_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [9, 0]),
-                    (Bottom, [9, 0]),
-                    (Right, [9, 0]),
-                    (Left, [9, 0]),
-                    (Forward, [9, 0]),
-                    (Back, [9, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.75),
-                1.0,
+            Self::InvalidHeight { name, height } => write!(
+                f,
+                "Block \"{}\" has the height {}, it should be above 0 and at most 1",
+                name, height
             ),
-            glowstone_block: generate_voxel_mesh(
-                VOXEL_DIMS,
-                ATLAS_CORDS,
-                [
-                    (Top, [10, 0]),
-                    (Bottom, [10, 0]),
-                    (Right, [10, 0]),
-                    (Left, [10, 0]),
-                    (Forward, [10, 0]),
-                    (Back, [10, 0]),
-                ],
-                VOXEL_CENTER,
-                PADDING,
-                Some(0.75),
-                1.0,
+            Self::MissingBuiltin(id) => write!(
+                f,
+                "There is no block with the id {}, which the game needs to have",
+                id
             ),
         }
     }
 }
 
+impl std::error::Error for BlockRegistryError {}
+
+#[derive(Clone)]
+pub struct RegisteredBlock {
+    pub name: String,
+    pub mesh: Mesh,
+    // The atlas tile of each face, indexed by the face.
+    pub tiles: [[u32; 2]; 6],
+    pub alpha: f32,
+    pub height: f32,
+    pub transparent: bool,
+    pub light_emission: u8,
+    pub hardness: f32,
+    // The atlas tile the block is shown with in the inventory.
+    pub icon: [u32; 2],
+}
+
+#[derive(Resource, Clone)]
+pub struct BlockRegistry {
+    // Indexed by the block's id, ids that weren't defined (and air) are None.
+    blocks: Vec<Option<RegisteredBlock>>,
+    pub atlas_size: [u32; 2],
+}
+
+impl BlockRegistry {
+    pub fn from_definitions(defs: &BlockDefinitions) -> Result<Self, BlockRegistryError> {
+        let mut blocks: Vec<Option<RegisteredBlock>> = vec![];
+        for def in defs.blocks.iter() {
+            if def.id == AIR {
+                return Err(BlockRegistryError::ReservedId {
+                    name: def.name.clone(),
+                });
+            }
+            let id = def.id as usize;
+            if blocks.len() <= id {
+                blocks.resize(id + 1, None);
+            }
+            if let Some(first) = &blocks[id] {
+                return Err(BlockRegistryError::DuplicateId {
+                    id: def.id,
+                    first: first.name.clone(),
+                    second: def.name.clone(),
+                });
+            }
+            if def.height <= 0.0 || def.height > 1.0 {
+                return Err(BlockRegistryError::InvalidHeight {
+                    name: def.name.clone(),
+                    height: def.height,
+                });
+            }
+            let mut textures = [(Top, [0, 0]); 6];
+            for (i, (face, face_name, tile)) in def.textures.resolve().into_iter().enumerate() {
+                let Some(tile) = tile else {
+                    return Err(BlockRegistryError::MissingTexture {
+                        name: def.name.clone(),
+                        face: face_name,
+                    });
+                };
+                if tile[0] >= defs.atlas_size[0] || tile[1] >= defs.atlas_size[1] {
+                    return Err(BlockRegistryError::TileOutOfAtlas {
+                        name: def.name.clone(),
+                        face: face_name,
+                        tile,
+                    });
+                }
+                textures[i] = (face, tile);
+            }
+            let icon = textures
+                .iter()
+                .find(|(face, _)| *face == Forward)
+                .map_or([0, 0], |(_, tile)| *tile);
+            blocks[id] = Some(RegisteredBlock {
+                name: def.name.clone(),
+                mesh: generate_voxel_mesh(
+                    [VOXEL_DIMS[0], VOXEL_DIMS[1] * def.height, VOXEL_DIMS[2]],
+                    defs.atlas_size,
+                    textures,
+                    [
+                        VOXEL_CENTER[0],
+                        VOXEL_CENTER[1] - VOXEL_DIMS[1] * (1.0 - def.height) / 2.0,
+                        VOXEL_CENTER[2],
+                    ],
+                    PADDING,
+                    Some(def.ambient_occlusion),
+                    def.alpha,
+                ),
+                tiles: textures.map(|(_, tile)| tile),
+                alpha: def.alpha,
+                height: def.height,
+                transparent: def.transparent,
+                light_emission: def.light_emission,
+                hardness: def.hardness,
+                icon,
+            });
+        }
+        if let Some(id) = BUILTIN_BLOCKS
+            .clone()
+            .find(|id| !matches!(blocks.get(*id as usize), Some(Some(_))))
+        {
+            return Err(BlockRegistryError::MissingBuiltin(id));
+        }
+        Ok(BlockRegistry {
+            blocks,
+            atlas_size: defs.atlas_size,
+        })
+    }
+
+    pub fn from_ron(ron: &str) -> Result<Self, BlockRegistryError> {
+        let defs: BlockDefinitions = ron::from_str(ron).map_err(BlockRegistryError::Parse)?;
+        Self::from_definitions(&defs)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, BlockRegistryError> {
+        Self::from_ron(&fs::read_to_string(path).map_err(BlockRegistryError::Io)?)
+    }
+
+    pub fn get(&self, block: Block) -> Option<&RegisteredBlock> {
+        self.blocks.get(block as usize)?.as_ref()
+    }
+
+    pub fn block_by_name(&self, name: &str) -> Option<Block> {
+        self.iter()
+            .find(|(_, registered)| registered.name == name)
+            .map(|(block, _)| block)
+    }
+
+    pub fn light_emission(&self, block: Block) -> u8 {
+        self.get(block).map_or(0, |b| b.light_emission)
+    }
+
+    pub fn hardness(&self, block: Block) -> f32 {
+        self.get(block).map_or(0.0, |b| b.hardness)
+    }
+
+    // Opaque blocks hide the faces behind them and stop light, air and transparent blocks don't.
+    pub fn is_opaque(&self, block: Block) -> bool {
+        self.get(block).is_some_and(|b| !b.transparent)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Block, &RegisteredBlock)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(id, b)| Some((id as Block, b.as_ref()?)))
+    }
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        let path = BlockRegistrySource::default().path;
+        BlockRegistry::load(&path)
+            .unwrap_or_else(|e| panic!("Couldn't load the block registry from {:?}: {}", path, e))
+    }
+}
+
+// The file the block registry was loaded from, it is checked for changes so blocks can be edited
+// while the game is running.
+#[derive(Resource)]
+pub struct BlockRegistrySource {
+    pub path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl Default for BlockRegistrySource {
+    fn default() -> Self {
+        BlockRegistrySource {
+            path: FileAssetReader::get_base_path()
+                .join("assets")
+                .join(BLOCK_DEFINITIONS_FILE),
+            last_modified: None,
+        }
+    }
+}
+
+impl BlockRegistrySource {
+    // Returns the new registry if the file changed since the last time this was called. The first
+    // call only records the modification time of the file.
+    pub fn reload_if_modified(&mut self) -> Option<Result<BlockRegistry, BlockRegistryError>> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        let last_modified = self.last_modified.replace(modified);
+        if last_modified? == modified {
+            return None;
+        }
+        Some(BlockRegistry::load(&self.path))
+    }
+}
+
 impl VoxelRegistry for BlockRegistry {
     type Voxel = Block;
 
@@ -225,23 +384,92 @@ impl VoxelRegistry for BlockRegistry {
     }
 
     fn is_covering(&self, voxel: &Self::Voxel, _side: prelude::Face) -> bool {
-        *voxel != AIR && *voxel != LEAVES && *voxel != GLASS && *voxel != WATER
+        self.is_opaque(*voxel)
     }
 
     fn get_mesh(&self, voxel: &Self::Voxel) -> VoxelMesh<&Mesh> {
-        match *voxel {
-            AIR => VoxelMesh::Null,
-            DIRT => VoxelMesh::NormalCube(&self.dirt_block),
-            GRASS => VoxelMesh::NormalCube(&self.grass_block),
-            STONE => VoxelMesh::NormalCube(&self.stone_block),
-            BRICKS => VoxelMesh::NormalCube(&self.bricks_block),
-            LOG => VoxelMesh::NormalCube(&self.log_block),
-            WOOD => VoxelMesh::NormalCube(&self.wood_block),
-            LEAVES => VoxelMesh::NormalCube(&self.leaves_block),
-            GLASS => VoxelMesh::NormalCube(&self.glass_block),
-            GLOWSTONE => VoxelMesh::NormalCube(&self.glowstone_block),
-            WATER => VoxelMesh::NormalCube(&self.water_block),
-            _ => VoxelMesh::Null,
+        match self.get(*voxel) {
+            Some(b) => VoxelMesh::NormalCube(&b.mesh),
+            None => VoxelMesh::Null,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Load definitions with the given blocks, in an atlas of 4 by 4 tiles, that have to be rejected.
+    fn error_of(blocks: &str) -> BlockRegistryError {
+        let ron = format!("(atlas_size: (4, 4), blocks: [{}])", blocks);
+        BlockRegistry::from_ron(&ron)
+            .err()
+            .expect("The definitions should be rejected")
+    }
+
+    #[test]
+    fn air_is_reserved() {
+        let e = error_of(r#"(id: 0, name: "void", textures: (all: Some((0, 0))))"#);
+        assert!(matches!(e, BlockRegistryError::ReservedId { name } if name == "void"));
+    }
+
+    #[test]
+    fn ids_are_unique() {
+        let e = error_of(
+            r#"(id: 1, name: "dirt", textures: (all: Some((0, 0)))),
+               (id: 1, name: "mud", textures: (all: Some((1, 0))))"#,
+        );
+        assert!(matches!(
+            e,
+            BlockRegistryError::DuplicateId { id: 1, first, second } if first == "dirt" && second == "mud"
+        ));
+    }
+
+    #[test]
+    fn every_face_has_a_texture() {
+        // The sides don't cover the top and the bottom.
+        let e = error_of(r#"(id: 1, name: "dirt", textures: (sides: Some((0, 0))))"#);
+        assert!(matches!(
+            e,
+            BlockRegistryError::MissingTexture { face: "top", .. }
+        ));
+    }
+
+    #[test]
+    fn tiles_are_in_the_atlas() {
+        let e = error_of(
+            r#"(id: 1, name: "dirt", textures: (all: Some((0, 0)), bottom: Some((1, 4))))"#,
+        );
+        assert!(matches!(
+            e,
+            BlockRegistryError::TileOutOfAtlas {
+                face: "bottom",
+                tile: [1, 4],
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn heights_are_at_most_a_block() {
+        for height in ["0.0", "1.5"] {
+            let e = error_of(&format!(
+                r#"(id: 1, name: "dirt", textures: (all: Some((0, 0))), height: {})"#,
+                height
+            ));
+            assert!(matches!(e, BlockRegistryError::InvalidHeight { .. }));
         }
     }
+
+    #[test]
+    fn the_builtin_blocks_have_to_be_defined() {
+        let ron = fs::read_to_string(BlockRegistrySource::default().path).unwrap();
+        let mut defs: BlockDefinitions = ron::from_str(&ron).unwrap();
+        assert!(BlockRegistry::from_definitions(&defs).is_ok());
+        defs.blocks.retain(|def| def.id != FLOWING_WATER + 6);
+        assert!(matches!(
+            BlockRegistry::from_definitions(&defs).err(),
+            Some(BlockRegistryError::MissingBuiltin(id)) if id == FLOWING_WATER + 6
+        ));
+    }
 }
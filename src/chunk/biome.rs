@@ -0,0 +1,96 @@
+use crate::block_reg::*;
+
+// Blocks below this height that would be air are filled with water.
+pub const SEA_LEVEL: i32 = 40;
+// Controls how wide the borders between biomes are, the bigger it is, the smoother the blending.
+const BIOME_BLEND: f64 = 0.04;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Biome {
+    Ocean,
+    Plains,
+    Desert,
+    Snowy,
+    Mountains,
+}
+
+pub const BIOMES: [Biome; 5] = [
+    Biome::Ocean,
+    Biome::Plains,
+    Biome::Desert,
+    Biome::Snowy,
+    Biome::Mountains,
+];
+
+// The climate of a column in the world, every value is roughly in the range -1.0..1.0
+#[derive(Clone, Copy, Debug)]
+pub struct Climate {
+    pub temperature: f64,
+    pub humidity: f64,
+    pub continentalness: f64,
+}
+
+pub struct BiomeParams {
+    pub surface: Block,
+    pub subsurface: Block,
+    // The height of the terrain is `base_height + amplitude * noise`, in world coordinates.
+    pub base_height: f64,
+    pub amplitude: f64,
+    // The climate at which this biome is the most dominant.
+    pub climate: Climate,
+}
+
+impl Biome {
+    pub fn params(self) -> BiomeParams {
+        let (surface, subsurface, base_height, amplitude, [t, h, c]) = match self {
+            Biome::Ocean => (SAND, SAND, 26.0, 6.0, [0.0, 0.0, -0.7]),
+            Biome::Plains => (GRASS, DIRT, 46.0, 5.0, [0.0, 0.2, 0.1]),
+            Biome::Desert => (SAND, SAND, 45.0, 4.0, [0.7, -0.6, 0.1]),
+            Biome::Snowy => (SNOW, DIRT, 50.0, 9.0, [-0.7, 0.2, 0.2]),
+            Biome::Mountains => (STONE, STONE, 72.0, 36.0, [0.0, 0.0, 0.75]),
+        };
+        BiomeParams {
+            surface,
+            subsurface,
+            base_height,
+            amplitude,
+            climate: Climate {
+                temperature: t,
+                humidity: h,
+                continentalness: c,
+            },
+        }
+    }
+
+    // How much each biome contributes to a column with the given climate. The weights are
+    // normalized (they add up to 1.0) and change smoothly with the climate, so blending the
+    // heights of the biomes with them leaves no cliffs at the borders between biomes.
+    pub fn weights(climate: Climate) -> [f64; BIOMES.len()] {
+        let mut weights = [0.0; BIOMES.len()];
+        for (i, biome) in BIOMES.iter().enumerate() {
+            let center = biome.params().climate;
+            let distance_sq = (climate.temperature - center.temperature).powi(2)
+                + (climate.humidity - center.humidity).powi(2)
+                + (climate.continentalness - center.continentalness).powi(2);
+            weights[i] = (-distance_sq / BIOME_BLEND).exp();
+        }
+        let sum: f64 = weights.iter().sum();
+        if sum > 0.0 {
+            weights.iter_mut().for_each(|w| *w /= sum);
+        } else {
+            weights[Biome::Plains as usize] = 1.0;
+        }
+        weights
+    }
+
+    // The biome with the biggest weight.
+    pub fn dominant(weights: &[f64; BIOMES.len()]) -> Biome {
+        let mut dominant = 0;
+        for i in 1..BIOMES.len() {
+            if weights[i] > weights[dominant] {
+                dominant = i;
+            }
+        }
+        BIOMES[dominant]
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{chunk::*, Block, BlockRegistry, Perlin, GEN_SEED};
+use crate::{
+    chunk::*, light_new_chunk, Block, BlockRegistry, LightGrid, MeshingMode, WorldSettings,
+};
 use bevy::utils::hashbrown::HashMap;
 use bevy::{
     prelude::*,
@@ -8,145 +10,529 @@ use bevy_meshem::prelude::*;
 use std::sync::Arc;
 
 #[derive(Component)]
-pub struct ComputeChunk(pub Task<Option<((Mesh, MeshMD<Block>), [Block; CHUNK_LEN], [i32; 2])>>);
+#[allow(clippy::type_complexity)]
+pub struct ComputeChunk(
+    pub  Task<(
+        Option<(Mesh, Option<MeshMD<Block>>)>,
+        ChunkStorage,
+        LightGrid,
+        [i32; 3],
+    )>,
+);
+
+// The blocks of a chunk that are being loaded or generated.
+#[derive(Component)]
+pub struct GenerateChunk {
+    pub cords: [i32; 3],
+    pub task: Task<ChunkStorage>,
+}
+
+// Start meshing and lighting a chunk whose blocks are known. Without a `meshing` mode the chunk is
+// only lit, for when nothing is rendered.
+pub fn mesh_task(
+    cords: [i32; 3],
+    grid: ChunkStorage,
+    generator: TerrainGenerator,
+    breg: Arc<BlockRegistry>,
+    meshing: Option<MeshingMode>,
+    sky_from_above: Option<[bool; WIDTH * LENGTH]>,
+) -> ComputeChunk {
+    ComputeChunk(AsyncComputeTaskPool::get().spawn(async move {
+        let mut mesh =
+            meshing.map(|meshing| mesh_chunk_with(meshing, cords, &grid.to_grid(), &breg));
+        let light = light_new_chunk(
+            cords,
+            &grid,
+            &generator,
+            &breg,
+            mesh.as_mut().map(|(mesh, _)| mesh),
+            sky_from_above.as_ref(),
+        );
+        (mesh, grid, light, cords)
+    }))
+}
+
+// How much being in front of a viewer counts for loading a chunk sooner, in chunks of distance.
+const VIEW_DIRECTION_WEIGHT: f32 = 1.5;
 
 enum QdChunk {
     Spawn,
     Despawn,
+    // A chunk that was sent by the server, it's spawned with these blocks instead of loading or
+    // generating them.
+    Received(ChunkStorage),
 }
 
+// The chunks waiting to be spawned or despawned. A chunk is only in it once, with the last thing
+// that was asked of it.
 #[derive(Resource, Default)]
 pub struct ChunkQueue {
-    // true = spawn, false= despawn
-    queue: Vec<([i32; 2], QdChunk)>,
+    queue: HashMap<[i32; 3], QdChunk>,
     pub panic_when_cant_find_chunk: bool,
 }
 
+// How much of the chunk loading is done in a frame, so that a lot of chunks to load at once (like
+// on startup) don't flood the task pool, or make a frame stutter while they are spawned.
+#[derive(Resource)]
+pub struct ChunkLoadBudget {
+    // How many chunks are started being generated and meshed.
+    pub tasks_per_frame: usize,
+    // How many of the chunks that are done are spawned.
+    pub spawns_per_frame: usize,
+}
+
+impl Default for ChunkLoadBudget {
+    fn default() -> Self {
+        ChunkLoadBudget {
+            tasks_per_frame: 16,
+            spawns_per_frame: 16,
+        }
+    }
+}
+
+// Someone chunks are loaded around: the chunk it is in, and where it looks.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkViewer {
+    pub chunk: [i32; 3],
+    pub forward: Vec3,
+}
+
+// How soon the chunk at `cords` should be loaded, the lower the sooner. The closer a chunk is to a
+// viewer the sooner it's loaded, and at the same distance, the chunks in front of a viewer come
+// before the ones behind it.
+pub fn load_priority(cords: [i32; 3], viewers: &[ChunkViewer]) -> f32 {
+    viewers
+        .iter()
+        .map(|viewer| {
+            let offset = Vec3::new(
+                (cords[0] - viewer.chunk[0]) as f32,
+                (cords[1] - viewer.chunk[1]) as f32,
+                (cords[2] - viewer.chunk[2]) as f32,
+            );
+            offset.length() - offset.normalize_or_zero().dot(viewer.forward) * VIEW_DIRECTION_WEIGHT
+        })
+        .fold(f32::MAX, f32::min)
+}
+
+// Where a chunk is on its way from being asked for to being loaded. A chunk that isn't in the
+// `ChunkMap` isn't loaded, and nothing is being done for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkState {
+    // Waiting in the `ChunkQueue` for its turn.
+    Queued,
+    // Its blocks are being loaded from disk or generated.
+    Generating,
+    // Its blocks are known, its mesh is being made and lit.
+    Meshing,
+    // Spawned, but the faces on its borders still have to be culled against some of the chunks
+    // around it, which are still loading.
+    AwaitingNeighbors,
+    Ready,
+    // Going to be despawned, or its task cancelled, the next time the queue is dequeued.
+    Unloading,
+}
+
+impl ChunkState {
+    // Whether the chunk has an entity with its blocks.
+    pub fn is_spawned(self) -> bool {
+        matches!(self, ChunkState::AwaitingNeighbors | ChunkState::Ready)
+    }
+}
+
+struct ChunkEntry {
+    state: ChunkState,
+    // The entity of the task that computes the chunk, it becomes the chunk's entity once it's
+    // spawned. Despawning it cancels the task.
+    ent: Option<Entity>,
+    // A chunk that is unloading keeps the state it was in, in case it's wanted again before it's
+    // dequeued.
+    unloading: bool,
+}
+
 #[derive(Resource, Default)]
 pub struct ChunkMap {
-    pos_to_ent: HashMap<[i32; 2], Entity>,
+    chunks: HashMap<[i32; 3], ChunkEntry>,
 }
 
 impl ChunkMap {
-    pub fn get_ent(&self, cords: [i32; 2]) -> Option<Entity> {
-        Some(*self.pos_to_ent.get(&cords)?)
+    // The entity of the chunk, only if it's spawned.
+    pub fn get_ent(&self, cords: [i32; 3]) -> Option<Entity> {
+        let entry = self.chunks.get(&cords)?;
+        entry.ent.filter(|_| entry.state.is_spawned())
     }
 
-    pub fn insert_ent(&mut self, cords: [i32; 2], ent: Entity) {
-        self.pos_to_ent.insert(cords, ent);
+    pub fn state(&self, cords: [i32; 3]) -> Option<ChunkState> {
+        let entry = self.chunks.get(&cords)?;
+        Some(if entry.unloading {
+            ChunkState::Unloading
+        } else {
+            entry.state
+        })
     }
 
-    pub fn remove_ent(&mut self, cords: [i32; 2], ent: Entity) {
-        assert_eq!(
-            self.pos_to_ent.remove(&cords).unwrap(),
-            ent,
-            "Tried removing entity associated with chunk, but the entity given was wrong"
-        );
+    // Move a chunk to the next state, the chunks that aren't in the map are left out.
+    pub fn set_state(&mut self, cords: [i32; 3], state: ChunkState) {
+        if let Some(entry) = self.chunks.get_mut(&cords) {
+            entry.state = state;
+        }
+    }
+
+    // Whether `ent` is the task or the entity of the chunk, and the chunk is in `state`. A task
+    // whose chunk was unloaded, or loaded again, can tell it's stale with this.
+    pub fn is_current(&self, cords: [i32; 3], ent: Entity, state: ChunkState) -> bool {
+        self.chunks
+            .get(&cords)
+            .is_some_and(|entry| entry.ent == Some(ent) && entry.state == state)
+    }
+
+    pub fn exists(&self, cords: [i32; 3]) -> bool {
+        self.chunks.contains_key(&cords)
+    }
+
+    pub fn iter_keys(&self) -> impl Iterator<Item = &[i32; 3]> {
+        self.chunks.keys()
     }
 
-    pub fn exists(&self, cords: [i32; 2]) -> bool {
-        self.pos_to_ent.contains_key(&cords)
+    // The chunks that are spawned, with their entities.
+    pub fn iter(&self) -> impl Iterator<Item = ([i32; 3], Entity)> + '_ {
+        self.chunks
+            .keys()
+            .filter_map(|cords| Some((*cords, self.get_ent(*cords)?)))
     }
 
-    pub fn iter_keys(&self) -> bevy::utils::hashbrown::hash_map::Keys<'_, [i32; 2], Entity> {
-        self.pos_to_ent.keys()
+    pub fn states(&self) -> impl Iterator<Item = ([i32; 3], ChunkState)> + '_ {
+        self.chunks
+            .keys()
+            .filter_map(|cords| Some((*cords, self.state(*cords)?)))
     }
 
-    pub fn iter(&self) -> bevy::utils::hashbrown::hash_map::Iter<'_, [i32; 2], Entity> {
-        self.pos_to_ent.iter()
+    fn insert(&mut self, cords: [i32; 3], state: ChunkState, ent: Option<Entity>) {
+        self.chunks.insert(
+            cords,
+            ChunkEntry {
+                state,
+                ent,
+                unloading: false,
+            },
+        );
+    }
+
+    // Forget the chunk, and give back its entity or the entity of its task.
+    fn remove(&mut self, cords: [i32; 3]) -> Option<Entity> {
+        self.chunks.remove(&cords)?.ent
     }
+}
+
+// Mesh the grid of a chunk.
+pub fn mesh_chunk(
+    cords: [i32; 3],
+    grid: &[Block],
+    breg: &BlockRegistry,
+) -> Option<(Mesh, MeshMD<Block>)> {
+    // The bottom of the world can never be seen, so it isn't meshed.
+    let outer_layer: &[Face] = if cords[1] == 0 { &[Bottom] } else { &[] };
+    mesh_grid(
+        CHUNK_DIMS,
+        outer_layer,
+        grid,
+        breg,
+        MeshingAlgorithm::Culling,
+        Some(SmoothLightingParameters {
+            intensity: 0.5,
+            max: 0.7,
+            smoothing: 1.5,
+            apply_at_gen: true,
+        }),
+    )
+}
 
-    pub fn change_ent(&mut self, cords: [i32; 2], ent: Entity) {
-        *(self
-            .pos_to_ent
-            .get_mut(&cords)
-            .expect("Couldn't find chunk entity")) = ent;
+// Mesh the grid of a chunk with culling or greedily. Greedy meshes have no meta data. A chunk without
+// any faces (like one that is all air) can't be meshed with culling, it gets an empty greedy mesh
+// that is meshed again from scratch once blocks are placed in it.
+pub fn mesh_chunk_with(
+    meshing: MeshingMode,
+    cords: [i32; 3],
+    grid: &[Block],
+    breg: &BlockRegistry,
+) -> (Mesh, Option<MeshMD<Block>>) {
+    let culled = match meshing {
+        MeshingMode::Culling => mesh_chunk(cords, grid, breg),
+        MeshingMode::Greedy => None,
+    };
+    match culled {
+        Some((mesh, meta_data)) => (mesh, Some(meta_data)),
+        None => (greedy_mesh(cords, grid, breg), None),
     }
 }
 
 impl ChunkQueue {
-    pub fn queue_spawn(&mut self, pos: [i32; 2]) {
-        self.queue.push((pos, QdChunk::Spawn));
+    // A chunk that was going to be unloaded just stays, and one that is already loading isn't
+    // loaded twice.
+    pub fn queue_spawn(&mut self, chunk_map: &mut ChunkMap, pos: [i32; 3]) {
+        match chunk_map.chunks.get_mut(&pos) {
+            Some(entry) if entry.unloading => {
+                entry.unloading = false;
+                self.queue.remove(&pos);
+            }
+            Some(_) => {}
+            None => {
+                chunk_map.insert(pos, ChunkState::Queued, None);
+                self.queue.insert(pos, QdChunk::Spawn);
+            }
+        }
     }
 
-    pub fn queue_despawn(&mut self, pos: [i32; 2]) {
-        self.queue.push((pos, QdChunk::Despawn));
+    // A chunk that is still queued is just forgotten, the others are unloaded once dequeued.
+    pub fn queue_despawn(&mut self, chunk_map: &mut ChunkMap, pos: [i32; 3]) {
+        match chunk_map.chunks.get_mut(&pos) {
+            Some(entry) if entry.state == ChunkState::Queued => {
+                chunk_map.chunks.remove(&pos);
+                self.queue.remove(&pos);
+            }
+            Some(entry) => {
+                entry.unloading = true;
+                self.queue.insert(pos, QdChunk::Despawn);
+            }
+            None => {
+                assert!(!self.panic_when_cant_find_chunk, "Couldn't find chunk");
+            }
+        }
+    }
+
+    pub fn queue_received(&mut self, chunk_map: &mut ChunkMap, pos: [i32; 3], grid: ChunkStorage) {
+        match chunk_map.chunks.get_mut(&pos) {
+            Some(entry) => entry.unloading = false,
+            None => chunk_map.insert(pos, ChunkState::Queued, None),
+        }
+        self.queue.insert(pos, QdChunk::Received(grid));
     }
 
-    // Dequeue all the pending chunks to spawn / despawn.
-    pub fn dequeue_all(
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // Dequeue the pending chunks to spawn / despawn. All of the chunks to despawn are despawned,
+    // but only `budget` chunks are started being spawned, the ones the viewers need the most first.
+    #[allow(clippy::too_many_arguments)]
+    pub fn dequeue(
         &mut self,
         mut commands: Commands,
         breg: Arc<BlockRegistry>,
         chunk_map: &mut ChunkMap,
+        save: &WorldSave,
+        pending_saves: &PendingSaves,
+        world_settings: &WorldSettings,
+        meshing: Option<MeshingMode>,
+        viewers: &[ChunkViewer],
+        budget: usize,
     ) {
         if self.queue.is_empty() {
             return;
         }
 
-        let noise = Perlin::new(GEN_SEED);
+        let generator = TerrainGenerator::new(world_settings.seed, world_settings.terrain());
         let thread_pool = AsyncComputeTaskPool::get();
-        for chunk in self.queue.as_slice() {
-            match chunk.1 {
-                // Despawn chunk by just despawning it's entity, and removing it
-                // from the ChunkMap data structure.
+        let mut order: Vec<([i32; 3], f32)> = self
+            .queue
+            .iter()
+            .map(|(pos, chunk)| match chunk {
+                // Despawning is cheap and frees the chunks, so it's done first.
+                QdChunk::Despawn => (*pos, f32::MIN),
+                _ => (*pos, load_priority(*pos, viewers)),
+            })
+            .collect();
+        order.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let mut started = 0;
+        for (pos, _) in order {
+            let Some(qd_chunk) = self.queue.remove(&pos) else {
+                continue;
+            };
+            if started == budget && !matches!(qd_chunk, QdChunk::Despawn) {
+                self.queue.insert(pos, qd_chunk);
+                break;
+            }
+            match qd_chunk {
+                // Despawn the chunk's entity, or the entity of its task, which cancels the task.
                 QdChunk::Despawn => {
-                    let ent;
-                    if let Some(e) = chunk_map.get_ent(chunk.0) {
-                        if e != Entity::PLACEHOLDER {
-                            ent = commands.entity(e).id();
-                        } else {
-                            continue;
-                        }
-                    } else {
-                        assert!(!self.panic_when_cant_find_chunk, "Couldn't find chunk");
-                        continue;
+                    if let Some(ent) = chunk_map.remove(pos) {
+                        commands.entity(ent).despawn();
                     }
-                    chunk_map.remove_ent(chunk.0, ent);
-                    commands.entity(ent).despawn();
                 }
 
-                // Spawn chunk by first inserting a placeholder entity to the ChunkMap so it won't
-                // be added again while the thread is still computing the mesh.
-                // Spawn a thread that computes the mesh etc. everything we need to spawn the
-                // chunk.
-                // `handle_tasks` picks up the resault of the thread when it finishes, and spawns
-                // it.
+                // Spawn a task that loads or generates the blocks of the chunk,
+                // `mesh_generated_chunks` then meshes them, and `handle_tasks` spawns the chunk.
                 QdChunk::Spawn => {
-                    let task;
-
-                    if chunk_map.exists(chunk.0) {
-                        assert!(
-                            !self.panic_when_cant_find_chunk,
-                            "Can't spawn chunk that is already spawned."
-                        );
-                        continue;
-                    }
-
-                    chunk_map.insert_ent(chunk.0, Entity::PLACEHOLDER);
-                    let breg = Arc::clone(&breg);
-                    let cords = chunk.0;
-                    task = thread_pool.spawn(async move {
-                        let grid = generate_chunk(cords, &noise);
-                        let t = mesh_grid(
-                            CHUNK_DIMS,
-                            &[Bottom /* , Forward, Back, Right, Left */],
-                            &grid,
-                            &*breg,
-                            MeshingAlgorithm::Culling,
-                            Some(SmoothLightingParameters {
-                                intensity: 0.5,
-                                max: 0.7,
-                                smoothing: 1.5,
-                                apply_at_gen: true,
-                            }),
-                        )?;
-                        Some((t, grid, cords))
+                    let cords = pos;
+                    let save_dir = save.dir.clone();
+                    // A chunk that was unloaded before its edits were written is loaded from them.
+                    let pending = pending_saves.get(cords).map(<[u8]>::to_vec);
+                    let task = thread_pool.spawn(async move {
+                        // Chunks that were edited are loaded from disk, the rest are generated.
+                        pending
+                            .and_then(|bytes| decode_grid(&bytes))
+                            .or_else(|| load_chunk(&save_dir, cords))
+                            .unwrap_or_else(|| generate_chunk(cords, &generator))
                     });
-                    commands.spawn(ComputeChunk(task));
+                    let ent = commands.spawn(GenerateChunk { cords, task }).id();
+                    chunk_map.insert(pos, ChunkState::Generating, Some(ent));
+                    started += 1;
+                }
+
+                // A chunk from the server replaces the one that is loaded, or the one that is
+                // still being computed.
+                QdChunk::Received(grid) => {
+                    if let Some(ent) = chunk_map.remove(pos) {
+                        commands.entity(ent).despawn();
+                    }
+                    let task = mesh_task(pos, grid, generator, Arc::clone(&breg), meshing, None);
+                    let ent = commands.spawn(task).id();
+                    chunk_map.insert(pos, ChunkState::Meshing, Some(ent));
+                    started += 1;
                 }
             }
         }
-        self.queue.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AIR;
+    use bevy::ecs::system::CommandQueue;
+    use bevy::tasks::TaskPool;
+
+    const VIEWER: ChunkViewer = ChunkViewer {
+        chunk: [0, 0, 0],
+        forward: Vec3::X,
+    };
+
+    // Dequeue the chunk queue once, with `budget` chunks to start.
+    fn dequeue(queue: &mut ChunkQueue, chunk_map: &mut ChunkMap, world: &mut World, budget: usize) {
+        let mut commands = CommandQueue::default();
+        queue.dequeue(
+            Commands::new(&mut commands, world),
+            Arc::new(BlockRegistry::default()),
+            chunk_map,
+            &WorldSave::default(),
+            &PendingSaves::default(),
+            &WorldSettings::default(),
+            None,
+            &[VIEWER],
+            budget,
+        );
+        commands.apply(world);
+    }
+
+    fn in_state(chunk_map: &ChunkMap, state: ChunkState) -> Vec<[i32; 3]> {
+        chunk_map
+            .states()
+            .filter(|(_, s)| *s == state)
+            .map(|(cords, _)| cords)
+            .collect()
+    }
+
+    #[test]
+    fn nearer_chunks_come_first() {
+        let priority = |cords| load_priority(cords, &[VIEWER]);
+        assert!(priority([0, 0, 0]) < priority([0, 0, 2]));
+        assert!(priority([0, 0, 2]) < priority([0, 0, 4]));
+        assert!(priority([0, 2, 0]) < priority([0, -4, 0]));
+    }
+
+    #[test]
+    fn chunks_in_front_come_first() {
+        let priority = |cords| load_priority(cords, &[VIEWER]);
+        // All at the same distance.
+        assert!(priority([2, 0, 0]) < priority([0, 0, 2]));
+        assert!(priority([0, 0, 2]) < priority([-2, 0, 0]));
+        assert_eq!(priority([0, 0, 2]), priority([0, 0, -2]));
+    }
+
+    #[test]
+    fn the_nearest_viewer_counts() {
+        let other = ChunkViewer {
+            chunk: [10, 0, 0],
+            forward: Vec3::NEG_X,
+        };
+        let both = [VIEWER, other];
+        assert_eq!(
+            load_priority([9, 0, 0], &both),
+            load_priority([9, 0, 0], &[other])
+        );
+        assert_eq!(
+            load_priority([1, 0, 0], &both),
+            load_priority([1, 0, 0], &[VIEWER])
+        );
+    }
+
+    #[test]
+    fn chunks_are_queued_once() {
+        let mut queue = ChunkQueue::default();
+        let mut chunk_map = ChunkMap::default();
+        queue.queue_spawn(&mut chunk_map, [1, 0, 0]);
+        queue.queue_spawn(&mut chunk_map, [1, 0, 0]);
+        assert_eq!(queue.queue.len(), 1);
+        assert_eq!(chunk_map.state([1, 0, 0]), Some(ChunkState::Queued));
+
+        // A chunk that is still queued is just forgotten.
+        queue.queue_despawn(&mut chunk_map, [1, 0, 0]);
+        assert!(queue.is_empty());
+        assert!(!chunk_map.exists([1, 0, 0]));
+
+        queue.queue_spawn(&mut chunk_map, [1, 0, 0]);
+        queue.queue_received(&mut chunk_map, [1, 0, 0], ChunkStorage::uniform(AIR));
+        assert_eq!(queue.queue.len(), 1);
+        assert!(matches!(queue.queue[&[1, 0, 0]], QdChunk::Received(_)));
+    }
+
+    #[test]
+    fn unloading_chunks_that_are_wanted_again_stay() {
+        AsyncComputeTaskPool::get_or_init(TaskPool::new);
+        let mut world = World::new();
+        let mut queue = ChunkQueue::default();
+        let mut chunk_map = ChunkMap::default();
+        queue.queue_spawn(&mut chunk_map, [1, 0, 0]);
+        dequeue(&mut queue, &mut chunk_map, &mut world, 1);
+        assert_eq!(chunk_map.state([1, 0, 0]), Some(ChunkState::Generating));
+
+        queue.queue_despawn(&mut chunk_map, [1, 0, 0]);
+        assert_eq!(chunk_map.state([1, 0, 0]), Some(ChunkState::Unloading));
+        queue.queue_spawn(&mut chunk_map, [1, 0, 0]);
+        assert!(queue.is_empty());
+        assert_eq!(chunk_map.state([1, 0, 0]), Some(ChunkState::Generating));
+        assert_eq!(world.query::<&GenerateChunk>().iter(&world).count(), 1);
+    }
+
+    #[test]
+    fn only_the_budget_is_started_in_a_frame() {
+        AsyncComputeTaskPool::get_or_init(TaskPool::new);
+        let mut world = World::new();
+        let mut queue = ChunkQueue::default();
+        let mut chunk_map = ChunkMap::default();
+        for x in -3..=3 {
+            for z in -3..=3 {
+                queue.queue_spawn(&mut chunk_map, [x, 0, z]);
+            }
+        }
+        dequeue(&mut queue, &mut chunk_map, &mut world, 5);
+        let started = in_state(&chunk_map, ChunkState::Generating);
+        let queued = in_state(&chunk_map, ChunkState::Queued);
+        assert_eq!(started.len(), 5);
+        assert_eq!(queued.len(), 44);
+        assert_eq!(world.query::<&GenerateChunk>().iter(&world).count(), 5);
+        // The ones that were started are the ones the viewer needs the most.
+        let priority = |cords: &[i32; 3]| load_priority(*cords, &[VIEWER]);
+        let last_started = started.iter().map(priority).fold(f32::MIN, f32::max);
+        assert!(queued.iter().all(|cords| priority(cords) >= last_started));
+        assert!(started.contains(&[0, 0, 0]) && started.contains(&[1, 0, 0]));
+
+        // Despawning doesn't take from the budget.
+        for cords in &started {
+            queue.queue_despawn(&mut chunk_map, *cords);
+        }
+        dequeue(&mut queue, &mut chunk_map, &mut world, 5);
+        assert!(started.iter().all(|cords| !chunk_map.exists(*cords)));
+        assert_eq!(in_state(&chunk_map, ChunkState::Generating).len(), 5);
+        assert_eq!(in_state(&chunk_map, ChunkState::Queued).len(), 39);
     }
 }
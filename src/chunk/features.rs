@@ -0,0 +1,261 @@
+use super::{biome::*, Column, TerrainGenerator, CHUNK_LEN, HEIGHT, LENGTH, WIDTH};
+use crate::{block_reg::*, chunk_position_to_block};
+
+// The world is divided into cells of FEATURE_CELL_SIZE x FEATURE_CELL_SIZE columns, and each cell
+// can have at most one feature rooted in it, this keeps features from growing into each other.
+const FEATURE_CELL_SIZE: i32 = 6;
+// How far (horizontally) a feature can reach from the column it is rooted in.
+const FEATURE_RADIUS: i32 = 2;
+
+// A feature is a set of blocks placed on top of the terrain, like a tree or a prefab structure.
+// The positions are relative to the block right above the surface of the column it's rooted in.
+pub struct Feature {
+    pub blocks: Vec<([i32; 3], Block)>,
+}
+
+impl Feature {
+    pub fn tree(trunk_height: i32) -> Self {
+        let mut blocks = vec![];
+        for y in 0..trunk_height {
+            blocks.push(([0, y, 0], LOG));
+        }
+        // Two wide layers of leaves around the top of the trunk, and two narrow ones above them.
+        let canopy: [(i32, i32); 4] = [(-2, 2), (-1, 2), (0, 1), (1, 1)];
+        for (y, radius) in canopy {
+            for x in -radius..=radius {
+                for z in -radius..=radius {
+                    // Round the corners of the canopy.
+                    if radius == 2 && x.abs() == 2 && z.abs() == 2 {
+                        continue;
+                    }
+                    if y == 1 && x.abs() == 1 && z.abs() == 1 {
+                        continue;
+                    }
+                    blocks.push(([x, trunk_height + y, z], LEAVES));
+                }
+            }
+        }
+        Feature { blocks }
+    }
+}
+
+// A cheap deterministic hash, used to make the same random decisions about a cell every time. Each
+// input is spread over all of the bits by its own odd constant before they are mixed, so inputs
+// that only differ in where their bits are don't hash the same.
+fn hash(seed: u32, x: i32, z: i32, salt: u32) -> u64 {
+    let mut h = (seed as u64).wrapping_mul(0xA0761D6478BD642F)
+        ^ (x as u32 as u64).wrapping_mul(0xE7037ED1A0B428DB)
+        ^ (z as u32 as u64).wrapping_mul(0x8EBC6AF09C88C6E3)
+        ^ (salt as u64).wrapping_mul(0xD6E8FEB86659FD93);
+    h = h.wrapping_add(0x9E3779B97F4A7C15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D049BB133111EB);
+    h ^ (h >> 31)
+}
+
+// A random number in the range 0.0..1.0, decided by the cell.
+fn cell_random(seed: u32, cell: [i32; 2], salt: u32) -> f64 {
+    (hash(seed, cell[0], cell[1], salt) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl TerrainGenerator {
+    // The feature rooted in a cell (if there is one), the column it is rooted in and the height of
+    // the block it's placed on.
+    pub fn feature_in_cell(&self, cell: [i32; 2]) -> Option<([i32; 2], Column, i32, Feature)> {
+        let seed = self.seed();
+        let root = [
+            cell[0] * FEATURE_CELL_SIZE
+                + (hash(seed, cell[0], cell[1], 1) % FEATURE_CELL_SIZE as u64) as i32,
+            cell[1] * FEATURE_CELL_SIZE
+                + (hash(seed, cell[0], cell[1], 2) % FEATURE_CELL_SIZE as u64) as i32,
+        ];
+        let column = self.column(root[0], root[1]);
+        let chance = match column.biome {
+            Biome::Plains => 0.45,
+            Biome::Snowy => 0.3,
+            Biome::Mountains => 0.1,
+            Biome::Desert | Biome::Ocean => return None,
+        };
+        // Trees only grow out of grass or snow, so not on beaches or bare mountain sides.
+        if column.surface != GRASS && column.surface != SNOW {
+            return None;
+        }
+        if cell_random(seed, cell, 3) >= chance {
+            return None;
+        }
+        // Trees don't grow on the floor of a cave that opens to the sky.
+        let ground = self.surface_height(root[0], root[1]);
+        if self.is_cave(root[0], ground, root[1], &column) {
+            return None;
+        }
+        let trunk_height = 4 + (hash(seed, cell[0], cell[1], 4) % 3) as i32;
+        Some((root, column, ground, Feature::tree(trunk_height)))
+    }
+}
+
+// Place all the features that reach into the chunk. Features are decided only by the seed and the
+// cell they are rooted in, so a feature that crosses the border between two chunks is placed the
+// same way in both of them no matter which one is generated first.
+pub fn decorate_chunk(
+    cords: [i32; 3],
+    grid: &mut [Block; CHUNK_LEN],
+    generator: &TerrainGenerator,
+) {
+    let min = chunk_position_to_block(cords, [0, 0, 0]);
+    let max = chunk_position_to_block(cords, [WIDTH - 1, HEIGHT - 1, LENGTH - 1]);
+    let min_cell = [
+        (min[0] - FEATURE_RADIUS).div_euclid(FEATURE_CELL_SIZE),
+        (min[2] - FEATURE_RADIUS).div_euclid(FEATURE_CELL_SIZE),
+    ];
+    let max_cell = [
+        (max[0] + FEATURE_RADIUS).div_euclid(FEATURE_CELL_SIZE),
+        (max[2] + FEATURE_RADIUS).div_euclid(FEATURE_CELL_SIZE),
+    ];
+    for cell_x in min_cell[0]..=max_cell[0] {
+        for cell_z in min_cell[1]..=max_cell[1] {
+            let Some((root, _, ground, feature)) = generator.feature_in_cell([cell_x, cell_z])
+            else {
+                continue;
+            };
+            for (offset, block) in feature.blocks {
+                let pos = [
+                    root[0] + offset[0] - min[0],
+                    ground + 1 + offset[1] - min[1],
+                    root[1] + offset[2] - min[2],
+                ];
+                if pos[0] < 0
+                    || pos[1] < 0
+                    || pos[2] < 0
+                    || pos[0] >= WIDTH as i32
+                    || pos[1] >= HEIGHT as i32
+                    || pos[2] >= LENGTH as i32
+                {
+                    continue;
+                }
+                let index =
+                    pos[0] as usize + pos[2] as usize * WIDTH + pos[1] as usize * WIDTH * LENGTH;
+                // Logs replace anything, leaves only grow into air. Because of this, the order in
+                // which overlapping features are placed doesn't change the result.
+                if block == LOG || grid[index] == AIR {
+                    grid[index] = block;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block_to_chunk_position, generate_chunk, one_d_cords, TerrainSettings};
+    use crate::{ChunkStorage, CHUNK_DIMS, WORLD_HEIGHT_CHUNKS};
+    use std::collections::HashSet;
+
+    // A tree near the origin that crosses the border between two columns of chunks, with where
+    // its blocks are in the world.
+    fn tree_across_chunks(generator: &TerrainGenerator) -> Vec<([i32; 3], Block)> {
+        for cell_x in -8..8 {
+            for cell_z in -8..8 {
+                let Some((root, _, ground, feature)) = generator.feature_in_cell([cell_x, cell_z])
+                else {
+                    continue;
+                };
+                let blocks: Vec<([i32; 3], Block)> = feature
+                    .blocks
+                    .iter()
+                    .map(|(offset, block)| {
+                        let pos = [
+                            root[0] + offset[0],
+                            ground + 1 + offset[1],
+                            root[1] + offset[2],
+                        ];
+                        (pos, *block)
+                    })
+                    .collect();
+                let chunk_of = |pos| {
+                    let (cords, _) = block_to_chunk_position(pos);
+                    [cords[0], cords[2]]
+                };
+                if blocks
+                    .iter()
+                    .any(|(pos, _)| chunk_of(*pos) != chunk_of(blocks[0].0))
+                {
+                    return blocks;
+                }
+            }
+        }
+        panic!("There's no tree across chunks near the origin");
+    }
+
+    #[test]
+    fn chunks_are_the_same_in_any_order() {
+        let generator = TerrainGenerator::new(3, TerrainSettings::default());
+        let tree = tree_across_chunks(&generator);
+        let (center, _) = block_to_chunk_position(tree[0].0);
+        // The chunks around the trunk, and above and below it, the leaves can be in the next
+        // layer of chunks.
+        let mut cords = vec![];
+        for x in -1..=1 {
+            for y in (center[1] - 1).max(0)..=(center[1] + 1).min(WORLD_HEIGHT_CHUNKS - 1) {
+                for z in -1..=1 {
+                    cords.push([center[0] + x, y, center[2] + z]);
+                }
+            }
+        }
+        let generate = |order: &[[i32; 3]]| {
+            // Every order uses its own generator, like chunks generated on different threads.
+            let generator = TerrainGenerator::new(3, TerrainSettings::default());
+            let mut chunks: Vec<([i32; 3], ChunkStorage)> = order
+                .iter()
+                .map(|cords| (*cords, generate_chunk(*cords, &generator)))
+                .collect();
+            chunks.sort_by_key(|(cords, _)| *cords);
+            chunks
+        };
+        let first = generate(&cords);
+        let reversed: Vec<[i32; 3]> = cords.iter().rev().copied().collect();
+        // Every other chunk first, like a ring around the center.
+        let interleaved: Vec<[i32; 3]> = cords
+            .iter()
+            .step_by(2)
+            .chain(cords.iter().skip(1).step_by(2))
+            .copied()
+            .collect();
+        assert_eq!(first, generate(&reversed));
+        assert_eq!(first, generate(&interleaved));
+
+        // The tree is whole in every chunk it reaches into.
+        let mut chunks_of_tree = vec![];
+        for (pos, block) in tree {
+            let (chunk_cords, local) = block_to_chunk_position(pos);
+            let Some((_, chunk)) = first.iter().find(|(cords, _)| *cords == chunk_cords) else {
+                continue;
+            };
+            let found = chunk.get(one_d_cords(local, CHUNK_DIMS));
+            // Another tree's trunk can grow through the leaves.
+            assert!(found == block || (block == LEAVES && found == LOG));
+            if !chunks_of_tree.contains(&chunk_cords) {
+                chunks_of_tree.push(chunk_cords);
+            }
+        }
+        assert!(chunks_of_tree.len() > 1);
+    }
+
+    #[test]
+    fn cells_hash_differently() {
+        let mut seen = HashSet::new();
+        for x in -64..64 {
+            for z in -64..64 {
+                assert!(
+                    seen.insert(hash(5, x, z, 0)),
+                    "{:?} hashes like another cell",
+                    [x, z]
+                );
+            }
+        }
+        // The bits of the coordinates, the seed and the salt don't overlap.
+        assert_ne!(hash(5, 1, 0, 0), hash(5, 0, 1 << 16, 0));
+        assert_ne!(hash(5, 0, 1, 0), hash(5, 0, 0, 1));
+        assert_ne!(hash(1, 0, 0, 0), hash(0, 1 << 16, 0, 0));
+    }
+}
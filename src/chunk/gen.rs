@@ -1,48 +1,238 @@
-use super::{CHUNK_LEN, HEIGHT, LENGTH, NOISE_FACTOR_CONT, NOISE_FACTOR_SCALE, WIDTH};
+use super::{biome::*, decorate_chunk, ChunkStorage, CHUNK_LEN, HEIGHT, LENGTH, WIDTH};
 use crate::block_reg::*;
-use noise::NoiseFn;
+use noise::{NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
 
-// Generate chunk from noise
-pub fn generate_chunk(cords: [i32; 2], noise: &impl NoiseFn<f64, 2>) -> [u16; CHUNK_LEN] {
-    let mut height_map: [usize; WIDTH * LENGTH] = [0; WIDTH * LENGTH];
-    let mut chunk = [0; CHUNK_LEN];
-    // First, generate a height map
-    for j in 0..LENGTH {
-        for i in 0..WIDTH {
-            height_map[i + j * WIDTH] = (HEIGHT as f64 * 1.0 / NOISE_FACTOR_SCALE
-                + (noise.get([
-                    ((i as i32 + cords[0] * WIDTH as i32) as f64 + 0.5) * NOISE_FACTOR_CONT,
-                    ((j as i32 + cords[1] * LENGTH as i32) as f64 + 0.5) * NOISE_FACTOR_CONT,
-                ]) * HEIGHT as f64
-                    * (1.0 - 1.0 / NOISE_FACTOR_SCALE)))
-                as usize;
+// The parameters of the terrain generator.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct TerrainSettings {
+    // How fast the climate changes across the world, smaller values make bigger biomes.
+    pub climate_frequency: f64,
+    pub continentalness_frequency: f64,
+    // How fast the height of the terrain changes inside of a biome.
+    pub detail_frequency: f64,
+    // The 3D noise that is added on top of the height map, it makes overhangs and arches. The
+    // strength is how many blocks above or below the height map the surface can move.
+    pub overhang_frequency: f64,
+    pub overhang_strength: f64,
+    // Caves are carved where two 3D noise fields are both close to zero, which makes long winding
+    // tunnels. The bigger the radius, the wider the tunnels.
+    pub cave_frequency: f64,
+    pub cave_radius: f64,
+    // Caves are never carved at or below this height, so the world always has a floor.
+    pub cave_floor: i32,
+    // How many blocks of the biome's subsurface block are under the surface, stone is under them.
+    pub subsurface_depth: i32,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        TerrainSettings {
+            climate_frequency: 0.003,
+            continentalness_frequency: 0.0015,
+            detail_frequency: 0.02,
+            overhang_frequency: 0.04,
+            overhang_strength: 6.0,
+            cave_frequency: 0.025,
+            cave_radius: 0.09,
+            cave_floor: 2,
+            subsurface_depth: 4,
         }
     }
-    // From the height map, assign a value to each block based on wether it is below or above the
-    // height level at that position, if it is the exact position of the height, grass block.
-    for y in 0..HEIGHT {
-        for z in 0..LENGTH {
-            for x in 0..WIDTH {
-                if height_map[x + z * WIDTH] < y {
-                    chunk[x + z * WIDTH + y * WIDTH * LENGTH] = AIR;
-                } else if height_map[x + z * WIDTH] == y && y > HEIGHT / 4 {
-                    chunk[x + z * WIDTH + y * WIDTH * LENGTH] = GRASS;
-                } else {
-                    chunk[x + z * WIDTH + y * WIDTH * LENGTH] = DIRT;
+}
+
+// The noise fields that make up the terrain. Everything generated is a pure function of the seed
+// and the coordinates, so chunks can be generated in any order and on any thread.
+#[derive(Clone, Copy)]
+pub struct TerrainGenerator {
+    seed: u32,
+    settings: TerrainSettings,
+    temperature: Perlin,
+    humidity: Perlin,
+    continentalness: Perlin,
+    detail: Perlin,
+    overhang: Perlin,
+    cave_a: Perlin,
+    cave_b: Perlin,
+}
+
+// Everything the generator needs to know about a column of blocks.
+#[derive(Clone, Copy, Debug)]
+pub struct Column {
+    // The height of the height map, the 3D noise can move the actual surface up or down from it.
+    pub height: i32,
+    pub biome: Biome,
+    pub surface: Block,
+    pub subsurface: Block,
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: u32, settings: TerrainSettings) -> Self {
+        TerrainGenerator {
+            seed,
+            settings,
+            temperature: Perlin::new(seed.wrapping_add(1)),
+            humidity: Perlin::new(seed.wrapping_add(2)),
+            continentalness: Perlin::new(seed.wrapping_add(3)),
+            detail: Perlin::new(seed),
+            overhang: Perlin::new(seed.wrapping_add(4)),
+            cave_a: Perlin::new(seed.wrapping_add(5)),
+            cave_b: Perlin::new(seed.wrapping_add(6)),
+        }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn settings(&self) -> &TerrainSettings {
+        &self.settings
+    }
+
+    pub fn climate(&self, x: i32, z: i32) -> Climate {
+        let climate_point = [
+            x as f64 * self.settings.climate_frequency,
+            z as f64 * self.settings.climate_frequency,
+        ];
+        Climate {
+            temperature: self.temperature.get(climate_point),
+            humidity: self.humidity.get(climate_point),
+            continentalness: self.continentalness.get([
+                x as f64 * self.settings.continentalness_frequency,
+                z as f64 * self.settings.continentalness_frequency,
+            ]) * 1.5,
+        }
+    }
+
+    // The column at (x, z) in world coordinates.
+    pub fn column(&self, x: i32, z: i32) -> Column {
+        let weights = Biome::weights(self.climate(x, z));
+        let detail = self.detail.get([
+            (x as f64 + 0.5) * self.settings.detail_frequency,
+            (z as f64 + 0.5) * self.settings.detail_frequency,
+        ]);
+        // Blend the height curves of all the biomes, weighted by how close the climate is to
+        // each of them.
+        let mut height = 0.0;
+        for (biome, weight) in BIOMES.iter().zip(weights) {
+            let params = biome.params();
+            height += weight * (params.base_height + params.amplitude * detail);
+        }
+        let height = height as i32;
+        let biome = Biome::dominant(&weights);
+        let params = biome.params();
+        let (mut surface, mut subsurface) = (params.surface, params.subsurface);
+        // Grass and snow don't grow on the shore or under water.
+        if height <= SEA_LEVEL + 1 && (surface == GRASS || surface == SNOW) {
+            (surface, subsurface) = (SAND, SAND);
+        }
+        Column {
+            height,
+            biome,
+            surface,
+            subsurface,
+        }
+    }
+
+    // The terrain is solid wherever the density is positive. The density is the distance from
+    // the height map, pushed up or down by 3D noise.
+    pub fn density(&self, x: i32, y: i32, z: i32, column: &Column) -> f64 {
+        let distance = (column.height - y) as f64;
+        // Far enough from the height map, the noise can't change whether the block is solid.
+        if distance.abs() > self.settings.overhang_strength {
+            return distance;
+        }
+        let f = self.settings.overhang_frequency;
+        distance
+            + self.settings.overhang_strength
+                * self
+                    .overhang
+                    .get([x as f64 * f, y as f64 * f * 1.5, z as f64 * f])
+    }
+
+    pub fn is_solid(&self, x: i32, y: i32, z: i32, column: &Column) -> bool {
+        self.density(x, y, z, column) > 0.0
+    }
+
+    pub fn is_cave(&self, x: i32, y: i32, z: i32, column: &Column) -> bool {
+        if y <= self.settings.cave_floor {
+            return false;
+        }
+        // Don't open caves under water, the water above them would be left floating.
+        if column.height <= SEA_LEVEL && y > column.height - self.settings.subsurface_depth * 2 {
+            return false;
+        }
+        let f = self.settings.cave_frequency;
+        let point = [x as f64 * f, y as f64 * f * 2.0, z as f64 * f];
+        self.cave_a.get(point).abs() < self.settings.cave_radius
+            && self.cave_b.get(point).abs() < self.settings.cave_radius
+    }
+
+    // The height of the highest solid block in the column that has air above it, this is where
+    // things can be placed on top of the terrain.
+    pub fn surface_height(&self, x: i32, z: i32) -> i32 {
+        let column = self.column(x, z);
+        let top = column.height + self.settings.overhang_strength.ceil() as i32;
+        let mut y = top;
+        while y > 0 && !self.is_solid(x, y, z, &column) {
+            y -= 1;
+        }
+        y
+    }
+}
+
+// Generate chunk from noise
+pub fn generate_chunk(cords: [i32; 3], generator: &TerrainGenerator) -> ChunkStorage {
+    let mut chunk = [0; CHUNK_LEN];
+    let subsurface_depth = generator.settings().subsurface_depth;
+    let min_y = cords[1] * HEIGHT as i32;
+    let max_y = min_y + HEIGHT as i32 - 1;
+    for z in 0..LENGTH {
+        for x in 0..WIDTH {
+            let world_x = x as i32 + cords[0] * WIDTH as i32;
+            let world_z = z as i32 + cords[2] * LENGTH as i32;
+            let column = generator.column(world_x, world_z);
+            // Go over the column from the top down, counting how many solid blocks are right
+            // above each block to know if it's the surface, the subsurface or deep underground.
+            // The count starts a few blocks above the chunk, because the blocks of the chunk above
+            // decide what the top of this chunk looks like.
+            let mut solid_above = 0;
+            for world_y in (min_y..=max_y + subsurface_depth + 1).rev() {
+                let solid = generator.is_solid(world_x, world_y, world_z, &column);
+                if world_y <= max_y {
+                    let y = (world_y - min_y) as usize;
+                    chunk[x + z * WIDTH + y * WIDTH * LENGTH] = if !solid {
+                        if world_y <= SEA_LEVEL {
+                            WATER
+                        } else {
+                            AIR
+                        }
+                    } else if generator.is_cave(world_x, world_y, world_z, &column) {
+                        AIR
+                    } else if solid_above == 0 {
+                        column.surface
+                    } else if solid_above <= subsurface_depth {
+                        column.subsurface
+                    } else {
+                        STONE
+                    };
                 }
+                solid_above = if solid { solid_above + 1 } else { 0 };
             }
         }
     }
-    chunk
+    // Finally, place the trees and structures on top of the terrain.
+    decorate_chunk(cords, &mut chunk, generator);
+    ChunkStorage::from_grid(&chunk)
 }
 
-#[allow(dead_code)]
-pub fn rle_compress<T: PartialEq + Copy>(data: &[T]) -> Vec<(T, usize)> {
+pub fn rle_compress<T: PartialEq + Copy>(data: impl IntoIterator<Item = T>) -> Vec<(T, usize)> {
     let mut compressed = Vec::new();
-    let mut iter = data.iter();
-    if let Some(mut run_val) = iter.next().copied() {
+    let mut iter = data.into_iter();
+    if let Some(mut run_val) = iter.next() {
         let mut run_len = 1;
-        for &val in iter {
+        for val in iter {
             if val == run_val {
                 run_len += 1;
             } else {
@@ -56,7 +246,6 @@ pub fn rle_compress<T: PartialEq + Copy>(data: &[T]) -> Vec<(T, usize)> {
     compressed
 }
 
-#[allow(dead_code)]
 pub fn rle_decompress<T: Copy>(compressed: &[(T, usize)]) -> Vec<T> {
     let mut decompressed = Vec::new();
     for &(val, count) in compressed {
@@ -64,3 +253,61 @@ pub fn rle_decompress<T: Copy>(compressed: &[(T, usize)]) -> Vec<T> {
     }
     decompressed
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generation_is_deterministic() {
+        let settings = TerrainSettings::default();
+        let generator = TerrainGenerator::new(7, settings);
+        for cords in [[0, 2, 0], [-3, 3, 5], [12, 0, -9]] {
+            let chunk = generate_chunk(cords, &generator);
+            assert_eq!(chunk, generate_chunk(cords, &generator));
+            assert_eq!(
+                chunk,
+                generate_chunk(cords, &TerrainGenerator::new(7, settings))
+            );
+        }
+        // The surface of another world looks different.
+        let other = TerrainGenerator::new(8, settings);
+        assert_ne!(
+            (0..4)
+                .map(|x| generator.surface_height(x * 16, 0))
+                .collect::<Vec<_>>(),
+            (0..4)
+                .map(|x| other.surface_height(x * 16, 0))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn biome_borders_have_no_cliffs() {
+        // Walking along x, a column is never much higher than the one before it where the biome
+        // changes, not more than where the terrain inside of a biome is the steepest.
+        let generator = TerrainGenerator::new(1, TerrainSettings::default());
+        let (mut borders, mut steepest_border, mut steepest_inside) = (0, 0, 0);
+        for z in (-2000..2000).step_by(100) {
+            let mut last = generator.column(-2000, z);
+            for x in -1999..2000 {
+                let column = generator.column(x, z);
+                let step = (column.height - last.height).abs();
+                if column.biome != last.biome {
+                    borders += 1;
+                    steepest_border = steepest_border.max(step);
+                } else {
+                    steepest_inside = steepest_inside.max(step);
+                }
+                last = column;
+            }
+        }
+        assert!(borders > 100, "The test crosses the borders of biomes");
+        assert!(steepest_border <= steepest_inside);
+        assert!(
+            steepest_border <= 4,
+            "A border has a cliff of {} blocks",
+            steepest_border
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use crate::{
+    chunk::*, one_d_cords, Block, BlockRegistry, Face, Face::*, MeshingMode, RegisteredBlock,
+    WorldSettings, PADDING,
+};
+use bevy::pbr::{ExtendedMaterial, MaterialExtension};
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::render_resource::{AsBindGroup, ShaderRef, ShaderType};
+
+// The uv coordinates of greedy meshes hold both the atlas tile of a face and where on the quad the
+// vertex is: the tile times this, plus how many blocks along the quad the vertex is. The shader
+// splits them apart again, so it has to be at least twice as long as the longest quad.
+pub const UV_TILE_STRIDE: f32 = 32.0;
+const GREEDY_SHADER: &str = "shaders/greedy_blocks.wgsl";
+// Greedy meshes can't be updated in place like culled ones, every edit meshes the chunk again. A
+// chunk that is meshed again this many times is edited often, and is meshed with culling from then
+// on.
+pub const MAX_GREEDY_REMESHES: u32 = 4;
+
+// A chunk with a greedy mesh, and how many times it was meshed again since it was spawned.
+#[derive(Component, Default)]
+pub struct GreedyMeshed {
+    pub remeshes: u32,
+}
+
+// The axes of a face: the one it faces along, and the two its quads span. The second one is up for
+// the faces on the sides, so their textures stand upright.
+pub(super) fn face_axes(face: Face) -> (usize, usize, usize) {
+    match face {
+        Top | Bottom => (1, 0, 2),
+        Right | Left => (0, 2, 1),
+        Back | Forward => (2, 0, 1),
+    }
+}
+
+pub(super) fn faces_positive(face: Face) -> bool {
+    matches!(face, Top | Right | Back)
+}
+
+// The quads of a mesh whose uvs repeat the atlas tiles over them, see `UV_TILE_STRIDE`.
+#[derive(Default)]
+pub(super) struct Quads {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl Quads {
+    // A quad on the `face` side of the blocks from `start`, `len` blocks along each axis it spans.
+    fn push(&mut self, face: Face, start: [usize; 3], len: [usize; 2], block: &RegisteredBlock) {
+        let (n, u, v) = face_axes(face);
+        // Blocks lower than a full block sit on the bottom of their space, they are never merged.
+        let extent = |axis: usize, len: usize| {
+            if axis == 1 {
+                len as f32 * block.height
+            } else {
+                len as f32
+            }
+        };
+        let mut corner = start.map(|c| c as f32 - 0.5);
+        if faces_positive(face) {
+            corner[n] += extent(n, 1);
+        }
+        self.push_rect(
+            face,
+            corner,
+            [extent(u, len[0]), extent(v, len[1])],
+            block.tiles[face as usize],
+            block.alpha,
+        );
+    }
+
+    // A quad that looks towards `face`, from `corner` to `size` further along the axes it spans.
+    pub(super) fn push_rect(
+        &mut self,
+        face: Face,
+        corner: [f32; 3],
+        size: [f32; 2],
+        tile: [u32; 2],
+        alpha: f32,
+    ) {
+        let (n, u, v) = face_axes(face);
+        let mut normal = [0.0; 3];
+        normal[n] = if faces_positive(face) { 1.0 } else { -1.0 };
+        // The textures of the faces that look along -x and +z would be mirrored otherwise.
+        let flip_u = matches!(face, Right | Forward);
+        let first = self.positions.len() as u32;
+        for (du, dv) in [(0, 0), (1, 0), (1, 1), (0, 1)] {
+            let mut pos = corner;
+            pos[u] += size[0] * du as f32;
+            pos[v] += size[1] * dv as f32;
+            let local_u = if flip_u { 1 - du } else { du } as f32 * size[0];
+            // The top of a texture is at the top of the faces on the sides.
+            let local_v = if v == 1 { 1 - dv } else { dv } as f32 * size[1];
+            self.positions.push(pos);
+            self.normals.push(normal);
+            self.uvs.push([
+                tile[0] as f32 * UV_TILE_STRIDE + local_u,
+                tile[1] as f32 * UV_TILE_STRIDE + local_v,
+            ]);
+            self.colors.push([1.0, 1.0, 1.0, alpha]);
+        }
+        // The corners go around the quad counter-clockwise when it's seen from the front.
+        let mut axis_u = Vec3::ZERO;
+        let mut axis_v = Vec3::ZERO;
+        axis_u[u] = 1.0;
+        axis_v[v] = 1.0;
+        let order = if axis_u.cross(axis_v).dot(Vec3::from(normal)) > 0.0 {
+            [0, 1, 2, 0, 2, 3]
+        } else {
+            [0, 2, 1, 0, 3, 2]
+        };
+        self.indices.extend(order.map(|i| first + i));
+    }
+
+    pub(super) fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+// Mesh the grid of a chunk greedily: the faces that can be seen are merged into as few quads as
+// possible, a quad covers a rectangle of faces of the same block that look the same way. Like
+// culling, the faces on the borders of the chunk are kept, except for the bottom of the world.
+pub fn greedy_mesh(cords: [i32; 3], grid: &[Block], breg: &BlockRegistry) -> Mesh {
+    let dims = [WIDTH, HEIGHT, LENGTH];
+    let mut quads = Quads::default();
+    for i in 0..6 {
+        let face = Face::from(i);
+        let (n, u, v) = face_axes(face);
+        // The faces of one layer of blocks, and whether each can be merged with the others.
+        let mut mask: Vec<Option<(Block, bool)>> = vec![None; dims[u] * dims[v]];
+        for layer in 0..dims[n] {
+            for b in 0..dims[v] {
+                for a in 0..dims[u] {
+                    let mut local = [0; 3];
+                    local[n] = layer;
+                    local[u] = a;
+                    local[v] = b;
+                    let block = grid[one_d_cords(local, CHUNK_DIMS)];
+                    mask[a + b * dims[u]] = breg
+                        .get(block)
+                        .filter(|_| {
+                            let next = layer as i32 + if faces_positive(face) { 1 } else { -1 };
+                            if (0..dims[n] as i32).contains(&next) {
+                                local[n] = next as usize;
+                                !breg.is_opaque(grid[one_d_cords(local, CHUNK_DIMS)])
+                            } else {
+                                !(face == Bottom && cords[1] == 0)
+                            }
+                        })
+                        .map(|registered| (block, registered.height == 1.0));
+                }
+            }
+            for b in 0..dims[v] {
+                let mut a = 0;
+                while a < dims[u] {
+                    let Some((block, mergeable)) = mask[a + b * dims[u]] else {
+                        a += 1;
+                        continue;
+                    };
+                    let same = Some((block, true));
+                    let (mut width, mut height) = (1, 1);
+                    if mergeable {
+                        while a + width < dims[u] && mask[a + width + b * dims[u]] == same {
+                            width += 1;
+                        }
+                        while b + height < dims[v]
+                            && (a..a + width).all(|x| mask[x + (b + height) * dims[u]] == same)
+                        {
+                            height += 1;
+                        }
+                    }
+                    for y in b..b + height {
+                        for x in a..a + width {
+                            mask[x + y * dims[u]] = None;
+                        }
+                    }
+                    let mut start = [0; 3];
+                    start[n] = layer;
+                    start[u] = a;
+                    start[v] = b;
+                    if let Some(registered) = breg.get(block) {
+                        quads.push(face, start, [width, height], registered);
+                    }
+                    a += width;
+                }
+            }
+        }
+    }
+    quads.into_mesh()
+}
+
+// The material of greedy meshes: the standard material of the blocks, but the atlas tile of each
+// face is repeated over its quad instead of stretched over it.
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct TiledAtlas {
+    #[uniform(100)]
+    pub tiling: AtlasTiling,
+}
+
+#[derive(ShaderType, Reflect, Debug, Clone)]
+pub struct AtlasTiling {
+    // The number of tiles in a row and in a column of the atlas.
+    pub atlas_size: Vec2,
+    pub padding: f32,
+    pub tile_stride: f32,
+}
+
+impl AtlasTiling {
+    pub fn new(breg: &BlockRegistry) -> Self {
+        AtlasTiling {
+            atlas_size: Vec2::new(breg.atlas_size[0] as f32, breg.atlas_size[1] as f32),
+            padding: PADDING,
+            tile_stride: UV_TILE_STRIDE,
+        }
+    }
+}
+
+impl MaterialExtension for TiledAtlas {
+    fn fragment_shader() -> ShaderRef {
+        GREEDY_SHADER.into()
+    }
+
+    fn prepass_fragment_shader() -> ShaderRef {
+        GREEDY_SHADER.into()
+    }
+}
+
+pub type GreedyMaterial = ExtendedMaterial<StandardMaterial, TiledAtlas>;
+
+#[derive(Resource, Clone)]
+pub struct GreedyBlockMaterial(pub Handle<GreedyMaterial>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{adjacent_cords, three_d_cords, AIR, DIRT, GLASS, STONE};
+    use bevy::utils::HashSet;
+
+    // A chunk above the bottom of the world, so the faces on its bottom are kept.
+    const CORDS: [i32; 3] = [0, 2, 0];
+
+    // The faces of blocks each quad covers, by the block and the way the face looks. Every face
+    // is only covered once.
+    fn covered_faces(mesh: &Mesh) -> Vec<([i32; 3], Face)> {
+        let positions = mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(|values| values.as_float3())
+            .unwrap();
+        let normals = mesh
+            .attribute(Mesh::ATTRIBUTE_NORMAL)
+            .and_then(|values| values.as_float3())
+            .unwrap();
+        let mut faces = vec![];
+        for (corners, normal) in positions.chunks(4).zip(normals.iter().step_by(4)) {
+            let n = (0..3).find(|axis| normal[*axis] != 0.0).unwrap();
+            let face = Face::from(
+                (0..6)
+                    .find(|i| {
+                        let face = Face::from(*i);
+                        face_axes(face).0 == n && faces_positive(face) == (normal[n] > 0.0)
+                    })
+                    .unwrap(),
+            );
+            let (_, u, v) = face_axes(face);
+            let min = |axis: usize| corners.iter().map(|c| c[axis]).fold(f32::MAX, f32::min);
+            let max = |axis: usize| corners.iter().map(|c| c[axis]).fold(f32::MIN, f32::max);
+            let layer = (corners[0][n] - normal[n] * 0.5).round() as i32;
+            for a in (min(u) + 0.5).round() as i32..(max(u) + 0.5).round() as i32 {
+                for b in (min(v) + 0.5).round() as i32..(max(v) + 0.5).round() as i32 {
+                    let mut block = [0; 3];
+                    block[n] = layer;
+                    block[u] = a;
+                    block[v] = b;
+                    faces.push((block, face));
+                }
+            }
+        }
+        faces
+    }
+
+    #[test]
+    fn a_slab_is_one_quad_per_side() {
+        let breg = BlockRegistry::default();
+        let mut grid = [AIR; CHUNK_LEN];
+        for x in 0..WIDTH {
+            for z in 0..LENGTH {
+                grid[one_d_cords([x, 5, z], CHUNK_DIMS)] = STONE;
+            }
+        }
+        let mesh = greedy_mesh(CORDS, &grid, &breg);
+        assert_eq!(mesh.count_vertices(), 6 * 4);
+        let faces = covered_faces(&mesh);
+        assert_eq!(faces.len(), 2 * WIDTH * LENGTH + 2 * WIDTH + 2 * LENGTH);
+        assert!(faces.iter().all(|(block, _)| block[1] == 5));
+    }
+
+    #[test]
+    fn greedy_covers_the_faces_culling_keeps() {
+        let breg = BlockRegistry::default();
+        // Blocks that look random, with transparent ones between them.
+        let mut grid = [AIR; CHUNK_LEN];
+        let mut state: u32 = 7;
+        for block in grid.iter_mut() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            *block = [AIR, AIR, STONE, DIRT, GLASS][(state >> 16) as usize % 5];
+        }
+        // Culling keeps the faces of blocks that aren't covered by an opaque block, and the faces
+        // on the borders of the chunk.
+        let mut kept = HashSet::new();
+        for (index, block) in grid.iter().enumerate() {
+            if *block == AIR {
+                continue;
+            }
+            let local = three_d_cords(index, CHUNK_DIMS);
+            let pos = local.map(|c| c as i32);
+            for i in 0..6 {
+                let face = Face::from(i);
+                let next = adjacent_cords(pos, face);
+                let inside = (0..3)
+                    .all(|axis| (0..[WIDTH, HEIGHT, LENGTH][axis] as i32).contains(&next[axis]));
+                let covered = inside
+                    && breg.is_opaque(grid[one_d_cords(next.map(|c| c as usize), CHUNK_DIMS)]);
+                if !covered {
+                    kept.insert((pos, face));
+                }
+            }
+        }
+
+        let faces = covered_faces(&greedy_mesh(CORDS, &grid, &breg));
+        let unique: HashSet<([i32; 3], Face)> = faces.iter().copied().collect();
+        assert_eq!(faces.len(), unique.len());
+        assert_eq!(unique, kept);
+    }
+}
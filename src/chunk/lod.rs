@@ -0,0 +1,370 @@
+use super::greedy::{face_axes, faces_positive, Quads};
+use crate::{
+    chunk::*, Block, BlockRegistry, CurrentChunk, Face::*, GreedyBlockMaterial, GreedyMaterial,
+    WorldSettings, SEA_LEVEL, WATER,
+};
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy::utils::HashMap;
+use futures_lite::future;
+use std::sync::Arc;
+
+// Every level of detail halves the resolution of the one before, the coarsest samples the terrain
+// every 2^MAX_LOD_LEVEL blocks.
+const MAX_LOD_LEVEL: u32 = 3;
+
+// The height map of a column of chunks past the render distance.
+#[derive(Component)]
+pub struct LodTile {
+    pub column: [i32; 2],
+    pub level: u32,
+}
+
+#[derive(Component)]
+pub struct ComputeLodTile(Task<([i32; 2], u32, Mesh)>);
+
+// The columns that are shown as a height map, by their x and z chunk coordinates.
+#[derive(Resource, Default)]
+pub struct LodMap {
+    tiles: HashMap<[i32; 2], (Entity, u32)>,
+    // The columns whose tile is being computed, and at which level.
+    pending: HashMap<[i32; 2], u32>,
+}
+
+impl LodMap {
+    // The level the column is shown at, None if it doesn't have a tile.
+    pub fn level(&self, column: [i32; 2]) -> Option<u32> {
+        Some(self.tiles.get(&column)?.1)
+    }
+}
+
+// The level of detail of the column at `column`, when the viewer is in the chunk `viewer`. The
+// columns in the render distance have no level, their chunks are loaded, and neither do the
+// columns past the LOD distance.
+pub fn lod_level(
+    column: [i32; 2],
+    viewer: [i32; 3],
+    render_distance: i32,
+    lod_distance: i32,
+) -> Option<u32> {
+    let distance = (column[0] - viewer[0])
+        .abs()
+        .max((column[1] - viewer[2]).abs());
+    if distance <= render_distance || distance > lod_distance {
+        return None;
+    }
+    // The first ring of tiles is at half the resolution, which halves again every time the
+    // distance doubles.
+    let level = 1 + (distance / (render_distance + 1)).ilog2();
+    Some(level.min(MAX_LOD_LEVEL))
+}
+
+// The mesh of the column at `column`, at a level of detail. The terrain is sampled every
+// 2^level blocks, each sample is a flat cell of the block on the surface, with walls down to the
+// cells around it that are lower. The walls on the borders of the tile go further down, to hide
+// the cracks next to tiles of other levels, and next to the chunks that are loaded.
+pub fn lod_mesh(
+    column: [i32; 2],
+    level: u32,
+    generator: &TerrainGenerator,
+    breg: &BlockRegistry,
+) -> Mesh {
+    let step = 1 << level;
+    let cells = WIDTH as i32 / step;
+    // The cells of the tile, with a ring of cells of the tiles around it.
+    let sample = |i: i32, j: i32| -> (i32, Block) {
+        let x = column[0] * WIDTH as i32 + i * step + step / 2;
+        let z = column[1] * LENGTH as i32 + j * step + step / 2;
+        let height = generator.surface_height(x, z);
+        if height < SEA_LEVEL {
+            (SEA_LEVEL, WATER)
+        } else {
+            (height, generator.column(x, z).surface)
+        }
+    };
+    let ring = cells + 2;
+    let samples: Vec<(i32, Block)> = (0..ring * ring)
+        .map(|k| sample(k % ring - 1, k / ring - 1))
+        .collect();
+    let cell = |i: i32, j: i32| samples[((i + 1) + (j + 1) * ring) as usize];
+
+    let mut quads = Quads::default();
+    for j in 0..cells {
+        for i in 0..cells {
+            let (height, block) = cell(i, j);
+            let Some(registered) = breg.get(block) else {
+                continue;
+            };
+            let min = [(i * step) as f32 - 0.5, (j * step) as f32 - 0.5];
+            let top = height as f32 + 0.5;
+            quads.push_rect(
+                Top,
+                [min[0], top, min[1]],
+                [step as f32; 2],
+                registered.tiles[Top as usize],
+                registered.alpha,
+            );
+            for (face, [di, dj]) in [
+                (Right, [1, 0]),
+                (Left, [-1, 0]),
+                (Back, [0, 1]),
+                (Forward, [0, -1]),
+            ] {
+                let (ni, nj) = (i + di, j + dj);
+                let neighbor = cell(ni, nj).0;
+                let on_border = !(0..cells).contains(&ni) || !(0..cells).contains(&nj);
+                let bottom = if on_border {
+                    (height.min(neighbor) - 2 * step).max(0)
+                } else {
+                    neighbor
+                };
+                if bottom >= height {
+                    continue;
+                }
+                let (n, _, _) = face_axes(face);
+                let mut corner = [min[0], bottom as f32 + 0.5, min[1]];
+                if faces_positive(face) {
+                    corner[n] += step as f32;
+                }
+                quads.push_rect(
+                    face,
+                    corner,
+                    [step as f32, (height - bottom) as f32],
+                    registered.tiles[face as usize],
+                    registered.alpha,
+                );
+            }
+        }
+    }
+    quads.into_mesh()
+}
+
+// Shows the terrain past the render distance as height maps, so the horizon is further away than
+// the chunks that are loaded. The tiles are only made from the generator, edits don't show on them.
+pub struct LodPlugin;
+
+impl Plugin for LodPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(
+            Update,
+            (queue_lod_tiles, spawn_lod_tiles, remove_loaded_lod_tiles).chain(),
+        );
+
+        // Resources
+        app.init_resource::<LodMap>();
+    }
+}
+
+// When the player moves to another chunk, compute the tiles of the columns whose level changed.
+// A tile is only replaced once the new one is done, so there is no hole in the meantime.
+fn queue_lod_tiles(
+    viewer: Query<Ref<CurrentChunk>>,
+    world_settings: Res<WorldSettings>,
+    breg: Res<BlockRegistry>,
+    mut lod_map: ResMut<LodMap>,
+    mut commands: Commands,
+) {
+    let Ok(viewer) = viewer.get_single() else {
+        return;
+    };
+    if !viewer.is_changed() {
+        return;
+    }
+    let viewer = viewer.0;
+    let (render_distance, lod_distance) =
+        (world_settings.render_distance, world_settings.lod_distance);
+    let lod_map = lod_map.as_mut();
+    // The tiles that are too far away now are removed, the ones that are in the render distance
+    // stay until the chunks of their column are loaded.
+    lod_map.tiles.retain(|column, (ent, _)| {
+        let far = !in_render_distance([column[0], 0, column[1]], viewer, lod_distance);
+        if far {
+            commands.entity(*ent).despawn();
+        }
+        !far
+    });
+    lod_map.pending.retain(|column, level| {
+        lod_level(*column, viewer, render_distance, lod_distance) == Some(*level)
+    });
+
+    let generator = TerrainGenerator::new(world_settings.seed, world_settings.terrain());
+    let breg = Arc::new(breg.clone());
+    let thread_pool = AsyncComputeTaskPool::get();
+    for x in -lod_distance..=lod_distance {
+        for z in -lod_distance..=lod_distance {
+            let column = [viewer[0] + x, viewer[2] + z];
+            let Some(level) = lod_level(column, viewer, render_distance, lod_distance) else {
+                continue;
+            };
+            if lod_map.level(column) == Some(level) || lod_map.pending.get(&column) == Some(&level)
+            {
+                continue;
+            }
+            lod_map.pending.insert(column, level);
+            let breg = Arc::clone(&breg);
+            let task = thread_pool
+                .spawn(async move { (column, level, lod_mesh(column, level, &generator, &breg)) });
+            commands.spawn(ComputeLodTile(task));
+        }
+    }
+}
+
+fn spawn_lod_tiles(
+    mut tasks: Query<(Entity, &mut ComputeLodTile)>,
+    mut lod_map: ResMut<LodMap>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mat: Res<GreedyBlockMaterial>,
+    mut commands: Commands,
+) {
+    for (ent, mut task) in tasks.iter_mut() {
+        let Some((column, level, mesh)) = future::block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+        commands.entity(ent).despawn();
+        // The player moved on while the tile was computed.
+        if lod_map.pending.get(&column) != Some(&level) {
+            continue;
+        }
+        lod_map.pending.remove(&column);
+        let tile = commands
+            .spawn((
+                MaterialMeshBundle::<GreedyMaterial> {
+                    mesh: meshes.add(mesh),
+                    material: mat.0.clone(),
+                    transform: Transform::from_xyz(
+                        column[0] as f32 * WIDTH as f32,
+                        0.0,
+                        column[1] as f32 * LENGTH as f32,
+                    ),
+                    ..default()
+                },
+                LodTile { column, level },
+            ))
+            .id();
+        if let Some((old, _)) = lod_map.tiles.insert(column, (tile, level)) {
+            commands.entity(old).despawn();
+        }
+    }
+}
+
+// The tiles of the columns that came into the render distance are removed once all of the chunks
+// of the column are loaded.
+fn remove_loaded_lod_tiles(
+    viewer: Query<&CurrentChunk>,
+    world_settings: Res<WorldSettings>,
+    chunk_map: Res<ChunkMap>,
+    mut lod_map: ResMut<LodMap>,
+    mut commands: Commands,
+) {
+    let Ok(viewer) = viewer.get_single() else {
+        return;
+    };
+    lod_map.tiles.retain(|column, (ent, _)| {
+        let loaded = in_render_distance(
+            [column[0], 0, column[1]],
+            viewer.0,
+            world_settings.render_distance,
+        ) && (0..WORLD_HEIGHT_CHUNKS)
+            .all(|y| chunk_map.get_ent([column[0], y, column[1]]).is_some());
+        if loaded {
+            commands.entity(*ent).despawn();
+        }
+        !loaded
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_by_distance() {
+        let (render_distance, lod_distance) = (4, 64);
+        let level = |x: i32| lod_level([x, 0], [0, 0, 0], render_distance, lod_distance);
+        assert_eq!(level(0), None);
+        assert_eq!(level(render_distance), None);
+        assert_eq!(level(render_distance + 1), Some(1));
+        // Every time the distance doubles, the level goes up by one.
+        assert_eq!(level(2 * (render_distance + 1) - 1), Some(1));
+        assert_eq!(level(2 * (render_distance + 1)), Some(2));
+        assert_eq!(level(4 * (render_distance + 1)), Some(3));
+        // Up to the coarsest level.
+        assert_eq!(level(8 * (render_distance + 1)), Some(MAX_LOD_LEVEL));
+        assert_eq!(level(lod_distance), Some(MAX_LOD_LEVEL));
+        assert_eq!(level(lod_distance + 1), None);
+    }
+
+    #[test]
+    fn levels_are_by_the_farthest_axis_from_the_viewer() {
+        let viewer = [10, 3, -10];
+        assert_eq!(lod_level([10, -10], viewer, 4, 64), None);
+        assert_eq!(lod_level([15, -12], viewer, 4, 64), Some(1));
+        assert_eq!(lod_level([12, -20], viewer, 4, 64), Some(2));
+    }
+
+    // The lowest block of the skirt on the +x border of each cell of a tile, by its z.
+    fn skirt_bottoms(mesh: &Mesh) -> Vec<(f32, f32)> {
+        let positions = mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(|values| values.as_float3())
+            .unwrap();
+        let normals = mesh
+            .attribute(Mesh::ATTRIBUTE_NORMAL)
+            .and_then(|values| values.as_float3())
+            .unwrap();
+        let border = WIDTH as f32 - 0.5;
+        positions
+            .chunks(4)
+            .zip(normals.chunks(4))
+            .filter(|(quad, normal)| {
+                normal[0] == [1.0, 0.0, 0.0] && quad.iter().all(|v| v[0] == border)
+            })
+            .map(|(quad, _)| {
+                let z = quad.iter().map(|v| v[2]).fold(f32::MAX, f32::min);
+                let bottom = quad.iter().map(|v| v[1]).fold(f32::MAX, f32::min);
+                (z, bottom)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skirts_reach_below_the_tiles_around() {
+        let settings = WorldSettings::default();
+        let generator = TerrainGenerator::new(settings.seed, settings.terrain());
+        let breg = BlockRegistry::default();
+        // The top of the surface of the cell of a level that covers x, z.
+        let top = |x: i32, z: i32, level: u32| {
+            let step = 1 << level;
+            let sample = |a: i32| a.div_euclid(step) * step + step / 2;
+            generator
+                .surface_height(sample(x), sample(z))
+                .max(SEA_LEVEL) as f32
+                + 0.5
+        };
+        let level = 2;
+        let mut skirts = 0;
+        for column in [[0, 0], [3, -2], [-5, 7]] {
+            let mesh = lod_mesh(column, level, &generator, &breg);
+            let x = (column[0] + 1) * WIDTH as i32;
+            for (z, bottom) in skirt_bottoms(&mesh) {
+                skirts += 1;
+                let z = column[1] * LENGTH as i32 + (z + 0.5) as i32;
+                // Across the border is a loaded chunk, or a tile one level finer or coarser.
+                for dz in 0..1 << level {
+                    for neighbor_level in [0, level - 1, level + 1] {
+                        let neighbor = top(x, z + dz, neighbor_level);
+                        assert!(
+                            bottom <= neighbor,
+                            "The skirt at {:?} ends at {} above the level {} tile at {}",
+                            [x, z + dz],
+                            bottom,
+                            neighbor_level,
+                            neighbor
+                        );
+                    }
+                }
+            }
+        }
+        assert!(skirts > 0);
+    }
+}
@@ -1,12 +1,28 @@
+pub mod biome;
 pub mod chunk_queue;
+pub mod features;
 pub mod gen;
+pub mod greedy;
+pub mod lod;
+pub mod save;
+pub mod storage;
 pub mod systems;
 
+pub use biome::*;
 pub use chunk_queue::*;
+pub use features::*;
 pub use gen::*;
+pub use greedy::*;
+pub use lod::*;
+pub use save::*;
+pub use storage::*;
 use systems::*;
 
-use crate::{Block, GlobalSecondsCounter};
+use crate::{
+    remesh_relit_chunks, world_is_local, Block, BlockRegistrySource, GlobalSecondsCounter,
+    LightGrid,
+};
+use bevy::pbr::MaterialPlugin;
 use bevy::prelude::*;
 use bevy_meshem::prelude::{Dimensions, MeshMD};
 
@@ -16,21 +32,37 @@ pub const HEIGHT: usize = CHUNK_DIMS.1;
 pub const WIDTH: usize = CHUNK_DIMS.0;
 pub const LENGTH: usize = CHUNK_DIMS.2;
 pub const CHUNK_LEN: usize = CHUNK_DIMS.0 * CHUNK_DIMS.1 * CHUNK_DIMS.2;
-const NOISE_FACTOR_CONT: f64 = 0.020;
-// has to be greater than 1.0
-pub(crate) const NOISE_FACTOR_SCALE: f64 = 1.8;
+// How many chunks are stacked on top of each other, the world is this many chunks tall.
+pub const WORLD_HEIGHT_CHUNKS: i32 = 8;
+pub const WORLD_HEIGHT: usize = HEIGHT * WORLD_HEIGHT_CHUNKS as usize;
 
 #[derive(Component)]
 pub struct ToUpdate;
 
+// Marks chunks that have to be meshed again from scratch instead of updated, because the light that
+// is baked into their mesh changed.
+#[derive(Component)]
+pub struct ToRemesh;
+
 #[derive(Component)]
 pub struct Chunk {
-    pub meta_data: MeshMD<Block>,
-    pub cords: [i32; 2],
+    // None for the chunks with a greedy mesh, which can't be updated in place.
+    pub meta_data: Option<MeshMD<Block>>,
+    pub cords: [i32; 3],
     // pub compressed_chunk: Vec<(Block, usize)>,
-    pub grid: [Block; CHUNK_LEN],
+    pub grid: ChunkStorage,
+    pub light: LightGrid,
 }
 
+// Present when the chunks are rendered. Without it (on a headless server) new chunks are only lit,
+// their meshes are never made.
+#[derive(Resource)]
+pub struct RenderChunks;
+
+// The mesh a chunk was spawned with, until it's added to the mesh assets.
+#[derive(Component)]
+pub struct NewChunkMesh(pub Option<Mesh>);
+
 #[derive(States, Clone, Default, PartialEq, Eq, Hash, Debug)]
 pub enum InitialChunkLoadState {
     #[default]
@@ -47,6 +79,30 @@ pub struct ToCull {
     pub culled: [bool; 6],
 }
 
+impl ToCull {
+    // A freshly meshed chunk still has to cull the faces against all of its neighbours, except for
+    // the top of the highest chunk and the bottom of the lowest chunk, which have none.
+    pub fn new(cords: [i32; 3]) -> Self {
+        ToCull {
+            culled: [
+                cords[1] == WORLD_HEIGHT_CHUNKS - 1,
+                cords[1] == 0,
+                false,
+                false,
+                false,
+                false,
+            ],
+        }
+    }
+}
+
+// Whether a chunk is close enough to a viewer in `viewer` to be loaded. The whole height of the world
+// is always loaded.
+pub fn in_render_distance(chunk: [i32; 3], viewer: [i32; 3], render_distance: i32) -> bool {
+    (chunk[0] - viewer[0]).abs() <= render_distance
+        && (chunk[2] - viewer[2]).abs() <= render_distance
+}
+
 pub struct ChunkPlugin;
 
 impl Plugin for ChunkPlugin {
@@ -55,11 +111,44 @@ impl Plugin for ChunkPlugin {
         app.add_systems(
             Update,
             (
-                spawn_and_despawn_chunks,
+                // When playing on a server, it decides which chunks are loaded, and saves them.
+                // Saves the chunks that are unloading before they are despawned.
+                (
+                    spawn_and_despawn_chunks,
+                    save_edited_chunks
+                        .after(spawn_and_despawn_chunks)
+                        .before(frame_chunk_update),
+                )
+                    .run_if(world_is_local),
                 frame_chunk_update,
+                // Checks the chunk map after the chunks that left the range were unloaded from it.
+                mesh_generated_chunks.after(frame_chunk_update),
                 (update_closby_chunks).run_if(in_state(InitialChunkLoadState::Complete)),
+                hot_reload_block_registry.run_if(resource_changed::<GlobalSecondsCounter>()),
             ),
         );
+        app.add_systems(Last, save_before_exit.run_if(world_is_local));
+
+        // Resources
+        app.init_resource::<ChunkMap>()
+            .init_resource::<ChunkQueue>()
+            .init_resource::<ChunkLoadBudget>()
+            .init_resource::<WorldSave>()
+            .init_resource::<PendingSaves>()
+            .init_resource::<BlockRegistrySource>();
+
+        // States
+        app.add_state::<InitialChunkLoadState>();
+    }
+}
+
+// Turns the chunks into meshes that can be rendered, and keeps them up to date.
+pub struct ChunkMeshPlugin;
+
+impl Plugin for ChunkMeshPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(Update, insert_chunk_meshes);
         app.add_systems(
             PostUpdate,
             (
@@ -68,14 +157,15 @@ impl Plugin for ChunkPlugin {
                         .and_then(resource_changed::<GlobalSecondsCounter>()),
                 ),
                 update_mesh_frame,
+                remesh_chunks.after(update_mesh_frame),
+                remesh_relit_chunks,
             ),
         );
 
         // Resources
-        app.init_resource::<ChunkMap>()
-            .init_resource::<ChunkQueue>();
+        app.insert_resource(RenderChunks);
 
-        // States
-        app.add_state::<InitialChunkLoadState>();
+        // Plugins
+        app.add_plugins(MaterialPlugin::<GreedyMaterial>::default());
     }
 }
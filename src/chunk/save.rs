@@ -0,0 +1,463 @@
+use super::{rle_compress, rle_decompress, ChunkStorage, CHUNK_LEN};
+use crate::Block;
+use bevy::prelude::*;
+use bevy::tasks::{IoTaskPool, Task, TaskPool};
+use bevy::utils::hashbrown::HashMap;
+use futures_lite::future;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// Every region file holds up to REGION_SIZE x REGION_SIZE columns of chunks.
+pub const REGION_SIZE: i32 = 16;
+pub const SAVE_DIR: &str = "saves/world";
+const REGION_MAGIC: &[u8; 4] = b"MCBR";
+const REGION_VERSION: u32 = 2;
+// How often the chunks that were edited are written to their region files.
+const SAVE_INTERVAL_SECS: f32 = 5.0;
+
+// Where the world is saved. Only chunks that have been edited by the player are written to disk,
+// the rest are regenerated from noise when they are loaded again.
+#[derive(Resource, Clone)]
+pub struct WorldSave {
+    pub dir: PathBuf,
+}
+
+impl Default for WorldSave {
+    fn default() -> Self {
+        WorldSave {
+            dir: PathBuf::from(SAVE_DIR),
+        }
+    }
+}
+
+// The chunks of a region that couldn't be written, and why.
+pub type FailedSave = (Vec<[i32; 3]>, io::Error);
+
+// Chunks marked with this component have been edited and will be written to their region file.
+#[derive(Component)]
+pub struct ToSave;
+
+// The edited chunks that are waiting to be written to disk, encoded by `encode_grid`. They are
+// written every few seconds, all the chunks of a region at once, on the IO task pool. Until they are
+// on disk, a chunk that is loaded again is loaded from here instead of from its region file.
+#[derive(Resource)]
+pub struct PendingSaves {
+    queued: HashMap<[i32; 3], Vec<u8>>,
+    // The chunks that `task` is writing.
+    writing: HashMap<[i32; 3], Vec<u8>>,
+    task: Option<Task<Vec<FailedSave>>>,
+    pub timer: Timer,
+}
+
+impl Default for PendingSaves {
+    fn default() -> Self {
+        PendingSaves {
+            queued: HashMap::new(),
+            writing: HashMap::new(),
+            task: None,
+            timer: Timer::from_seconds(SAVE_INTERVAL_SECS, TimerMode::Repeating),
+        }
+    }
+}
+
+impl PendingSaves {
+    pub fn queue(&mut self, cords: [i32; 3], grid: &ChunkStorage) {
+        self.queued.insert(cords, encode_grid(grid));
+    }
+
+    // The last saved blocks of a chunk that isn't written yet.
+    pub fn get(&self, cords: [i32; 3]) -> Option<&[u8]> {
+        self.queued
+            .get(&cords)
+            .or_else(|| self.writing.get(&cords))
+            .map(Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty() && self.writing.is_empty()
+    }
+
+    // Once the last write is done, start writing the chunks that were queued since. Only one write
+    // runs at a time, so a region is never written by two tasks at once.
+    pub fn write(&mut self, dir: &Path) {
+        if let Some(task) = self.task.as_mut() {
+            let Some(failed) = future::block_on(future::poll_once(task)) else {
+                return;
+            };
+            self.task = None;
+            self.finish_write(failed);
+        }
+        if self.queued.is_empty() {
+            return;
+        }
+        self.writing = std::mem::take(&mut self.queued);
+        let chunks = self.writing.clone();
+        let dir = dir.to_path_buf();
+        self.task = Some(IoTaskPool::get().spawn(async move { save_chunks(&dir, &chunks) }));
+    }
+
+    // The chunks that couldn't be written are queued again, so the next write tries them again,
+    // unless the chunk was queued again since with newer blocks.
+    fn finish_write(&mut self, failed: Vec<FailedSave>) {
+        for (chunks, e) in failed {
+            warn!("Couldn't save chunks, trying again later: {}", e);
+            for cords in chunks {
+                if let Some(data) = self.writing.remove(&cords) {
+                    self.queued.entry(cords).or_insert(data);
+                }
+            }
+        }
+        self.writing.clear();
+    }
+
+    // Write all of the chunks now, waiting for the write that is running. Used before exiting, the
+    // chunks that can't be written stay queued.
+    pub fn flush(&mut self, dir: &Path) {
+        if let Some(task) = self.task.take() {
+            let failed = future::block_on(task);
+            self.finish_write(failed);
+        }
+        let chunks = std::mem::take(&mut self.queued);
+        for (failed, e) in save_chunks(dir, &chunks) {
+            warn!("Couldn't save chunks {:?}: {}", failed, e);
+            for cords in failed {
+                self.queued.insert(cords, chunks[&cords].clone());
+            }
+        }
+    }
+}
+
+// The coordinates of the region a chunk belongs to, all the chunks in a column share a region.
+pub fn region_of(cords: [i32; 3]) -> [i32; 2] {
+    [
+        cords[0].div_euclid(REGION_SIZE),
+        cords[2].div_euclid(REGION_SIZE),
+    ]
+}
+
+pub fn region_path(dir: &Path, region: [i32; 2]) -> PathBuf {
+    dir.join(format!("r.{}.{}.region", region[0], region[1]))
+}
+
+// Encode the blocks of a chunk as a run-length compressed byte buffer.
+pub fn encode_grid(grid: &ChunkStorage) -> Vec<u8> {
+    let runs = rle_compress(grid.iter());
+    let mut bytes = Vec::with_capacity(4 + runs.len() * 6);
+    bytes.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    for (block, len) in runs {
+        bytes.extend_from_slice(&block.to_le_bytes());
+        bytes.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+    bytes
+}
+
+// Decode a buffer made by `encode_grid`, returns None if the data is corrupted.
+pub fn decode_grid(bytes: &[u8]) -> Option<ChunkStorage> {
+    let mut cursor = bytes;
+    let run_count = read_u32(&mut cursor)? as usize;
+    // Every run has at least one block, more runs than that is corrupted data, and would allocate
+    // whatever the count says.
+    if run_count > CHUNK_LEN {
+        return None;
+    }
+    let mut runs = Vec::with_capacity(run_count);
+    for _ in 0..run_count {
+        let block = Block::from_le_bytes(take(&mut cursor)?);
+        let len = read_u32(&mut cursor)? as usize;
+        runs.push((block, len));
+    }
+    if !cursor.is_empty() || runs.iter().map(|(_, len)| len).sum::<usize>() != CHUNK_LEN {
+        return None;
+    }
+    Some(ChunkStorage::from_grid(&rle_decompress(&runs)))
+}
+
+// Read all the chunks stored in a region file. A missing file is an empty region.
+pub fn read_region(path: &Path) -> io::Result<HashMap<[i32; 3], Vec<u8>>> {
+    let mut region = HashMap::new();
+    let mut bytes = Vec::new();
+    match fs::File::open(path) {
+        Ok(mut file) => file.read_to_end(&mut bytes)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(region),
+        Err(e) => return Err(e),
+    };
+    let corrupted = || io::Error::new(io::ErrorKind::InvalidData, "Corrupted region file");
+    let mut cursor = bytes.as_slice();
+    if take::<4>(&mut cursor).ok_or_else(corrupted)? != *REGION_MAGIC
+        || read_u32(&mut cursor).ok_or_else(corrupted)? != REGION_VERSION
+    {
+        return Err(corrupted());
+    }
+    let chunk_count = read_u32(&mut cursor).ok_or_else(corrupted)?;
+    for _ in 0..chunk_count {
+        let x = i32::from_le_bytes(take(&mut cursor).ok_or_else(corrupted)?);
+        let y = i32::from_le_bytes(take(&mut cursor).ok_or_else(corrupted)?);
+        let z = i32::from_le_bytes(take(&mut cursor).ok_or_else(corrupted)?);
+        let len = read_u32(&mut cursor).ok_or_else(corrupted)? as usize;
+        if cursor.len() < len {
+            return Err(corrupted());
+        }
+        region.insert([x, y, z], cursor[..len].to_vec());
+        cursor = &cursor[len..];
+    }
+    Ok(region)
+}
+
+// Write a whole region file. The file is written to a temporary path first and then renamed, so
+// chunk loading tasks never read a half written region.
+pub fn write_region(path: &Path, region: &HashMap<[i32; 3], Vec<u8>>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(REGION_MAGIC);
+    bytes.extend_from_slice(&REGION_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(region.len() as u32).to_le_bytes());
+    for (cords, data) in region.iter() {
+        for cord in cords {
+            bytes.extend_from_slice(&cord.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::File::create(&tmp_path)?.write_all(&bytes)?;
+    fs::rename(tmp_path, path)
+}
+
+pub fn save_chunk(dir: &Path, cords: [i32; 3], grid: &ChunkStorage) -> io::Result<()> {
+    let path = region_path(dir, region_of(cords));
+    let mut region = read_region(&path)?;
+    region.insert(cords, encode_grid(grid));
+    write_region(&path, &region)
+}
+
+// Write encoded chunks to their region files, reading and writing every region once. Gives back
+// the chunks of the regions that couldn't be written, with the error.
+pub fn save_chunks(dir: &Path, chunks: &HashMap<[i32; 3], Vec<u8>>) -> Vec<FailedSave> {
+    let mut regions: HashMap<[i32; 2], Vec<[i32; 3]>> = HashMap::new();
+    for cords in chunks.keys() {
+        regions.entry(region_of(*cords)).or_default().push(*cords);
+    }
+    let mut errors = vec![];
+    for (region_cords, in_region) in regions {
+        let path = region_path(dir, region_cords);
+        let result = read_region(&path).and_then(|mut region| {
+            for cords in in_region.iter() {
+                region.insert(*cords, chunks[cords].clone());
+            }
+            write_region(&path, &region)
+        });
+        if let Err(e) = result {
+            errors.push((in_region, e));
+        }
+    }
+    errors
+}
+
+// Load a chunk from its region file, returns None if the chunk was never saved.
+pub fn load_chunk(dir: &Path, cords: [i32; 3]) -> Option<ChunkStorage> {
+    let path = region_path(dir, region_of(cords));
+    let region = match read_region(&path) {
+        Ok(region) => region,
+        Err(e) => {
+            warn!("Couldn't read region file {:?}: {}", path, e);
+            return None;
+        }
+    };
+    let grid = decode_grid(region.get(&cords)?);
+    if grid.is_none() {
+        warn!("Chunk {:?} in region file {:?} is corrupted", cords, path);
+    }
+    grid
+}
+
+fn take<const N: usize>(cursor: &mut &[u8]) -> Option<[u8; N]> {
+    if cursor.len() < N {
+        return None;
+    }
+    let (head, tail) = cursor.split_at(N);
+    *cursor = tail;
+    head.try_into().ok()
+}
+
+fn read_u32(cursor: &mut &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(take(cursor)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AIR, DIRT, GRASS, STONE};
+
+    // A directory of its own for every test, empty when the test starts.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("minecraft_bevy_save_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn test_grid() -> ChunkStorage {
+        let mut grid = [AIR; CHUNK_LEN];
+        for (i, block) in grid.iter_mut().enumerate() {
+            *block = match i % 7 {
+                0 => STONE,
+                1 | 2 => DIRT,
+                3 => GRASS,
+                _ => AIR,
+            };
+        }
+        ChunkStorage::from_grid(&grid)
+    }
+
+    #[test]
+    fn encode_and_decode_grid() {
+        for grid in [test_grid(), ChunkStorage::uniform(STONE)] {
+            assert_eq!(decode_grid(&encode_grid(&grid)), Some(grid));
+        }
+    }
+
+    #[test]
+    fn save_and_load_chunks() {
+        let dir = test_dir("round_trip");
+        let grid = test_grid();
+        // Two chunks in the same region, and one in another region.
+        save_chunk(&dir, [0, 1, 0], &grid).unwrap();
+        save_chunk(&dir, [3, 2, -1], &ChunkStorage::uniform(DIRT)).unwrap();
+        save_chunk(&dir, [REGION_SIZE, 0, 0], &ChunkStorage::uniform(STONE)).unwrap();
+        assert_eq!(load_chunk(&dir, [0, 1, 0]), Some(grid));
+        assert_eq!(
+            load_chunk(&dir, [3, 2, -1]),
+            Some(ChunkStorage::uniform(DIRT))
+        );
+        assert_eq!(
+            load_chunk(&dir, [REGION_SIZE, 0, 0]),
+            Some(ChunkStorage::uniform(STONE))
+        );
+        assert_eq!(load_chunk(&dir, [1, 1, 0]), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_chunks_of_several_regions() {
+        let dir = test_dir("batch");
+        save_chunk(&dir, [1, 0, 1], &ChunkStorage::uniform(GRASS)).unwrap();
+        let chunks = HashMap::from([
+            ([0, 0, 0], encode_grid(&test_grid())),
+            ([-1, 3, 0], encode_grid(&ChunkStorage::uniform(STONE))),
+        ]);
+        assert!(save_chunks(&dir, &chunks).is_empty());
+        assert_eq!(load_chunk(&dir, [0, 0, 0]), Some(test_grid()));
+        assert_eq!(
+            load_chunk(&dir, [-1, 3, 0]),
+            Some(ChunkStorage::uniform(STONE))
+        );
+        // The chunks that were already in the region are kept.
+        assert_eq!(
+            load_chunk(&dir, [1, 0, 1]),
+            Some(ChunkStorage::uniform(GRASS))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupted_grids_are_rejected() {
+        let bytes = encode_grid(&test_grid());
+        // Cut off in the middle of a run.
+        assert_eq!(decode_grid(&bytes[..bytes.len() - 3]), None);
+        assert_eq!(decode_grid(&[]), None);
+        // Extra bytes after the last run.
+        assert_eq!(decode_grid(&[bytes.as_slice(), &[0]].concat()), None);
+        // Runs that don't add up to a chunk.
+        let mut short = bytes.clone();
+        let len_at = 4 + 2;
+        let len = u32::from_le_bytes(short[len_at..len_at + 4].try_into().unwrap());
+        short[len_at..len_at + 4].copy_from_slice(&(len + 1).to_le_bytes());
+        assert_eq!(decode_grid(&short), None);
+        // A run count far larger than a chunk, that would allocate gigabytes.
+        let mut huge = u32::MAX.to_le_bytes().to_vec();
+        huge.extend_from_slice(&STONE.to_le_bytes());
+        huge.extend_from_slice(&(CHUNK_LEN as u32).to_le_bytes());
+        assert_eq!(decode_grid(&huge), None);
+    }
+
+    #[test]
+    fn corrupted_regions_are_rejected() {
+        let dir = test_dir("corrupted");
+        save_chunk(&dir, [0, 0, 0], &test_grid()).unwrap();
+        let path = region_path(&dir, [0, 0]);
+        let bytes = fs::read(&path).unwrap();
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        let mut wrong_version = bytes.clone();
+        wrong_version[4..8].copy_from_slice(&(REGION_VERSION + 1).to_le_bytes());
+        let cut_off = bytes[..bytes.len() - 10].to_vec();
+        for corrupted in [wrong_magic, wrong_version, cut_off] {
+            fs::write(&path, corrupted).unwrap();
+            assert!(read_region(&path).is_err());
+            assert_eq!(load_chunk(&dir, [0, 0, 0]), None);
+            // A corrupted region isn't overwritten, the chunks in it might still be recovered.
+            assert!(save_chunk(&dir, [1, 0, 0], &test_grid()).is_err());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_are_tried_again() {
+        IoTaskPool::get_or_init(TaskPool::new);
+        let dir = test_dir("failed");
+        // A directory where the region file should be makes writing the region fail.
+        let region = region_path(&dir, [0, 0]);
+        fs::create_dir_all(&region).unwrap();
+        let mut pending = PendingSaves::default();
+        pending.queue([0, 0, 0], &test_grid());
+        pending.queue([REGION_SIZE, 0, 0], &ChunkStorage::uniform(STONE));
+        pending.write(&dir);
+        let failed = future::block_on(pending.task.take().unwrap());
+        assert_eq!(failed.len(), 1);
+        pending.finish_write(failed);
+        // Only the chunk of the region that failed is still waiting.
+        assert_eq!(
+            pending.get([0, 0, 0]),
+            Some(encode_grid(&test_grid()).as_slice())
+        );
+        assert_eq!(pending.get([REGION_SIZE, 0, 0]), None);
+        assert_eq!(
+            load_chunk(&dir, [REGION_SIZE, 0, 0]),
+            Some(ChunkStorage::uniform(STONE))
+        );
+
+        // The next write tries again, with the newest blocks of the chunk.
+        pending.queue([0, 0, 0], &ChunkStorage::uniform(DIRT));
+        fs::remove_dir(&region).unwrap();
+        pending.write(&dir);
+        let failed = future::block_on(pending.task.take().unwrap());
+        assert!(failed.is_empty());
+        pending.finish_write(failed);
+        assert!(pending.is_empty());
+        assert_eq!(
+            load_chunk(&dir, [0, 0, 0]),
+            Some(ChunkStorage::uniform(DIRT))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_that_cant_be_flushed_stay_queued() {
+        let dir = test_dir("flush");
+        let region = region_path(&dir, [0, 0]);
+        fs::create_dir_all(&region).unwrap();
+        let mut pending = PendingSaves::default();
+        pending.queue([0, 0, 0], &test_grid());
+        pending.flush(&dir);
+        assert!(!pending.is_empty());
+
+        fs::remove_dir(&region).unwrap();
+        pending.flush(&dir);
+        assert!(pending.is_empty());
+        assert_eq!(load_chunk(&dir, [0, 0, 0]), Some(test_grid()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{chunk::*, Block};
+
+// The blocks of a chunk, compressed with a palette: the chunk keeps the list of the different blocks
+// in it, and for every block its index in that list, packed into as few bits as the list needs. Most
+// chunks have a handful of different blocks, so an index takes 1 to 4 bits instead of the 16 of a
+// block. A chunk of a single block, like the air above the terrain or the stone deep under it, has
+// no indices at all. The blocks are indexed like `one_d_cords`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkStorage {
+    palette: Vec<Block>,
+    // How many bits an index takes: 0 when the palette has a single block, otherwise a power of
+    // two, so an index never spans two words.
+    bits: u32,
+    words: Vec<u64>,
+}
+
+// The fewest bits (a power of two) that can index a palette of `len` blocks.
+fn bits_for(len: usize) -> u32 {
+    match len {
+        0 | 1 => 0,
+        _ => (usize::BITS - (len - 1).leading_zeros()).next_power_of_two(),
+    }
+}
+
+impl ChunkStorage {
+    pub fn uniform(block: Block) -> Self {
+        ChunkStorage {
+            palette: vec![block],
+            bits: 0,
+            words: Vec::new(),
+        }
+    }
+
+    // Compress a whole grid of CHUNK_LEN blocks.
+    pub fn from_grid(grid: &[Block]) -> Self {
+        assert_eq!(grid.len(), CHUNK_LEN, "A chunk has CHUNK_LEN blocks");
+        let mut palette = grid.to_vec();
+        palette.sort_unstable();
+        palette.dedup();
+        palette.shrink_to_fit();
+        if palette.len() == 1 {
+            return ChunkStorage::uniform(palette[0]);
+        }
+        let bits = bits_for(palette.len());
+        let mut storage = ChunkStorage {
+            words: vec![0; CHUNK_LEN.div_ceil((u64::BITS / bits) as usize)],
+            palette,
+            bits,
+        };
+        for (index, block) in grid.iter().enumerate() {
+            let entry = storage
+                .palette
+                .binary_search(block)
+                .expect("Every block of the grid is in the palette");
+            storage.write(index, entry);
+        }
+        storage
+    }
+
+    pub fn get(&self, index: usize) -> Block {
+        self.palette[self.read(index)]
+    }
+
+    // Put `block` at `index`, and give back the block that was there.
+    pub fn set(&mut self, index: usize, block: Block) -> Block {
+        let old = self.get(index);
+        if old == block {
+            return old;
+        }
+        let entry = match self.palette.iter().position(|b| *b == block) {
+            Some(entry) => entry,
+            // The indices don't fit in their bits anymore, so the chunk is compressed again. This
+            // also drops the blocks that aren't in the chunk anymore from the palette.
+            None if self.palette.len() == 1 << self.bits => {
+                let mut grid = self.to_grid();
+                grid[index] = block;
+                *self = ChunkStorage::from_grid(&grid);
+                return old;
+            }
+            None => {
+                self.palette.push(block);
+                self.palette.len() - 1
+            }
+        };
+        self.write(index, entry);
+        old
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Block> + '_ {
+        (0..CHUNK_LEN).map(|index| self.get(index))
+    }
+
+    // The blocks uncompressed, for the code that needs them all at once, like meshing.
+    pub fn to_grid(&self) -> [Block; CHUNK_LEN] {
+        let mut grid = [self.palette[0]; CHUNK_LEN];
+        if self.bits > 0 {
+            for (index, block) in grid.iter_mut().enumerate() {
+                *block = self.get(index);
+            }
+        }
+        grid
+    }
+
+    // The block the whole chunk is made of, if it's made of one.
+    pub fn as_uniform(&self) -> Option<Block> {
+        (self.bits == 0).then_some(self.palette[0])
+    }
+
+    pub fn palette(&self) -> &[Block] {
+        &self.palette
+    }
+
+    // How many bytes the chunk's blocks take, on the stack and on the heap.
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+            + self.palette.capacity() * size_of::<Block>()
+            + self.words.capacity() * size_of::<u64>()
+    }
+
+    fn read(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = (u64::BITS / self.bits) as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        ((self.words[index / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn write(&mut self, index: usize, entry: usize) {
+        let per_word = (u64::BITS / self.bits) as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        let word = &mut self.words[index / per_word];
+        *word = (*word & !(((1 << self.bits) - 1) << shift)) | ((entry as u64) << shift);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{WorldSettings, AIR, DIRT, GLASS, SAND, STONE, WATER};
+    use std::mem::size_of;
+
+    // Check every block of `storage` against the plain grid it should hold.
+    fn assert_holds(storage: &ChunkStorage, grid: &[Block]) {
+        assert!(storage.iter().eq(grid.iter().copied()));
+        assert_eq!(storage.to_grid().as_slice(), grid);
+    }
+
+    #[test]
+    fn index_bits_are_powers_of_two() {
+        for (len, bits) in [
+            (1, 0),
+            (2, 1),
+            (3, 2),
+            (4, 2),
+            (5, 4),
+            (16, 4),
+            (17, 8),
+            (256, 8),
+            (257, 16),
+        ] {
+            assert_eq!(bits_for(len), bits, "{} blocks", len);
+        }
+    }
+
+    #[test]
+    fn round_trip_through_every_width() {
+        // Grids with 1, 2, 3, 5, 17 and 257 different blocks, which take 0 to 16 bits an index.
+        for kinds in [1, 2, 3, 5, 17, 257] {
+            let grid: Vec<Block> = (0..CHUNK_LEN)
+                .map(|i| ((i * 7 + i / 5) % kinds) as Block * 3)
+                .collect();
+            let storage = ChunkStorage::from_grid(&grid);
+            assert_eq!(storage.bits, bits_for(kinds));
+            assert_eq!(storage.palette().len(), kinds);
+            assert_eq!(storage.as_uniform().is_some(), kinds == 1);
+            assert_holds(&storage, &grid);
+            assert_eq!(ChunkStorage::from_grid(&storage.to_grid()), storage);
+        }
+    }
+
+    #[test]
+    fn uniform_chunks_have_no_indices() {
+        let storage = ChunkStorage::from_grid(&[STONE; CHUNK_LEN]);
+        assert_eq!(storage, ChunkStorage::uniform(STONE));
+        assert_eq!(storage.as_uniform(), Some(STONE));
+        assert!(storage.words.is_empty());
+        assert_holds(&storage, &[STONE; CHUNK_LEN]);
+
+        // Setting the block that is already there changes nothing.
+        let mut same = storage.clone();
+        assert_eq!(same.set(100, STONE), STONE);
+        assert_eq!(same, storage);
+    }
+
+    #[test]
+    fn setting_blocks_grows_the_palette() {
+        let mut storage = ChunkStorage::uniform(AIR);
+        let mut grid = vec![AIR; CHUNK_LEN];
+        // Every new block crosses into the next width at 2, 3, 5, 17 and 257 blocks.
+        for block in 1..300 {
+            let index = (block as usize * 13) % CHUNK_LEN;
+            assert_eq!(storage.set(index, block), grid[index]);
+            grid[index] = block;
+            let kinds = block as usize + 1;
+            assert_eq!(storage.bits, bits_for(kinds), "{} blocks", kinds);
+            assert_eq!(storage.palette().len(), kinds);
+            assert_holds(&storage, &grid);
+        }
+    }
+
+    #[test]
+    fn recompressing_drops_the_blocks_that_are_gone() {
+        let mut grid = vec![AIR; CHUNK_LEN];
+        grid[0] = DIRT;
+        grid[1] = STONE;
+        grid[2] = SAND;
+        let mut storage = ChunkStorage::from_grid(&grid);
+        assert_eq!((storage.bits, storage.palette().len()), (2, 4));
+
+        // The dirt is gone, but stays in the palette until it's full.
+        storage.set(0, AIR);
+        grid[0] = AIR;
+        assert!(storage.palette().contains(&DIRT));
+        // A fifth block doesn't fit in 2 bits, the chunk is compressed again without the dirt, so
+        // the indices still fit.
+        storage.set(3, GLASS);
+        grid[3] = GLASS;
+        assert_eq!(storage.bits, 2);
+        assert!(!storage.palette().contains(&DIRT));
+        assert_holds(&storage, &grid);
+
+        // Once all of the other blocks are gone, growing the full palette leaves only air and the
+        // new block, which take a single bit.
+        for index in 1..4 {
+            storage.set(index, AIR);
+        }
+        assert_eq!(storage.palette().len(), 4);
+        storage.set(8, WATER);
+        grid = vec![AIR; CHUNK_LEN];
+        grid[8] = WATER;
+        assert_eq!(storage.bits, 1);
+        assert_eq!(storage.palette(), &[AIR, WATER]);
+        assert_holds(&storage, &grid);
+    }
+
+    #[test]
+    fn generated_chunks_take_less_memory() {
+        let generator = TerrainGenerator::new(5, WorldSettings::default().terrain());
+        let chunks: Vec<ChunkStorage> = (0..WORLD_HEIGHT_CHUNKS)
+            .map(|y| generate_chunk([0, y, 0], &generator))
+            .collect();
+        // The top of the world is air.
+        assert_eq!(chunks.last().unwrap().as_uniform(), Some(AIR));
+        for chunk in chunks.iter() {
+            assert!(chunk.memory_usage() < size_of::<[Block; CHUNK_LEN]>() / 2);
+            assert_holds(chunk, &chunk.to_grid());
+            assert_eq!(
+                ChunkStorage::from_grid(&chunk.to_grid()).to_grid(),
+                chunk.to_grid()
+            );
+        }
+    }
+}
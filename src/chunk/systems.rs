@@ -1,45 +1,164 @@
-use super::ToCull;
+use super::{
+    NewChunkMesh, PendingSaves, RenderChunks, TerrainGenerator, ToCull, ToRemesh, ToSave, WorldSave,
+};
 use crate::{
-    block_reg::BlockRegistry, chunk_queue::*, iter_faces_of_chunk, update_mesh, Arc, Chunk,
-    ChunkCloseToPlayer, CurrentChunk, Face, Face::*, ToUpdate, VoxelRegistry, LENGTH,
-    RENDER_DISTANCE, WIDTH,
+    adjacent_cords, bake_light, block_reg::BlockRegistry, chunk_queue::*, in_render_distance,
+    is_chunk_in_world, iter_faces_of_chunk, light_of_block, sky_from_above, update_mesh, Arc,
+    Chunk, ChunkCloseToPlayer, CurrentChunk, Face, Face::*, GreedyBlockMaterial, GreedyMaterial,
+    GreedyMeshed, MeshingMode, ToUpdate, VoxelRegistry, CHUNK_DIMS, HEIGHT, LENGTH,
+    MAX_GREEDY_REMESHES, WIDTH, WORLD_HEIGHT_CHUNKS,
 };
+use crate::{BlockMaterial, BlockRegistrySource, WorldSettings};
+use bevy::app::AppExit;
 use bevy::prelude::*;
 use bevy_meshem::prelude::VoxelChange;
+use futures_lite::future;
 
-// Each frame, dequeue all the pending chunks to despawn / spawn onto
-// the thread pool, after they are done, they will be picked up by `handle_tasks`.
+// Each frame, dequeue the pending chunks to despawn / spawn onto
+// the thread pool, after they are done, they will be picked up by `mesh_generated_chunks` and
+// `handle_tasks`.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn frame_chunk_update(
     mut cq: ResMut<ChunkQueue>,
     cm: ResMut<ChunkMap>,
     breg: Res<BlockRegistry>,
+    save: Res<WorldSave>,
+    pending_saves: Res<PendingSaves>,
+    world_settings: Res<WorldSettings>,
+    render: Option<Res<RenderChunks>>,
+    budget: Res<ChunkLoadBudget>,
+    viewers: Query<(&CurrentChunk, &GlobalTransform)>,
     commands: Commands,
 ) {
-    cq.dequeue_all(
+    if cq.is_empty() {
+        return;
+    }
+    let viewers: Vec<ChunkViewer> = viewers
+        .iter()
+        .map(|(current_chunk, tran)| ChunkViewer {
+            chunk: current_chunk.0,
+            forward: tran.forward(),
+        })
+        .collect();
+    cq.dequeue(
         commands,
         Arc::new(breg.into_inner().clone()),
         cm.into_inner(),
+        save.into_inner(),
+        pending_saves.into_inner(),
+        &world_settings,
+        render.map(|_| world_settings.meshing),
+        &viewers,
+        budget.tasks_per_frame,
     );
 }
 
-pub(crate) fn spawn_and_despawn_chunks(
-    q2: Query<(&CurrentChunk, &Transform), Changed<CurrentChunk>>,
-    mut chunk_queue: ResMut<ChunkQueue>,
+// Once the blocks of a chunk are loaded or generated, start meshing it. The tasks of the chunks
+// that were unloaded in the meantime were despawned with them.
+pub(crate) fn mesh_generated_chunks(
+    mut tasks: Query<(Entity, &mut GenerateChunk)>,
+    chunks: Query<&Chunk>,
+    mut chunk_map: ResMut<ChunkMap>,
+    breg: Res<BlockRegistry>,
+    world_settings: Res<WorldSettings>,
+    render: Option<Res<RenderChunks>>,
+    mut commands: Commands,
+) {
+    let generator = TerrainGenerator::new(world_settings.seed, world_settings.terrain());
+    let meshing = render.map(|_| world_settings.meshing);
+    let mut shared_breg = None;
+    for (ent, mut generate) in tasks.iter_mut() {
+        let Some(grid) = future::block_on(future::poll_once(&mut generate.task)) else {
+            continue;
+        };
+        let cords = generate.cords;
+        if !chunk_map.is_current(cords, ent, ChunkState::Generating) {
+            continue;
+        }
+        let breg = shared_breg.get_or_insert_with(|| Arc::new(breg.clone()));
+        let task = mesh_task(
+            cords,
+            grid,
+            generator,
+            Arc::clone(breg),
+            meshing,
+            sky_from_above(&chunks, &chunk_map, cords),
+        );
+        commands.entity(ent).remove::<GenerateChunk>().insert(task);
+        chunk_map.set_state(cords, ChunkState::Meshing);
+    }
+}
+
+// Queue the chunks that were edited to be saved every few seconds, or right before they are
+// unloaded, and write them to their region files in the background.
+pub(crate) fn save_edited_chunks(
+    query: Query<(Entity, &Chunk), With<ToSave>>,
     chunk_map: Res<ChunkMap>,
+    mut pending: ResMut<PendingSaves>,
+    save: Res<WorldSave>,
+    time: Res<Time>,
+    mut commands: Commands,
 ) {
-    for (j, _) in q2.iter() {
-        let cords = j.0;
-        for chunk in chunk_map.iter_keys() {
-            if (chunk[0] - cords[0]).abs() > RENDER_DISTANCE {
-                chunk_queue.queue_despawn(*chunk);
-            }
-            if (chunk[1] - cords[1]).abs() > RENDER_DISTANCE {
-                chunk_queue.queue_despawn(*chunk);
-            }
+    let due = pending.timer.tick(time.delta()).just_finished();
+    for (ent, chunk) in query.iter() {
+        if due || chunk_map.state(chunk.cords) == Some(ChunkState::Unloading) {
+            pending.queue(chunk.cords, &chunk.grid);
+            commands.entity(ent).remove::<ToSave>();
         }
-        for u in -RENDER_DISTANCE..=RENDER_DISTANCE {
-            for v in -RENDER_DISTANCE..=RENDER_DISTANCE {
-                chunk_queue.queue_spawn([cords[0] + u, cords[1] + v]);
+    }
+    pending.write(&save.dir);
+}
+
+// Write everything that wasn't saved yet before the game exits.
+pub(crate) fn save_before_exit(
+    mut exit: EventReader<AppExit>,
+    query: Query<&Chunk, With<ToSave>>,
+    mut pending: ResMut<PendingSaves>,
+    save: Res<WorldSave>,
+) {
+    if exit.read().count() == 0 {
+        return;
+    }
+    for chunk in query.iter() {
+        pending.queue(chunk.cords, &chunk.grid);
+    }
+    pending.flush(&save.dir);
+}
+
+// Chunks are loaded around every entity with a `CurrentChunk` (the player, or the players of a
+// server), and unloaded once they are out of the render distance of all of them.
+pub(crate) fn spawn_and_despawn_chunks(
+    viewers: Query<Ref<CurrentChunk>>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    mut chunk_map: ResMut<ChunkMap>,
+    world_settings: Res<WorldSettings>,
+    mut removed_viewers: RemovedComponents<CurrentChunk>,
+) {
+    // The chunks around a viewer that went away (a player that left) are unloaded too.
+    if !viewers.iter().any(|viewer| viewer.is_changed()) && removed_viewers.read().count() == 0 {
+        return;
+    }
+    let render_distance = world_settings.render_distance;
+    let far_chunks: Vec<[i32; 3]> = chunk_map
+        .iter_keys()
+        .filter(|chunk| {
+            !viewers
+                .iter()
+                .any(|viewer| in_render_distance(**chunk, viewer.0, render_distance))
+        })
+        .copied()
+        .collect();
+    for chunk in far_chunks {
+        chunk_queue.queue_despawn(&mut chunk_map, chunk);
+    }
+    for viewer in viewers.iter().filter(|viewer| viewer.is_changed()) {
+        let cords = viewer.0;
+        // Every column in the render distance is loaded from the bottom to the top of the world.
+        for u in -render_distance..=render_distance {
+            for v in -render_distance..=render_distance {
+                for h in 0..WORLD_HEIGHT_CHUNKS {
+                    chunk_queue.queue_spawn(&mut chunk_map, [cords[0] + u, h, cords[2] + v]);
+                }
             }
         }
     }
@@ -48,31 +167,64 @@ pub(crate) fn spawn_and_despawn_chunks(
 // We need to keep track of the chunks that are close to the player,
 // so we dont need to iterate over all the chunks when breaking / placing blocks.
 pub(crate) fn update_closby_chunks(
-    current_chunk: Query<&CurrentChunk, Changed<CurrentChunk>>,
+    viewers: Query<Ref<CurrentChunk>>,
     chunk_map: Res<ChunkMap>,
     mut commands: Commands,
     old_close_chunks: Query<Entity, With<ChunkCloseToPlayer>>,
+    new_chunks: Query<(), Added<Chunk>>,
 ) {
-    if let Ok(cords) = current_chunk.get_single() {
-        let cords = cords.0;
-        for chunk in old_close_chunks.iter() {
-            commands.entity(chunk).remove::<ChunkCloseToPlayer>();
-        }
+    // Chunks that were still loading when a viewer moved are marked once they are spawned.
+    if !viewers.iter().any(|viewer| viewer.is_changed()) && new_chunks.is_empty() {
+        return;
+    }
+    for chunk in old_close_chunks.iter() {
+        commands.entity(chunk).remove::<ChunkCloseToPlayer>();
+    }
 
+    for viewer in viewers.iter() {
+        let cords = viewer.0;
         for i in -1..=1 {
             for j in -1..=1 {
-                commands
-                    .entity(
-                        chunk_map
-                            .get_ent([cords[0] + i, cords[1] + j])
-                            .expect("Chunk that was supposed to be loaded was not."),
-                    )
-                    .insert(ChunkCloseToPlayer);
+                for k in -1..=1 {
+                    let close_chunk = [cords[0] + i, cords[1] + k, cords[2] + j];
+                    // There are no chunks above the top or below the bottom of the world.
+                    if !is_chunk_in_world(close_chunk) {
+                        continue;
+                    }
+                    // Chunks sent by a server might not have arrived yet.
+                    let Some(ent) = chunk_map.get_ent(close_chunk) else {
+                        continue;
+                    };
+                    commands.entity(ent).insert(ChunkCloseToPlayer);
+                }
             }
         }
     }
 }
 
+// Give the chunks that were just spawned their mesh, so they are rendered.
+pub(crate) fn insert_chunk_meshes(
+    mut new_meshes: Query<(Entity, &mut NewChunkMesh, Has<GreedyMeshed>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mat: Res<BlockMaterial>,
+    greedy_mat: Res<GreedyBlockMaterial>,
+    mut commands: Commands,
+) {
+    for (ent, mut new_mesh, greedy) in new_meshes.iter_mut() {
+        let mut entity = commands.entity(ent);
+        if let Some(mesh) = new_mesh.0.take() {
+            entity.insert(meshes.add(mesh));
+            // Greedy meshes need the material that repeats the textures over their quads.
+            if greedy {
+                entity.insert(greedy_mat.0.clone());
+            } else {
+                entity.insert(mat.0.clone());
+            }
+        }
+        entity.remove::<NewChunkMesh>();
+    }
+}
+
 // Update the mesh of the chunks every frame.
 pub(crate) fn update_mesh_frame(
     mut query: Query<(Entity, &Handle<Mesh>, &mut Chunk), With<ToUpdate>>,
@@ -82,10 +234,15 @@ pub(crate) fn update_mesh_frame(
 ) {
     let breg = Arc::new(breg.into_inner().clone());
     for (ent, mesh_handle, mut chunk) in query.iter_mut() {
+        // Greedy meshes can't be updated, they are meshed again when their light changes.
+        let Some(meta_data) = chunk.meta_data.as_mut() else {
+            commands.entity(ent).remove::<ToUpdate>();
+            continue;
+        };
         let mesh_ref_mut = meshes
             .get_mut(mesh_handle)
             .expect("Can't find chunk mesh in internal assets");
-        update_mesh(mesh_ref_mut, &mut chunk.meta_data, &*breg.clone());
+        update_mesh(mesh_ref_mut, meta_data, &*breg.clone());
         if let Some(aabb) = mesh_ref_mut.compute_aabb() {
             if let Some(mut comm) = commands.get_entity(ent) {
                 comm.insert(aabb).remove::<ToUpdate>();
@@ -96,62 +253,126 @@ pub(crate) fn update_mesh_frame(
     }
 }
 
+// Mesh the chunks whose light changed again from scratch, with the new light baked into them.
+// Updating the mesh isn't enough, because the faces that didn't change still have the old light.
+#[allow(clippy::type_complexity)]
+pub(crate) fn remesh_chunks(
+    to_remesh: Query<(Entity, Option<&GreedyMeshed>), With<ToRemesh>>,
+    mut chunks: ParamSet<(Query<&Chunk>, Query<(&mut Chunk, &Handle<Mesh>)>)>,
+    chunk_map: Res<ChunkMap>,
+    breg: Res<BlockRegistry>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mat: Res<BlockMaterial>,
+    mut commands: Commands,
+) {
+    let breg = breg.into_inner();
+    let mut remeshed = vec![];
+    {
+        let chunks = chunks.p0();
+        for (ent, greedy) in to_remesh.iter() {
+            let Ok(chunk) = chunks.get(ent) else {
+                continue;
+            };
+            // A chunk that is meshed again often is edited often, it's cheaper to mesh it with
+            // culling, whose mesh can be updated in place.
+            let remeshes = greedy.map(|greedy| greedy.remeshes);
+            let meshing = match remeshes {
+                Some(remeshes) if remeshes < MAX_GREEDY_REMESHES => MeshingMode::Greedy,
+                _ => MeshingMode::Culling,
+            };
+            let (mut mesh, meta_data) =
+                mesh_chunk_with(meshing, chunk.cords, &chunk.grid.to_grid(), breg);
+            bake_light(&mut mesh, chunk.cords, |pos| {
+                light_of_block(&chunks, &chunk_map, breg, pos)
+            });
+            remeshed.push((ent, mesh, meta_data, remeshes));
+        }
+    }
+    for (ent, mesh, meta_data, remeshes) in remeshed {
+        let mut chunks = chunks.p1();
+        let Ok((mut chunk, mesh_handle)) = chunks.get_mut(ent) else {
+            continue;
+        };
+        if let Some(old_mesh) = meshes.get_mut(mesh_handle) {
+            *old_mesh = mesh;
+        }
+        chunk.meta_data = meta_data;
+        let mut entity = commands.entity(ent);
+        entity.remove::<(ToRemesh, ToUpdate)>();
+        match remeshes {
+            Some(remeshes) if chunk.meta_data.is_none() => {
+                entity.insert(GreedyMeshed {
+                    remeshes: remeshes + 1,
+                });
+            }
+            // The chunk isn't greedy anymore, its mesh needs the material of the culled meshes.
+            Some(_) => {
+                entity
+                    .remove::<(GreedyMeshed, Handle<GreedyMaterial>)>()
+                    .insert(mat.0.clone());
+            }
+            None => {}
+        }
+        // The new mesh has all the faces on the borders of the chunk, they have to be culled again.
+        if chunk.meta_data.is_some() {
+            entity.insert(ToCull::new(chunk.cords));
+        }
+    }
+}
+
 //
 pub(crate) fn cull_sides_of_mesh(
     chunks_to_cull: Query<(Entity, &ToCull), With<Chunk>>,
     mut chunks_query: Query<&mut Chunk>,
     breg: Res<BlockRegistry>,
     mut commands: Commands,
-    chunk_map: Res<ChunkMap>,
+    mut chunk_map: ResMut<ChunkMap>,
 ) {
     let breg = breg.into_inner();
     for (ent, to_cull) in &chunks_to_cull {
-        if ent == Entity::PLACEHOLDER {
-            continue;
-        }
-        let chunk = chunks_query.get(ent).unwrap();
-        let cords = chunk.cords;
-        let grid = chunk.grid;
-        let dims = chunk.meta_data.dims;
+        let cords = chunks_query.get(ent).unwrap().cords;
+        let dims = CHUNK_DIMS;
         let mut culled = [true; 6];
         for i in 0..6 {
             if to_cull.culled[i] {
                 continue;
             }
             let face = Face::from(i);
-            let adj_chunk_cords = match face {
-                Right => [cords[0] + 1, cords[1]],
-                Left => [cords[0] - 1, cords[1]],
-                Back => [cords[0], cords[1] + 1],
-                Forward => [cords[0], cords[1] - 1],
-                Top | Bottom => panic!("Shouldn't happen"),
-            };
+            let adj_chunk_cords = adjacent_cords(cords, face);
             if let Some(adj_chunk) = chunk_map.get_ent(adj_chunk_cords) {
-                if adj_chunk == Entity::PLACEHOLDER {
-                    continue;
-                }
-                let adj_chunk_grid = { chunks_query.get(adj_chunk).unwrap().grid };
-                for svox in iter_faces_of_chunk(dims, face) {
-                    let adj_voxel_ind = match face {
-                        Right => if svox < WIDTH {1} else {(svox - WIDTH) + 1},
-                        Left => (svox + WIDTH) - 1,
-                        Back => svox - (WIDTH * (LENGTH - 1)),
-                        Forward => svox + (WIDTH * (LENGTH - 1)),
-                        _ => panic!("Shouldn't happen"),
-                    };
+                // The faces that are covered are found first, only reading the blocks of both
+                // chunks, and then culled.
+                let mut covered = vec![];
+                {
+                    let chunk = chunks_query.get(ent).unwrap();
+                    let adj_chunk = chunks_query.get(adj_chunk).unwrap();
+                    for svox in iter_faces_of_chunk(dims, face) {
+                        let adj_voxel_ind = match face {
+                            Right => (svox + 1) - WIDTH,
+                            Left => (svox + WIDTH) - 1,
+                            Back => svox - (WIDTH * (LENGTH - 1)),
+                            Forward => svox + (WIDTH * (LENGTH - 1)),
+                            Top => svox - (WIDTH * LENGTH * (HEIGHT - 1)),
+                            Bottom => svox + (WIDTH * LENGTH * (HEIGHT - 1)),
+                        };
 
-                    let adj_voxel = &adj_chunk_grid[adj_voxel_ind];
-                    if breg.is_covering(adj_voxel, face.opposite()) {
-                        let mut chunk = chunks_query.get_mut(ent).unwrap();
-                        chunk
-                            .meta_data
-                            .log(VoxelChange::CullFaces, svox, grid[svox], {
-                                let mut r = [None; 6];
-                                r[face as usize] = Some(*adj_voxel);
-                                r
-                            });
+                        let adj_voxel = adj_chunk.grid.get(adj_voxel_ind);
+                        if breg.is_covering(&adj_voxel, face.opposite()) {
+                            covered.push((svox, chunk.grid.get(svox), adj_voxel));
+                        }
                     }
                 }
+                let mut chunk = chunks_query.get_mut(ent).unwrap();
+                let Some(meta_data) = chunk.meta_data.as_mut() else {
+                    continue;
+                };
+                for (svox, block, adj_voxel) in covered {
+                    meta_data.log(VoxelChange::CullFaces, svox, block, {
+                        let mut r = [None; 6];
+                        r[face as usize] = Some(adj_voxel);
+                        r
+                    });
+                }
             } else {
                 culled[face as usize] = false;
                 continue;
@@ -159,6 +380,7 @@ pub(crate) fn cull_sides_of_mesh(
         }
         if culled == [true; 6] {
             commands.entity(ent).insert(ToUpdate).remove::<ToCull>();
+            chunk_map.set_state(cords, ChunkState::Ready);
         } else {
             commands
                 .entity(ent)
@@ -167,3 +389,30 @@ pub(crate) fn cull_sides_of_mesh(
         }
     }
 }
+
+// When the block definitions file changes, rebuild the block registry and remesh all the loaded
+// chunks with it. If the new definitions are invalid, the old registry is kept.
+pub(crate) fn hot_reload_block_registry(
+    mut source: ResMut<BlockRegistrySource>,
+    mut breg: ResMut<BlockRegistry>,
+    chunks: Query<Entity, With<Chunk>>,
+    mut commands: Commands,
+) {
+    match source.reload_if_modified() {
+        None => return,
+        Some(Err(e)) => {
+            warn!(
+                "Couldn't reload the block registry, keeping the old one: {}",
+                e
+            );
+            return;
+        }
+        Some(Ok(new_breg)) => {
+            *breg = new_breg;
+            info!("\nInternal Log:\nBlock registry has been reloaded");
+        }
+    }
+    for ent in chunks.iter() {
+        commands.entity(ent).insert(ToRemesh);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::*;
+use core::f32::consts::PI;
+
+pub const CROSSHAIR_SIZE: f32 = 22.0;
+
+#[derive(Resource, Clone)]
+pub struct BlockMaterial(pub Handle<StandardMaterial>);
+
+// Everything the player sees and does: the camera and the controls, the chunk meshes, the
+// inventory and the sky. It needs the `DefaultPlugins`, and runs on top of the `SimulationPlugin`.
+pub struct ClientPlugin;
+
+impl Plugin for ClientPlugin {
+    fn build(&self, app: &mut App) {
+        // Plugins
+        app.add_plugins((
+            // AtmospherePlugin,
+            PlayerPlugin,
+            ChunkMeshPlugin,
+            LodPlugin,
+            InventoryPlugin,
+            SelectionPlugin,
+            GameModePlugin,
+            ConsoleUiPlugin,
+            EditJournalPlugin,
+        ));
+
+        // Resources
+        app.insert_resource(ClearColor(Color::rgb(0.70, 0.95, 1.0)))
+            .init_resource::<TargetedBlock>()
+            .init_resource::<BreakProgress>()
+            .init_resource::<TimeOfDay>()
+            .insert_resource(AmbientLight {
+                brightness: 1.25,
+                color: Color::ANTIQUE_WHITE,
+            })
+            .insert_resource(CycleTimer(Timer::new(
+                bevy::utils::Duration::from_millis(50),
+                TimerMode::Repeating,
+            )));
+        // .insert_resource(AtmosphereModel::default());
+
+        // Systems
+        app.add_systems(PostStartup, setup)
+            .add_systems(OnEnter(InitialChunkLoadState::Complete), setup_light)
+            .add_systems(Update, (add_break_detector /* debug_cage */,))
+            .add_systems(Update, time_command)
+            .add_systems(PostUpdate, daylight_cycle);
+
+        // Commands
+        app.register_command(CommandSpec {
+            name: "time",
+            params: vec![
+                Param::optional("set", ArgKind::Word),
+                Param::optional("hour", ArgKind::Number),
+            ],
+            help: "Show the time of day, or set it",
+        });
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut greedy_materials: ResMut<Assets<GreedyMaterial>>,
+    asset_server: Res<AssetServer>,
+    breg: Res<BlockRegistry>,
+    mut camera_query: Query<&mut Projection>,
+) {
+    let texture_handle: Handle<Image> = asset_server.load("blocks.png");
+    let block_material = StandardMaterial {
+        base_color_texture: Some(texture_handle),
+        reflectance: 0.0,
+        alpha_mode: AlphaMode::Mask(0.3),
+        perceptual_roughness: 0.75,
+        ..default()
+    };
+    let greedy_mat = greedy_materials.add(GreedyMaterial {
+        base: block_material.clone(),
+        extension: TiledAtlas {
+            tiling: AtlasTiling::new(&breg),
+        },
+    });
+    let mat = materials.add(block_material);
+    commands.insert_resource(BlockMaterial(mat));
+    commands.insert_resource(GreedyBlockMaterial(greedy_mat));
+    let mut projection = camera_query.get_single_mut().unwrap();
+    if let Projection::Perspective(ref mut perspective) = *projection {
+        perspective.fov = PI / 3.5;
+    }
+}
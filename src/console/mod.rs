@@ -0,0 +1,395 @@
+pub mod ui;
+use crate::*;
+use bevy::utils::{HashMap, Instant};
+pub use ui::*;
+
+// How many lines the console remembers.
+const CONSOLE_HISTORY: usize = 100;
+
+// What an argument of a command has to be.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    Int,
+    Number,
+    Word,
+    // A block, by its name in the registry or by its id, or air.
+    Block,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Param {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    // Optional parameters can only come after all the required ones.
+    pub optional: bool,
+}
+
+impl Param {
+    pub const fn required(name: &'static str, kind: ArgKind) -> Self {
+        Param {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind) -> Self {
+        Param {
+            name,
+            kind,
+            optional: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CommandSpec {
+    // The command is run by typing `/name`.
+    pub name: &'static str,
+    pub params: Vec<Param>,
+    pub help: &'static str,
+}
+
+impl CommandSpec {
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for param in self.params.iter() {
+            if param.optional {
+                usage += &format!(" [{}]", param.name);
+            } else {
+                usage += &format!(" <{}>", param.name);
+            }
+        }
+        usage
+    }
+}
+
+// The commands the plugins registered, by name.
+#[derive(Resource, Default)]
+pub struct CommandRegistry {
+    commands: HashMap<&'static str, CommandSpec>,
+}
+
+impl CommandRegistry {
+    pub fn get(&self, name: &str) -> Option<&CommandSpec> {
+        self.commands.get(name)
+    }
+
+    // The commands sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = &CommandSpec> {
+        let mut commands: Vec<&CommandSpec> = self.commands.values().collect();
+        commands.sort_by_key(|spec| spec.name);
+        commands.into_iter()
+    }
+
+    // Parse a line like `/give stone 10` into the command it runs.
+    pub fn parse(
+        &self,
+        line: &str,
+        breg: &BlockRegistry,
+        source: CommandSource,
+    ) -> Result<ConsoleCommand, String> {
+        let mut words = line.trim().trim_start_matches('/').split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let spec = self
+            .get(name)
+            .ok_or_else(|| format!("Unknown command /{}, try /help", name))?;
+        let words: Vec<&str> = words.collect();
+        let required = spec.params.iter().filter(|param| !param.optional).count();
+        if words.len() < required || words.len() > spec.params.len() {
+            return Err(format!("Usage: {}", spec.usage()));
+        }
+        let mut args = Vec::with_capacity(words.len());
+        for (word, param) in words.iter().zip(spec.params.iter()) {
+            let arg = match param.kind {
+                ArgKind::Int => word.parse().ok().map(ArgValue::Int),
+                // "inf", "NaN" and numbers too big for an f32 parse, but aren't numbers a command
+                // can do anything with.
+                ArgKind::Number => word
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|v| v.is_finite())
+                    .map(ArgValue::Number),
+                ArgKind::Word => Some(ArgValue::Word(word.to_string())),
+                ArgKind::Block => (*word == "air")
+                    .then_some(AIR)
+                    .or_else(|| breg.block_by_name(word))
+                    .or_else(|| word.parse().ok().filter(|id| breg.get(*id).is_some()))
+                    .map(ArgValue::Block),
+            };
+            args.push(arg.ok_or_else(|| {
+                format!(
+                    "Invalid value {} for <{}>\nUsage: {}",
+                    word,
+                    param.name,
+                    spec.usage()
+                )
+            })?);
+        }
+        Ok(ConsoleCommand {
+            name: spec.name,
+            args,
+            source,
+        })
+    }
+}
+
+pub trait RegisterCommand {
+    fn register_command(&mut self, spec: CommandSpec) -> &mut Self;
+}
+
+impl RegisterCommand for App {
+    fn register_command(&mut self, spec: CommandSpec) -> &mut Self {
+        let name = spec.name;
+        let mut registry = self
+            .world
+            .get_resource_or_insert_with(CommandRegistry::default);
+        assert!(
+            registry.commands.insert(name, spec).is_none(),
+            "Command /{} was registered twice",
+            name
+        );
+        self
+    }
+}
+
+// Where a line was typed: in the console of the player, or in the terminal of a headless server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandSource {
+    Player,
+    Server,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArgValue {
+    Int(i32),
+    Number(f32),
+    Word(String),
+    Block(Block),
+}
+
+// A line that was typed into a console. Lines that start with `/` are commands, the rest are chat.
+#[derive(Event, Clone, Debug)]
+pub struct ConsoleInput {
+    pub line: String,
+    pub source: CommandSource,
+}
+
+// A command that was parsed, the arguments match the parameters it was registered with. The
+// systems of the plugin that registered the command run it.
+#[derive(Event, Clone, Debug)]
+pub struct ConsoleCommand {
+    pub name: &'static str,
+    pub args: Vec<ArgValue>,
+    pub source: CommandSource,
+}
+
+impl ConsoleCommand {
+    // The arguments by their index, None if an optional argument wasn't given.
+    pub fn int(&self, i: usize) -> Option<i32> {
+        match self.args.get(i)? {
+            ArgValue::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self, i: usize) -> Option<f32> {
+        match self.args.get(i)? {
+            ArgValue::Number(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn word(&self, i: usize) -> Option<&str> {
+        match self.args.get(i)? {
+            ArgValue::Word(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn block(&self, i: usize) -> Option<Block> {
+        match self.args.get(i)? {
+            ArgValue::Block(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+// A chat message, shown to everyone playing in the world.
+#[derive(Event, Clone, Debug)]
+pub struct ChatMessage {
+    pub sender: String,
+    pub text: String,
+}
+
+// The name the player chats with.
+#[derive(Resource)]
+pub struct PlayerName(pub String);
+
+impl Default for PlayerName {
+    fn default() -> Self {
+        PlayerName(String::from("Player"))
+    }
+}
+
+// The lines shown in the console, the answers of the commands and the chat.
+#[derive(Resource, Default)]
+pub struct ConsoleLog {
+    lines: Vec<(Instant, String)>,
+}
+
+impl ConsoleLog {
+    pub fn print(&mut self, line: impl Into<String>) {
+        let line = line.into();
+        info!("\nIn-Game Log:\n{}", line);
+        self.lines.push((Instant::now(), line));
+        if self.lines.len() > CONSOLE_HISTORY {
+            self.lines.remove(0);
+        }
+    }
+
+    // The lines from the oldest to the newest, with when they were printed.
+    pub fn lines(&self) -> impl Iterator<Item = &(Instant, String)> {
+        self.lines.iter()
+    }
+}
+
+// Parses the lines typed into the console, and runs the commands every app has.
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(
+            Update,
+            (
+                run_console_input,
+                (
+                    help_command,
+                    seed_command,
+                    print_chat.run_if(world_is_local),
+                ),
+            )
+                .chain(),
+        );
+
+        // Resources
+        app.init_resource::<CommandRegistry>()
+            .init_resource::<ConsoleLog>()
+            .init_resource::<PlayerName>();
+
+        // Events
+        app.add_event::<ConsoleInput>()
+            .add_event::<ConsoleCommand>()
+            .add_event::<ChatMessage>();
+
+        // Commands
+        app.register_command(CommandSpec {
+            name: "help",
+            params: vec![],
+            help: "List the commands",
+        })
+        .register_command(CommandSpec {
+            name: "seed",
+            params: vec![],
+            help: "Show the seed of the world",
+        });
+    }
+}
+
+fn run_console_input(
+    mut input: EventReader<ConsoleInput>,
+    registry: Res<CommandRegistry>,
+    breg: Res<BlockRegistry>,
+    name: Res<PlayerName>,
+    mut log: ResMut<ConsoleLog>,
+    mut commands: EventWriter<ConsoleCommand>,
+    mut chat: EventWriter<ChatMessage>,
+) {
+    for input in input.read() {
+        let line = input.line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with('/') {
+            chat.send(ChatMessage {
+                sender: match input.source {
+                    CommandSource::Player => name.0.clone(),
+                    CommandSource::Server => String::from("Server"),
+                },
+                text: line.to_string(),
+            });
+            continue;
+        }
+        match registry.parse(line, &breg, input.source) {
+            Ok(command) => commands.send(command),
+            Err(e) => log.print(e),
+        }
+    }
+}
+
+// When the world is played locally, nobody else has to see the chat.
+fn print_chat(mut chat: EventReader<ChatMessage>, mut log: ResMut<ConsoleLog>) {
+    for message in chat.read() {
+        log.print(format!("<{}> {}", message.sender, message.text));
+    }
+}
+
+fn help_command(
+    mut commands: EventReader<ConsoleCommand>,
+    registry: Res<CommandRegistry>,
+    mut log: ResMut<ConsoleLog>,
+) {
+    for _ in commands.read().filter(|command| command.name == "help") {
+        for spec in registry.iter() {
+            log.print(format!("{} - {}", spec.usage(), spec.help));
+        }
+    }
+}
+
+fn seed_command(
+    mut commands: EventReader<ConsoleCommand>,
+    world_settings: Res<WorldSettings>,
+    mut log: ResMut<ConsoleLog>,
+) {
+    for _ in commands.read().filter(|command| command.name == "seed") {
+        log.print(format!("Seed: {}", world_settings.seed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::default();
+        registry.commands.insert(
+            "tp",
+            CommandSpec {
+                name: "tp",
+                params: vec![
+                    Param::required("x", ArgKind::Number),
+                    Param::required("y", ArgKind::Number),
+                    Param::required("z", ArgKind::Number),
+                ],
+                help: "",
+            },
+        );
+        registry
+    }
+
+    #[test]
+    fn numbers_have_to_be_finite() {
+        let (registry, breg) = (registry(), BlockRegistry::default());
+        let command = registry
+            .parse("/tp 1.5 -3 1e30", &breg, CommandSource::Player)
+            .unwrap();
+        assert_eq!(command.number(0), Some(1.5));
+        assert_eq!(command.number(1), Some(-3.0));
+        assert_eq!(command.number(2), Some(1e30));
+        for number in ["inf", "-inf", "NaN", "1e39", "x"] {
+            let line = format!("/tp 0 {} 0", number);
+            assert!(registry.parse(&line, &breg, CommandSource::Player).is_err());
+        }
+    }
+}
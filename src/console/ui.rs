@@ -0,0 +1,166 @@
+use super::*;
+use bevy::input::InputSystem;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+
+// How many lines are shown while the console is open, and how long new lines stay on the screen
+// while it's closed.
+const VISIBLE_LINES: usize = 12;
+const LINE_FADE_SECONDS: u64 = 8;
+const CONSOLE_FONT_SIZE: f32 = 18.0;
+const CONSOLE_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+
+#[derive(Resource, Default)]
+pub struct ConsoleScreen {
+    pub open: bool,
+    pub input: String,
+}
+
+#[derive(Component)]
+pub(super) struct ConsoleText;
+
+#[derive(Component)]
+pub(super) struct ConsoleInputLine;
+
+// The console overlay of the client: opened with T for chat or / for a command, closed with Enter
+// (which sends the line) or Escape.
+pub struct ConsoleUiPlugin;
+
+impl Plugin for ConsoleUiPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(Startup, setup_console_ui)
+            // The keys are taken before any other system sees them, while the player is typing
+            // they shouldn't move it.
+            .add_systems(PreUpdate, type_in_console.after(InputSystem))
+            .add_systems(Update, update_console_ui);
+
+        // Resources
+        app.init_resource::<ConsoleScreen>();
+    }
+}
+
+pub(super) fn setup_console_ui(mut commands: Commands) {
+    let style = TextStyle {
+        font_size: CONSOLE_FONT_SIZE,
+        color: Color::WHITE,
+        ..default()
+    };
+    // Above the hotbar, on the left of the screen.
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(8.0),
+                bottom: Val::Px(80.0),
+                width: Val::Percent(40.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section("", style.clone()).with_background_color(CONSOLE_COLOR),
+                ConsoleText,
+            ));
+            parent.spawn((
+                TextBundle::from_section("", style)
+                    .with_style(Style {
+                        display: Display::None,
+                        margin: UiRect::top(Val::Px(4.0)),
+                        ..default()
+                    })
+                    .with_background_color(CONSOLE_COLOR),
+                ConsoleInputLine,
+            ));
+        });
+}
+
+pub(super) fn type_in_console(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut buttons: ResMut<Input<MouseButton>>,
+    mut chars: EventReader<ReceivedCharacter>,
+    key_bindings: Res<KeyBindings>,
+    mut screen: ResMut<ConsoleScreen>,
+    mut input: EventWriter<ConsoleInput>,
+    mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let was_open = screen.open;
+    if !screen.open {
+        // The character of the key that opened the console isn't typed.
+        chars.clear();
+        if keys.just_pressed(key_bindings.open_chat) {
+            screen.open = true;
+            screen.input.clear();
+        } else if keys.just_pressed(key_bindings.open_command) {
+            screen.open = true;
+            screen.input = String::from("/");
+        } else {
+            return;
+        }
+    } else {
+        for c in chars.read() {
+            if !c.char.is_control() {
+                screen.input.push(c.char);
+            }
+        }
+        if keys.just_pressed(KeyCode::Back) {
+            screen.input.pop();
+        }
+        if keys.just_pressed(KeyCode::Return) {
+            input.send(ConsoleInput {
+                line: std::mem::take(&mut screen.input),
+                source: CommandSource::Player,
+            });
+            screen.open = false;
+        } else if keys.just_pressed(KeyCode::Escape) {
+            screen.input.clear();
+            screen.open = false;
+        }
+    }
+    keys.reset_all();
+    buttons.reset_all();
+    // The cursor is let go while typing, like in the inventory screen.
+    if screen.open != was_open {
+        if let Ok(mut window) = primary_window.get_single_mut() {
+            window.cursor.grab_mode = if screen.open {
+                CursorGrabMode::None
+            } else {
+                CursorGrabMode::Confined
+            };
+            window.cursor.visible = screen.open;
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub(super) fn update_console_ui(
+    screen: Res<ConsoleScreen>,
+    log: Res<ConsoleLog>,
+    mut text: Query<(&mut Text, &mut Visibility), With<ConsoleText>>,
+    mut input_line: Query<(&mut Text, &mut Style), (With<ConsoleInputLine>, Without<ConsoleText>)>,
+) {
+    let fade = bevy::utils::Duration::from_secs(LINE_FADE_SECONDS);
+    let lines: Vec<&str> = log
+        .lines()
+        .filter(|(printed, _)| screen.open || printed.elapsed() < fade)
+        .map(|(_, line)| line.as_str())
+        .collect();
+    let lines = &lines[lines.len().saturating_sub(VISIBLE_LINES)..];
+    if let Ok((mut text, mut visibility)) = text.get_single_mut() {
+        text.sections[0].value = lines.join("\n");
+        *visibility = if lines.is_empty() {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+    if let Ok((mut text, mut style)) = input_line.get_single_mut() {
+        text.sections[0].value = format!("> {}_", screen.input);
+        style.display = if screen.open {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+}
@@ -0,0 +1,340 @@
+use crate::*;
+use std::collections::VecDeque;
+
+// How many of the player's edits are remembered.
+const JOURNAL_LEN: usize = 256;
+
+// A block the player changed, in the chunk at `chunk`, at `index` in its grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub chunk: [i32; 3],
+    pub index: usize,
+    pub old: Block,
+    pub new: Block,
+}
+
+impl JournalEntry {
+    pub fn pos(&self) -> [i32; 3] {
+        chunk_position_to_block(self.chunk, three_d_cords(self.index, CHUNK_DIMS))
+    }
+
+    // The change that puts back the block this one replaced.
+    pub fn inverse(self) -> Self {
+        JournalEntry {
+            old: self.new,
+            new: self.old,
+            ..self
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JournalStep {
+    Undo,
+    Redo,
+}
+
+// The edits the player made, the oldest first. The entries before the cursor are done, the ones
+// from the cursor on were undone, and are forgotten once the player makes a new edit.
+#[derive(Component, Default)]
+pub struct EditJournal {
+    entries: VecDeque<JournalEntry>,
+    cursor: usize,
+    // The change that was sent to undo or redo an entry. It isn't recorded as a new edit when it's
+    // made, the cursor moves instead.
+    pending: Option<(JournalStep, JournalEntry)>,
+}
+
+impl EditJournal {
+    pub fn record(&mut self, entry: JournalEntry) {
+        if let Some((step, change)) = self.pending {
+            if change == entry {
+                self.pending = None;
+                match step {
+                    JournalStep::Undo => self.cursor -= 1,
+                    JournalStep::Redo => self.cursor += 1,
+                }
+                return;
+            }
+        }
+        // The entry the pending change was for might be forgotten now.
+        self.pending = None;
+        self.entries.truncate(self.cursor);
+        self.entries.push_back(entry);
+        if self.entries.len() > JOURNAL_LEN {
+            self.entries.pop_front();
+        }
+        self.cursor = self.entries.len();
+    }
+
+    // The change that would undo the last edit, or redo the last edit that was undone.
+    pub fn next(&self, step: JournalStep) -> Option<JournalEntry> {
+        match step {
+            JournalStep::Undo => Some(self.entries.get(self.cursor.checked_sub(1)?)?.inverse()),
+            JournalStep::Redo => self.entries.get(self.cursor).copied(),
+        }
+    }
+
+    // The change from `next` was sent, the cursor moves once it's recorded.
+    pub fn step(&mut self, step: JournalStep) {
+        self.pending = self.next(step).map(|change| (step, change));
+    }
+
+    // The change that was sent to undo or redo an edit, and wasn't made yet.
+    pub fn pending(&self) -> Option<JournalEntry> {
+        self.pending.map(|(_, change)| change)
+    }
+
+    // The change that was sent won't be made, it was skipped or rejected.
+    pub fn forget_pending(&mut self) {
+        self.pending = None;
+    }
+
+    // The change from `next` can't be made anymore, because the block was changed since.
+    pub fn discard(&mut self, step: JournalStep) {
+        if self.next(step).is_none() {
+            return;
+        }
+        if step == JournalStep::Undo {
+            self.cursor -= 1;
+        }
+        self.entries.remove(self.cursor);
+    }
+}
+
+// Remembers the blocks the player breaks and places, so they can be undone with Ctrl+Z and redone
+// with Ctrl+Y.
+pub struct EditJournalPlugin;
+
+impl Plugin for EditJournalPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(Update, (record_edits, undo_and_redo_edits).chain());
+    }
+}
+
+fn record_edits(
+    mut edits: EventReader<BlockEdited>,
+    connection: Option<Res<ServerConnection>>,
+    mut player: Query<&mut EditJournal, With<FlyCam>>,
+) {
+    let Ok(mut journal) = player.get_single_mut() else {
+        return;
+    };
+    for edit in edits.read().filter(|edit| edit.by_player) {
+        let (chunk, local) = block_to_chunk_position(edit.pos);
+        journal.record(JournalEntry {
+            chunk,
+            index: one_d_cords(local, CHUNK_DIMS),
+            old: edit.old,
+            new: edit.new,
+        });
+    }
+    // A change that was sent on an earlier frame is made by now, unless the server didn't answer
+    // yet.
+    if let Some(change) = journal.pending() {
+        if !connection.is_some_and(|connection| connection.awaits_reply(change.pos())) {
+            journal.forget_pending();
+        }
+    }
+}
+
+// Undo or redo an edit by making the opposite change the same way the player would, so it's meshed
+// (and sent to the server) like any other edit.
+#[allow(clippy::too_many_arguments)]
+fn undo_and_redo_edits(
+    keys: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    game_mode: Res<State<GameMode>>,
+    mut player: Query<(&mut EditJournal, &Transform), With<FlyCam>>,
+    world: VoxelWorld,
+    close_chunks: Query<(), With<ChunkCloseToPlayer>>,
+    mut block_change: EventWriter<BlockChange>,
+    mut log: ResMut<ConsoleLog>,
+) {
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let step = if keys.just_pressed(key_bindings.undo_edit) {
+        JournalStep::Undo
+    } else if keys.just_pressed(key_bindings.redo_edit) {
+        JournalStep::Redo
+    } else {
+        return;
+    };
+    let Ok((mut journal, tran)) = player.get_single_mut() else {
+        return;
+    };
+    // One change at a time, the next one depends on whether it's made.
+    if journal.pending().is_some() {
+        return;
+    }
+    if !game_mode.get().can_undo() {
+        log.print("Edits can only be undone in creative");
+        return;
+    }
+    let Some(change) = journal.next(step) else {
+        log.print(match step {
+            JournalStep::Undo => "There is nothing to undo",
+            JournalStep::Redo => "There is nothing to redo",
+        });
+        return;
+    };
+    // Only blocks in the chunks around the player can be changed.
+    let block_at = |pos: [i32; 3]| {
+        let ent = world.chunk_map().get_ent(block_to_chunk_position(pos).0)?;
+        close_chunks.get(ent).ok()?;
+        world.get_block(pos.into())
+    };
+    let pos = change.pos();
+    let neighbors = (0..6).map(|i| adjacent_cords(pos, Face::from(i)));
+    let Some(current) = block_at(pos) else {
+        log.print("The edit is too far away");
+        return;
+    };
+    if neighbors.clone().any(|neighbor| {
+        is_chunk_in_world(block_to_chunk_position(neighbor).0) && block_at(neighbor).is_none()
+    }) {
+        log.print("The edit is too far away");
+        return;
+    }
+    if current != change.old {
+        log.print("The block was changed since, the edit can't be undone or redone");
+        journal.discard(step);
+        return;
+    }
+    let player = Hitbox::player(tran.translation);
+    if change.new != AIR
+        && (0..3).all(|axis| {
+            pos[axis] as f32 - 0.5 < player.max[axis] && pos[axis] as f32 + 0.5 > player.min[axis]
+        })
+    {
+        log.print("The player is in the way");
+        return;
+    }
+    block_change.send(if change.new == AIR {
+        BlockChange {
+            blocks: vec![(change.chunk, change.index, None)],
+            change: VoxelChange::Broken,
+            block: AIR,
+        }
+    } else {
+        // Blocks are placed against another block, a server doesn't accept them otherwise.
+        let onto = neighbors
+            .filter(|neighbor| block_at(*neighbor).is_some_and(|block| block != AIR))
+            .map(|neighbor| {
+                let (chunk, local) = block_to_chunk_position(neighbor);
+                (chunk, one_d_cords(local, CHUNK_DIMS))
+            })
+            .next();
+        BlockChange {
+            blocks: vec![(change.chunk, change.index, onto)],
+            change: VoxelChange::Added,
+            block: change.new,
+        }
+    });
+    journal.step(step);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(index: usize, old: Block, new: Block) -> JournalEntry {
+        JournalEntry {
+            chunk: [0, 2, 0],
+            index,
+            old,
+            new,
+        }
+    }
+
+    // Send the next change, and make it.
+    fn replay(journal: &mut EditJournal, step: JournalStep) {
+        let change = journal.next(step).unwrap();
+        journal.step(step);
+        journal.record(change);
+    }
+
+    #[test]
+    fn undoing_and_redoing() {
+        let mut journal = EditJournal::default();
+        journal.record(entry(0, AIR, STONE));
+        journal.record(entry(1, DIRT, AIR));
+        assert_eq!(journal.next(JournalStep::Undo), Some(entry(1, AIR, DIRT)));
+        assert_eq!(journal.next(JournalStep::Redo), None);
+
+        replay(&mut journal, JournalStep::Undo);
+        assert_eq!(journal.next(JournalStep::Undo), Some(entry(0, STONE, AIR)));
+        assert_eq!(journal.next(JournalStep::Redo), Some(entry(1, DIRT, AIR)));
+        replay(&mut journal, JournalStep::Undo);
+        assert_eq!(journal.next(JournalStep::Undo), None);
+
+        replay(&mut journal, JournalStep::Redo);
+        replay(&mut journal, JournalStep::Redo);
+        assert_eq!(journal.next(JournalStep::Undo), Some(entry(1, AIR, DIRT)));
+        assert_eq!(journal.next(JournalStep::Redo), None);
+        assert_eq!(journal.entries.len(), 2);
+    }
+
+    #[test]
+    fn changes_that_are_not_made_dont_move_the_cursor() {
+        let mut journal = EditJournal::default();
+        journal.record(entry(0, AIR, STONE));
+        journal.step(JournalStep::Undo);
+        assert_eq!(journal.pending(), Some(entry(0, STONE, AIR)));
+        journal.forget_pending();
+        assert_eq!(journal.next(JournalStep::Undo), Some(entry(0, STONE, AIR)));
+
+        // The same change made later by the player is a new edit.
+        journal.record(entry(0, STONE, AIR));
+        assert_eq!(journal.entries.len(), 2);
+        assert_eq!(journal.next(JournalStep::Undo), Some(entry(0, AIR, STONE)));
+    }
+
+    #[test]
+    fn a_new_edit_forgets_what_was_undone() {
+        let mut journal = EditJournal::default();
+        for i in 0..3 {
+            journal.record(entry(i, AIR, STONE));
+        }
+        replay(&mut journal, JournalStep::Undo);
+        replay(&mut journal, JournalStep::Undo);
+        journal.record(entry(5, AIR, GLASS));
+        assert_eq!(journal.next(JournalStep::Redo), None);
+        assert_eq!(journal.next(JournalStep::Undo), Some(entry(5, GLASS, AIR)));
+        assert_eq!(journal.entries.len(), 2);
+    }
+
+    #[test]
+    fn discarding_changes_that_cant_be_made() {
+        let mut journal = EditJournal::default();
+        for i in 0..3 {
+            journal.record(entry(i, AIR, STONE));
+        }
+        replay(&mut journal, JournalStep::Undo);
+        // The last edit can't be redone, the one before it can't be undone.
+        journal.discard(JournalStep::Redo);
+        assert_eq!(journal.next(JournalStep::Redo), None);
+        journal.discard(JournalStep::Undo);
+        assert_eq!(journal.next(JournalStep::Undo), Some(entry(0, STONE, AIR)));
+        assert_eq!(journal.entries.len(), 1);
+        journal.discard(JournalStep::Undo);
+        journal.discard(JournalStep::Undo);
+        assert_eq!(journal.next(JournalStep::Undo), None);
+    }
+
+    #[test]
+    fn only_the_last_edits_are_remembered() {
+        let mut journal = EditJournal::default();
+        for i in 0..JOURNAL_LEN + 10 {
+            journal.record(entry(i, AIR, STONE));
+        }
+        assert_eq!(journal.entries.len(), JOURNAL_LEN);
+        for _ in 0..JOURNAL_LEN {
+            replay(&mut journal, JournalStep::Undo);
+        }
+        assert_eq!(journal.next(JournalStep::Undo), None);
+        assert_eq!(journal.next(JournalStep::Redo), Some(entry(10, AIR, STONE)));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::*;
+use bevy::utils::{Duration, HashSet};
+
+// A water source is a full block of water, the water that flows out of it gets one level lower
+// with each block it flows sideways.
+pub const SOURCE_LEVEL: u8 = 8;
+// Water that falls down from above is as strong as water right next to a source.
+const FALLING_LEVEL: u8 = SOURCE_LEVEL - 1;
+const FLUID_TICK: Duration = Duration::from_millis(250);
+
+// The water level of a block, or None if it isn't water.
+pub fn water_level(block: Block) -> Option<u8> {
+    match block {
+        WATER => Some(SOURCE_LEVEL),
+        b if (FLOWING_WATER..FLOWING_WATER + FALLING_LEVEL as Block).contains(&b) => {
+            Some((b - FLOWING_WATER) as u8 + 1)
+        }
+        _ => None,
+    }
+}
+
+// The block of water with the given level, level 0 is no water at all.
+pub fn water_block(level: u8) -> Block {
+    match level {
+        0 => AIR,
+        SOURCE_LEVEL => WATER,
+        level => FLOWING_WATER + level as Block - 1,
+    }
+}
+
+// Anything that blocks can be read from, in world coordinates. Blocks that aren't loaded are None,
+// water doesn't flow into them.
+pub trait FluidWorld {
+    fn block(&self, pos: [i32; 3]) -> Option<Block>;
+}
+
+// Water spreads sideways from a source, or from flowing water that sits on something it can't
+// flow into.
+fn spreads_sideways(world: &impl FluidWorld, pos: [i32; 3], level: u8) -> bool {
+    if level == SOURCE_LEVEL {
+        return true;
+    }
+    world
+        .block(adjacent_cords(pos, Bottom))
+        .is_some_and(|below| below != AIR && water_level(below).is_none())
+}
+
+// What the block at `pos` should become, or None if it stays the same. Only air and flowing water
+// change, the level of flowing water is decided by the water around it, so when the source is
+// removed the water that flowed from it dries up one level at a time.
+fn flow_at(world: &impl FluidWorld, pos: [i32; 3]) -> Option<Block> {
+    let block = world.block(pos)?;
+    let flowing = water_level(block).is_some_and(|level| level < SOURCE_LEVEL);
+    if block != AIR && !flowing {
+        return None;
+    }
+    let level = if world
+        .block(adjacent_cords(pos, Top))
+        .and_then(water_level)
+        .is_some()
+    {
+        FALLING_LEVEL
+    } else {
+        [Right, Left, Back, Forward]
+            .into_iter()
+            .filter_map(|face| {
+                let neighbor = adjacent_cords(pos, face);
+                let level = water_level(world.block(neighbor)?)?;
+                spreads_sideways(world, neighbor, level).then(|| level - 1)
+            })
+            .max()
+            .unwrap_or(0)
+    };
+    let new_block = water_block(level);
+    (new_block != block).then_some(new_block)
+}
+
+// The blocks that might have to change on the next tick of the water simulation.
+#[derive(Resource, Default)]
+pub struct FluidSim {
+    scheduled: HashSet<[i32; 3]>,
+}
+
+impl FluidSim {
+    // Check the block and the blocks around it on the next tick, call this when a block changes.
+    pub fn schedule_around(&mut self, pos: [i32; 3]) {
+        self.scheduled.insert(pos);
+        for i in 0..6 {
+            self.scheduled.insert(adjacent_cords(pos, Face::from(i)));
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.scheduled.is_empty()
+    }
+
+    // Advance the water by one step, and return the blocks that changed. All the blocks are decided
+    // from the world as it was before the tick, so the order they are checked in doesn't matter.
+    // The changes aren't written to the world, that's left to the caller.
+    pub fn tick(&mut self, world: &impl FluidWorld) -> Vec<([i32; 3], Block)> {
+        let changes: Vec<([i32; 3], Block)> = self
+            .scheduled
+            .drain()
+            .filter_map(|pos| Some((pos, flow_at(world, pos)?)))
+            .collect();
+        for (pos, _) in changes.iter() {
+            self.schedule_around(*pos);
+        }
+        changes
+    }
+}
+
+impl FluidWorld for VoxelWorldMut<'_, '_> {
+    fn block(&self, pos: [i32; 3]) -> Option<Block> {
+        self.get_block(pos.into())
+    }
+}
+
+#[derive(Resource)]
+pub struct FluidTimer(pub Timer);
+
+pub struct FluidPlugin;
+
+impl Plugin for FluidPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        // Water only flows in the world that is simulated, not in the copy of a client.
+        app.add_systems(Update, flow_water.run_if(world_is_local));
+
+        // Resources
+        app.init_resource::<FluidSim>()
+            .insert_resource(FluidTimer(Timer::new(FLUID_TICK, TimerMode::Repeating)));
+    }
+}
+
+// Run the water simulation on a fixed tick, and write the blocks that changed into the chunks.
+fn flow_water(
+    time: Res<Time>,
+    mut timer: ResMut<FluidTimer>,
+    mut sim: ResMut<FluidSim>,
+    mut world: VoxelWorldMut,
+    mut edits: EventWriter<BlockEdited>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() || sim.is_idle() {
+        return;
+    }
+    let changes = sim.tick(&world);
+    for (pos, old, new) in world.set_blocks(changes) {
+        edits.send(BlockEdited {
+            pos,
+            old,
+            new,
+            by_player: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::utils::HashMap;
+
+    // A stone floor at y = 0, with air above it, in a box around the origin. The blocks outside of
+    // the box aren't loaded.
+    #[derive(Default)]
+    struct TestWorld {
+        blocks: HashMap<[i32; 3], Block>,
+    }
+
+    const SIZE: i32 = 12;
+
+    impl TestWorld {
+        fn set(&mut self, sim: &mut FluidSim, pos: [i32; 3], block: Block) {
+            self.blocks.insert(pos, block);
+            sim.schedule_around(pos);
+        }
+
+        // Tick the water until it stops changing.
+        fn settle(&mut self, sim: &mut FluidSim) {
+            for _ in 0..100 {
+                if sim.is_idle() {
+                    return;
+                }
+                for (pos, block) in sim.tick(self) {
+                    self.blocks.insert(pos, block);
+                }
+            }
+            panic!("The water never settled");
+        }
+
+        fn level(&self, pos: [i32; 3]) -> u8 {
+            self.block(pos).and_then(water_level).unwrap_or(0)
+        }
+    }
+
+    impl FluidWorld for TestWorld {
+        fn block(&self, pos: [i32; 3]) -> Option<Block> {
+            if pos.iter().any(|c| c.abs() > SIZE) {
+                return None;
+            }
+            Some(
+                *self
+                    .blocks
+                    .get(&pos)
+                    .unwrap_or(if pos[1] <= 0 { &STONE } else { &AIR }),
+            )
+        }
+    }
+
+    #[test]
+    fn water_spreads_with_lower_levels() {
+        let (mut world, mut sim) = (TestWorld::default(), FluidSim::default());
+        world.set(&mut sim, [0, 1, 0], WATER);
+        world.settle(&mut sim);
+        for distance in 0..SOURCE_LEVEL as i32 {
+            let level = SOURCE_LEVEL - distance as u8;
+            assert_eq!(world.level([distance, 1, 0]), level);
+            assert_eq!(world.level([0, 1, -distance]), level);
+        }
+        // Sideways, a block away diagonally is two steps away.
+        assert_eq!(world.level([1, 1, 1]), SOURCE_LEVEL - 2);
+        assert_eq!(world.level([SOURCE_LEVEL as i32, 1, 0]), 0);
+        // Water doesn't climb.
+        assert_eq!(world.level([1, 2, 0]), 0);
+    }
+
+    #[test]
+    fn water_falls_and_spreads_where_it_lands() {
+        let (mut world, mut sim) = (TestWorld::default(), FluidSim::default());
+        world.set(&mut sim, [0, 5, 0], WATER);
+        world.settle(&mut sim);
+        for y in 1..5 {
+            assert_eq!(world.level([0, y, 0]), FALLING_LEVEL);
+        }
+        // The source spreads a block sideways, but the water that flows out of it falls down
+        // instead of spreading further.
+        assert_eq!(world.level([1, 5, 0]), SOURCE_LEVEL - 1);
+        assert_eq!(world.level([2, 5, 0]), 0);
+        // Falling water doesn't spread sideways until it lands.
+        assert_eq!(world.level([2, 3, 0]), 0);
+        assert_eq!(world.level([1, 1, 1]), FALLING_LEVEL - 1);
+        assert_eq!(world.level([4, 1, 0]), FALLING_LEVEL - 3);
+
+        // Water stops at the border of the loaded blocks.
+        world.set(&mut sim, [SIZE, 3, SIZE], WATER);
+        world.settle(&mut sim);
+        assert_eq!(world.level([SIZE, 1, SIZE]), FALLING_LEVEL);
+        assert_eq!(world.level([SIZE - 2, 1, SIZE]), FALLING_LEVEL - 1);
+    }
+
+    #[test]
+    fn water_dries_up_without_its_source() {
+        let (mut world, mut sim) = (TestWorld::default(), FluidSim::default());
+        world.set(&mut sim, [0, 4, 0], WATER);
+        world.set(&mut sim, [0, 3, 0], STONE);
+        world.settle(&mut sim);
+        assert!(
+            world
+                .blocks
+                .values()
+                .filter(|b| water_level(**b).is_some())
+                .count()
+                > 10
+        );
+
+        world.set(&mut sim, [0, 4, 0], AIR);
+        world.settle(&mut sim);
+        assert_eq!(
+            world.blocks.values().find(|b| water_level(**b).is_some()),
+            None
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+
+// What the player is allowed to do. In survival blocks are used up when they're placed and broken
+// blocks drop, in creative the player has as many blocks as it wants, breaks them instantly and can
+// fly, and in spectator the player flies through everything and can't change the world.
+#[derive(States, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    #[default]
+    Survival,
+    Creative,
+    Spectator,
+}
+
+impl GameMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "survival" => Some(GameMode::Survival),
+            "creative" => Some(GameMode::Creative),
+            "spectator" => Some(GameMode::Spectator),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            GameMode::Survival => GameMode::Creative,
+            GameMode::Creative => GameMode::Spectator,
+            GameMode::Spectator => GameMode::Survival,
+        }
+    }
+
+    // Whether placing a block takes it out of the inventory.
+    pub fn consumes_blocks(self) -> bool {
+        self == GameMode::Survival
+    }
+
+    // Whether broken blocks drop an item that can be picked up.
+    pub fn drops_items(self) -> bool {
+        self == GameMode::Survival
+    }
+
+    pub fn breaks_instantly(self) -> bool {
+        self == GameMode::Creative
+    }
+
+    pub fn can_fly(self) -> bool {
+        self != GameMode::Survival
+    }
+
+    // Whether the player collides with blocks.
+    pub fn collides(self) -> bool {
+        self != GameMode::Spectator
+    }
+
+    // Whether the player can break and place blocks.
+    pub fn can_interact(self) -> bool {
+        self != GameMode::Spectator
+    }
+
+    // Whether the player can undo and redo its edits, only when blocks are free so undoing doesn't
+    // make new ones.
+    pub fn can_undo(self) -> bool {
+        self == GameMode::Creative
+    }
+}
+
+pub struct GameModePlugin;
+
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut App) {
+        // States
+        app.add_state::<GameMode>();
+
+        // Systems
+        app.add_systems(Startup, set_initial_game_mode).add_systems(
+            Update,
+            (
+                // The server decides the game mode of the players that joined it.
+                cycle_game_mode.run_if(world_is_local.and_then(cheats_enabled)),
+                gamemode_command,
+                apply_game_mode.run_if(state_changed::<GameMode>()),
+            ),
+        );
+
+        // Commands
+        app.register_command(CommandSpec {
+            name: "gamemode",
+            params: vec![Param::required("mode", ArgKind::Word)],
+            help: "Switch to survival, creative or spectator",
+        });
+    }
+}
+
+fn set_initial_game_mode(
+    world_settings: Res<WorldSettings>,
+    mut next_mode: ResMut<NextState<GameMode>>,
+) {
+    next_mode.set(world_settings.game_mode);
+}
+
+// Switching game modes while playing is cheating, unless the world was started with cheats on.
+fn cheats_enabled(world_settings: Res<WorldSettings>) -> bool {
+    world_settings.cheats
+}
+
+fn cycle_game_mode(
+    keys: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mode: Res<State<GameMode>>,
+    mut next_mode: ResMut<NextState<GameMode>>,
+) {
+    if keys.just_pressed(key_bindings.cycle_game_mode) {
+        next_mode.set(mode.next());
+    }
+}
+
+fn gamemode_command(
+    mut commands: EventReader<ConsoleCommand>,
+    connection: Option<Res<ServerConnection>>,
+    world_settings: Res<WorldSettings>,
+    mut next_mode: ResMut<NextState<GameMode>>,
+    mut log: ResMut<ConsoleLog>,
+) {
+    for command in commands.read().filter(|command| command.name == "gamemode") {
+        if connection.is_some() {
+            log.print("The server decides the game mode");
+            continue;
+        }
+        if !world_settings.cheats {
+            log.print("Cheats are off, start the world with --cheats to switch game modes");
+            continue;
+        }
+        match command.word(0).and_then(GameMode::from_name) {
+            Some(mode) => next_mode.set(mode),
+            None => log.print("Usage: /gamemode <survival|creative|spectator>"),
+        }
+    }
+}
+
+// Spectators always fly, and in survival the player can't fly at all.
+fn apply_game_mode(mode: Res<State<GameMode>>, mut player: Query<&mut PlayerPhysics>) {
+    info!("\nIn-Game Log:\nGame mode is now {:?}", mode.get());
+    for mut physics in player.iter_mut() {
+        physics.mode = match mode.get() {
+            GameMode::Survival => MovementMode::Walk,
+            GameMode::Creative => physics.mode,
+            GameMode::Spectator => MovementMode::Fly,
+        };
+        physics.velocity = Vec3::ZERO;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [GameMode; 3] = [GameMode::Survival, GameMode::Creative, GameMode::Spectator];
+
+    #[test]
+    fn what_each_mode_allows() {
+        // Survival, creative, spectator.
+        let allowed = |rule: fn(GameMode) -> bool| MODES.map(rule);
+        assert_eq!(allowed(GameMode::can_interact), [true, true, false]);
+        assert_eq!(allowed(GameMode::breaks_instantly), [false, true, false]);
+        assert_eq!(allowed(GameMode::can_undo), [false, true, false]);
+        assert_eq!(allowed(GameMode::consumes_blocks), [true, false, false]);
+        assert_eq!(allowed(GameMode::drops_items), [true, false, false]);
+        assert_eq!(allowed(GameMode::can_fly), [false, true, true]);
+        assert_eq!(allowed(GameMode::collides), [true, true, false]);
+        for mode in MODES {
+            let name = format!("{:?}", mode).to_lowercase();
+            assert_eq!(GameMode::from_name(&name), Some(mode));
+        }
+        assert_eq!(GameMode::Spectator.next(), GameMode::Survival);
+    }
+
+    // Switch the game mode of a player that moves with `mode`, and return how it moves after.
+    fn switch(from: MovementMode, to: GameMode) -> MovementMode {
+        let mut app = App::new();
+        app.add_state::<GameMode>()
+            .add_systems(Update, apply_game_mode.run_if(state_changed::<GameMode>()));
+        let player = app
+            .world
+            .spawn(PlayerPhysics {
+                velocity: Vec3::ONE,
+                on_ground: false,
+                mode: from,
+            })
+            .id();
+        app.world.resource_mut::<NextState<GameMode>>().set(to);
+        app.update();
+        let physics = app.world.get::<PlayerPhysics>(player).unwrap();
+        assert_eq!(physics.velocity, Vec3::ZERO);
+        physics.mode
+    }
+
+    #[test]
+    fn switching_modes_sets_how_the_player_moves() {
+        for from in [MovementMode::Walk, MovementMode::Fly] {
+            assert_eq!(switch(from, GameMode::Survival), MovementMode::Walk);
+            assert_eq!(switch(from, GameMode::Spectator), MovementMode::Fly);
+            // In creative the player keeps flying or walking.
+            assert_eq!(switch(from, GameMode::Creative), from);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::*;
+use bevy::utils::HashMap;
+
+// Dropped items are small spinning blocks.
+const DROP_SIZE: f32 = 0.25;
+const DROP_SPIN_SPEED: f32 = 1.5;
+// The speed a drop pops out of the broken block with.
+const DROP_POP_SPEED: f32 = 4.0;
+const DROP_GRAVITY: f32 = 20.0;
+const DROP_MAX_FALL_SPEED: f32 = 30.0;
+// How close the player has to get to a drop to pick it up, and how long after it dropped it can
+// be picked up.
+const PICKUP_DISTANCE: f32 = 1.5;
+const PICKUP_DELAY: f32 = 0.5;
+// Drops that weren't picked up disappear after 5 minutes.
+const DROP_LIFETIME: f32 = 300.0;
+
+// A block to drop at `pos` (in world coordinates).
+#[derive(Event)]
+pub struct DropItem {
+    pub block: Block,
+    pub pos: [i32; 3],
+}
+
+#[derive(Component)]
+pub struct DroppedItem {
+    pub block: Block,
+    pub velocity: Vec3,
+    // How many seconds ago it was dropped.
+    pub age: f32,
+}
+
+// The meshes of the drops, one for each block.
+#[derive(Resource, Default)]
+pub(super) struct DropMeshes(HashMap<Block, Handle<Mesh>>);
+
+pub(super) fn spawn_drops(
+    mut commands: Commands,
+    mut drops: EventReader<DropItem>,
+    mut drop_meshes: ResMut<DropMeshes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    breg: Res<BlockRegistry>,
+    mat: Res<BlockMaterial>,
+) {
+    // The meshes of the blocks change when the registry is reloaded.
+    if breg.is_changed() {
+        drop_meshes.0.clear();
+    }
+    for drop in drops.read() {
+        let Some(registered) = breg.get(drop.block) else {
+            continue;
+        };
+        let mesh = drop_meshes
+            .0
+            .entry(drop.block)
+            .or_insert_with(|| meshes.add(registered.mesh.clone()))
+            .clone();
+        commands.spawn((
+            PbrBundle {
+                mesh,
+                material: mat.0.clone(),
+                transform: Transform::from_xyz(
+                    drop.pos[0] as f32,
+                    drop.pos[1] as f32,
+                    drop.pos[2] as f32,
+                )
+                .with_scale(Vec3::splat(DROP_SIZE)),
+                ..default()
+            },
+            DroppedItem {
+                block: drop.block,
+                velocity: Vec3::Y * DROP_POP_SPEED,
+                age: 0.0,
+            },
+        ));
+    }
+}
+
+// Drops fall onto the blocks below them, and stop in the air if the chunk below them isn't loaded.
+pub(super) fn move_drops(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut drops: Query<(Entity, &mut DroppedItem, &mut Transform)>,
+    world: VoxelWorld,
+) {
+    let dt = time.delta_seconds();
+    for (ent, mut drop, mut transform) in drops.iter_mut() {
+        drop.age += dt;
+        if drop.age > DROP_LIFETIME {
+            commands.entity(ent).despawn();
+            continue;
+        }
+        drop.velocity.y = (drop.velocity.y - DROP_GRAVITY * dt).max(-DROP_MAX_FALL_SPEED);
+        let half_size = Vec3::splat(DROP_SIZE / 2.0);
+        let (motion, collision) = move_hitbox(
+            Hitbox {
+                min: transform.translation - half_size,
+                max: transform.translation + half_size,
+            },
+            drop.velocity * dt,
+            0.0,
+            |pos| world.get_block(pos.into()).is_none_or(blocks_movement),
+        );
+        transform.translation += motion;
+        if collision.on_ground || collision.hit_ceiling {
+            drop.velocity.y = 0.0;
+        }
+        transform.rotate_y(DROP_SPIN_SPEED * dt);
+    }
+}
+
+// The player picks up the drops close to it, as long as there is room for them in the inventory.
+pub(super) fn pick_up_drops(
+    mut commands: Commands,
+    mut inv: ResMut<Inventory>,
+    drops: Query<(Entity, &DroppedItem, &Transform)>,
+    player: Query<&Transform, With<FlyCam>>,
+    game_mode: Res<State<GameMode>>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+    if !game_mode.get().can_interact() {
+        return;
+    }
+    let hitbox = Hitbox::player(player.translation);
+    let center = (hitbox.min + hitbox.max) / 2.0;
+    for (ent, drop, transform) in drops.iter() {
+        if drop.age < PICKUP_DELAY || transform.translation.distance(center) > PICKUP_DISTANCE {
+            continue;
+        }
+        if inv.add(drop.block) {
+            commands.entity(ent).despawn();
+        }
+    }
+}
@@ -0,0 +1,431 @@
+pub mod drops;
+pub mod ui;
+use crate::*;
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+pub use drops::*;
+pub use ui::*;
+
+// The first slots of the inventory are the hotbar, the player can only hold the blocks in it.
+pub const HOTBAR_SLOTS: usize = 9;
+pub const INVENTORY_SLOTS: usize = 36;
+pub const MAX_STACK: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ItemStack {
+    pub block: Block,
+    pub count: u32,
+}
+
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct Inventory {
+    pub slots: [Option<ItemStack>; INVENTORY_SLOTS],
+    // The hotbar slot the player is holding.
+    pub selected: usize,
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        let mut inv = Inventory::empty();
+        for block in [
+            GRASS, DIRT, STONE, BRICKS, LOG, WOOD, LEAVES, GLASS, GLOWSTONE, WATER, SAND, SNOW,
+        ] {
+            for _ in 0..MAX_STACK {
+                inv.add(block);
+            }
+        }
+        inv
+    }
+}
+
+impl Inventory {
+    pub fn empty() -> Self {
+        Inventory {
+            slots: [None; INVENTORY_SLOTS],
+            selected: 0,
+        }
+    }
+
+    // The block in the selected slot, if there is one.
+    pub fn selected_block(&self) -> Option<Block> {
+        self.slots[self.selected].map(|stack| stack.block)
+    }
+
+    // Take one block out of the selected slot, the slot is emptied when the last one is taken.
+    pub fn take_selected(&mut self) -> Option<Block> {
+        let slot = &mut self.slots[self.selected];
+        let stack = slot.as_mut()?;
+        stack.count -= 1;
+        let block = stack.block;
+        if stack.count == 0 {
+            *slot = None;
+        }
+        Some(block)
+    }
+
+    // Put a block into the inventory. It goes onto a stack of the same block that isn't full if
+    // there is one, otherwise into the first empty slot (the hotbar comes first). Returns false if
+    // there was no room for it.
+    pub fn add(&mut self, block: Block) -> bool {
+        if let Some(stack) = self
+            .slots
+            .iter_mut()
+            .flatten()
+            .find(|stack| stack.block == block && stack.count < MAX_STACK)
+        {
+            stack.count += 1;
+            return true;
+        }
+        if let Some(slot) = self.slots.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(ItemStack { block, count: 1 });
+            return true;
+        }
+        false
+    }
+
+    // How many of `block` there are in the whole inventory.
+    pub fn count(&self, block: Block) -> u32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|stack| stack.block == block)
+            .map(|stack| stack.count)
+            .sum()
+    }
+
+    pub fn select(&mut self, slot: usize) {
+        if slot < HOTBAR_SLOTS {
+            self.selected = slot;
+        }
+    }
+
+    // Move the selection by `steps` slots, going around the ends of the hotbar.
+    pub fn scroll(&mut self, steps: i32) {
+        self.selected = (self.selected as i32 + steps).rem_euclid(HOTBAR_SLOTS as i32) as usize;
+    }
+
+    // Swap the contents of two slots, two stacks of the same block are merged instead as far as
+    // they fit onto the stack in `b`.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+        match (self.slots[a], self.slots[b]) {
+            (Some(from), Some(to)) if from.block == to.block => {
+                let moved = from.count.min(MAX_STACK - to.count);
+                self.slots[b] = Some(ItemStack {
+                    count: to.count + moved,
+                    ..to
+                });
+                self.slots[a] = (from.count > moved).then_some(ItemStack {
+                    count: from.count - moved,
+                    ..from
+                });
+            }
+            _ => self.slots.swap(a, b),
+        }
+    }
+}
+
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(Startup, setup_inventory_ui).add_systems(
+            Update,
+            (
+                (input_inventory, toggle_inventory_screen, click_slots),
+                update_slots,
+            )
+                .chain(),
+        );
+        app.add_systems(
+            Update,
+            (
+                use_blocks_on_edits,
+                spawn_drops,
+                move_drops,
+                pick_up_drops.before(update_slots),
+            )
+                .chain(),
+        );
+
+        // Resources
+        app.init_resource::<Inventory>()
+            .init_resource::<InventoryScreen>()
+            .init_resource::<DropMeshes>();
+
+        // Events
+        app.add_event::<DropItem>();
+
+        // Commands
+        app.add_systems(Update, give_command.before(update_slots))
+            .register_command(CommandSpec {
+                name: "give",
+                params: vec![
+                    Param::required("block", ArgKind::Block),
+                    Param::optional("count", ArgKind::Int),
+                ],
+                help: "Put blocks into the inventory",
+            });
+    }
+}
+
+// Placing a block uses up one of the blocks the player is holding, and breaking a block drops it,
+// depending on the game mode.
+fn use_blocks_on_edits(
+    mut edits: EventReader<BlockEdited>,
+    mut inv: ResMut<Inventory>,
+    game_mode: Res<State<GameMode>>,
+    mut drops: EventWriter<DropItem>,
+) {
+    for edit in edits.read().filter(|edit| edit.by_player) {
+        if edit.new != AIR {
+            if game_mode.get().consumes_blocks() && inv.selected_block() == Some(edit.new) {
+                inv.take_selected();
+            }
+        // Water doesn't drop anything.
+        } else if game_mode.get().drops_items() && water_level(edit.old).is_none() {
+            drops.send(DropItem {
+                block: edit.old,
+                pos: edit.pos,
+            });
+        }
+    }
+}
+
+fn give_command(
+    mut commands: EventReader<ConsoleCommand>,
+    mut inv: ResMut<Inventory>,
+    breg: Res<BlockRegistry>,
+    mut log: ResMut<ConsoleLog>,
+) {
+    for command in commands.read().filter(|command| command.name == "give") {
+        let Some(block) = command.block(0).filter(|block| *block != AIR) else {
+            log.print("Air can't be given");
+            continue;
+        };
+        let count = command.int(1).unwrap_or(1).max(0) as u32;
+        // Whatever doesn't fit into the inventory is lost.
+        let given = (0..count).take_while(|_| inv.add(block)).count();
+        let name = breg
+            .get(block)
+            .map_or("", |registered| registered.name.as_str());
+        log.print(format!("Gave {} {}", given, name));
+    }
+}
+
+const HOTBAR_KEYS: [KeyCode; HOTBAR_SLOTS] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+// The number keys pick a hotbar slot, and the mouse wheel scrolls through the hotbar.
+fn input_inventory(
+    mut inv: ResMut<Inventory>,
+    keys: Res<Input<KeyCode>>,
+    mut scroll: EventReader<MouseWheel>,
+    screen: Res<InventoryScreen>,
+) {
+    for (slot, key) in HOTBAR_KEYS.iter().enumerate() {
+        if keys.just_pressed(*key) {
+            inv.select(slot);
+        }
+    }
+    let mut steps = 0.0;
+    for event in scroll.read() {
+        steps += match event.unit {
+            MouseScrollUnit::Line => event.y,
+            // Trackpads scroll in pixels, a line is about 20 of them.
+            MouseScrollUnit::Pixel => event.y / 20.0,
+        };
+    }
+    // Scrolling down moves the selection to the right.
+    if steps != 0.0 && !screen.open {
+        inv.scroll(-steps.signum() as i32 * steps.abs().ceil() as i32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adding_fills_stacks_before_empty_slots() {
+        let mut inv = Inventory::empty();
+        assert!(inv.add(STONE));
+        assert!(inv.add(DIRT));
+        assert!(inv.add(STONE));
+        assert_eq!(
+            inv.slots[0],
+            Some(ItemStack {
+                block: STONE,
+                count: 2
+            })
+        );
+        assert_eq!(
+            inv.slots[1],
+            Some(ItemStack {
+                block: DIRT,
+                count: 1
+            })
+        );
+
+        // A full stack starts a new one in the first empty slot.
+        for _ in 2..MAX_STACK + 1 {
+            inv.add(STONE);
+        }
+        assert_eq!(inv.slots[0].unwrap().count, MAX_STACK);
+        assert_eq!(
+            inv.slots[2],
+            Some(ItemStack {
+                block: STONE,
+                count: 1
+            })
+        );
+        assert_eq!(inv.count(STONE), MAX_STACK + 1);
+
+        // An emptied slot in the hotbar is used before the slots after it.
+        inv.slots[1] = None;
+        inv.add(SAND);
+        assert_eq!(inv.slots[1].unwrap().block, SAND);
+    }
+
+    #[test]
+    fn adding_to_a_full_inventory() {
+        let mut inv = Inventory::empty();
+        for _ in 0..MAX_STACK * INVENTORY_SLOTS as u32 {
+            assert!(inv.add(STONE));
+        }
+        assert!(!inv.add(STONE));
+        assert!(!inv.add(DIRT));
+        assert_eq!(inv.count(STONE), MAX_STACK * INVENTORY_SLOTS as u32);
+        assert_eq!(inv.count(DIRT), 0);
+    }
+
+    #[test]
+    fn taking_the_last_block_empties_the_slot() {
+        let mut inv = Inventory::empty();
+        inv.add(GLASS);
+        inv.add(GLASS);
+        assert_eq!(inv.take_selected(), Some(GLASS));
+        assert_eq!(
+            inv.slots[0],
+            Some(ItemStack {
+                block: GLASS,
+                count: 1
+            })
+        );
+        assert_eq!(inv.take_selected(), Some(GLASS));
+        assert_eq!(inv.slots[0], None);
+        assert_eq!(inv.take_selected(), None);
+
+        // Only the selected slot is taken from.
+        inv.add(LOG);
+        inv.select(1);
+        assert_eq!(inv.take_selected(), None);
+        assert_eq!(inv.count(LOG), 1);
+    }
+
+    #[test]
+    fn swapping_and_merging_slots() {
+        let mut inv = Inventory::empty();
+        inv.slots[0] = Some(ItemStack {
+            block: STONE,
+            count: 10,
+        });
+        inv.slots[1] = Some(ItemStack {
+            block: DIRT,
+            count: 5,
+        });
+        inv.swap(0, 1);
+        assert_eq!(
+            inv.slots[0],
+            Some(ItemStack {
+                block: DIRT,
+                count: 5
+            })
+        );
+        assert_eq!(
+            inv.slots[1],
+            Some(ItemStack {
+                block: STONE,
+                count: 10
+            })
+        );
+
+        // Into an empty slot.
+        inv.swap(1, 20);
+        assert_eq!(inv.slots[1], None);
+        assert_eq!(
+            inv.slots[20],
+            Some(ItemStack {
+                block: STONE,
+                count: 10
+            })
+        );
+
+        // Stacks of the same block merge as far as they fit, the rest stays behind.
+        inv.slots[3] = Some(ItemStack {
+            block: STONE,
+            count: 60,
+        });
+        inv.swap(20, 3);
+        assert_eq!(
+            inv.slots[3],
+            Some(ItemStack {
+                block: STONE,
+                count: MAX_STACK
+            })
+        );
+        assert_eq!(
+            inv.slots[20],
+            Some(ItemStack {
+                block: STONE,
+                count: 6
+            })
+        );
+        inv.slots[3] = Some(ItemStack {
+            block: STONE,
+            count: 1,
+        });
+        inv.swap(20, 3);
+        assert_eq!(
+            inv.slots[3],
+            Some(ItemStack {
+                block: STONE,
+                count: 7
+            })
+        );
+        assert_eq!(inv.slots[20], None);
+
+        // Swapping a slot with itself changes nothing.
+        let before = inv.clone();
+        inv.swap(3, 3);
+        assert_eq!(inv, before);
+    }
+
+    #[test]
+    fn scrolling_wraps_around_the_hotbar() {
+        let mut inv = Inventory::empty();
+        inv.scroll(1);
+        assert_eq!(inv.selected, 1);
+        inv.scroll(-2);
+        assert_eq!(inv.selected, HOTBAR_SLOTS - 1);
+        inv.scroll(1);
+        assert_eq!(inv.selected, 0);
+        inv.scroll(HOTBAR_SLOTS as i32 * 3 + 4);
+        assert_eq!(inv.selected, 4);
+
+        // Selecting a slot outside the hotbar is ignored.
+        inv.select(HOTBAR_SLOTS);
+        assert_eq!(inv.selected, 4);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::*;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+
+// The size of a tile of blocks.png in pixels.
+const ATLAS_TILE_SIZE: f32 = 32.0;
+const SLOT_SIZE: f32 = 52.0;
+const ICON_SIZE: f32 = 36.0;
+const SLOT_BORDER: f32 = 3.0;
+const SLOT_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.55);
+const BORDER_COLOR: Color = Color::rgba(0.25, 0.25, 0.25, 0.9);
+const SELECTED_BORDER_COLOR: Color = Color::WHITE;
+const HELD_BORDER_COLOR: Color = Color::YELLOW;
+const SCREEN_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+
+// Whether the inventory screen is open, and the slot the player picked up (by clicking it) to
+// move somewhere else.
+#[derive(Resource, Default)]
+pub struct InventoryScreen {
+    pub open: bool,
+    pub held: Option<usize>,
+}
+
+// The block icons, cut out of the block textures.
+#[derive(Resource)]
+pub(super) struct IconAtlas(Handle<TextureAtlas>);
+
+// The button that shows an inventory slot, and the icon and stack size in it.
+#[derive(Component)]
+pub(super) struct SlotUi(usize);
+
+#[derive(Component)]
+pub(super) struct SlotIcon(usize);
+
+#[derive(Component)]
+pub(super) struct SlotCount(usize);
+
+// The part of the inventory that is only shown while the inventory screen is open.
+#[derive(Component)]
+pub(super) struct InventoryPanel;
+
+fn spawn_slot(parent: &mut ChildBuilder, slot: usize, atlas: &Handle<TextureAtlas>) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(SLOT_SIZE),
+                    height: Val::Px(SLOT_SIZE),
+                    margin: UiRect::all(Val::Px(2.0)),
+                    border: UiRect::all(Val::Px(SLOT_BORDER)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: SLOT_COLOR.into(),
+                border_color: BORDER_COLOR.into(),
+                ..default()
+            },
+            SlotUi(slot),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                AtlasImageBundle {
+                    style: Style {
+                        width: Val::Px(ICON_SIZE),
+                        height: Val::Px(ICON_SIZE),
+                        ..default()
+                    },
+                    texture_atlas: atlas.clone(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                SlotIcon(slot),
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 18.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(2.0),
+                    bottom: Val::Px(0.0),
+                    ..default()
+                }),
+                SlotCount(slot),
+            ));
+        });
+}
+
+fn spawn_row(
+    parent: &mut ChildBuilder,
+    slots: std::ops::Range<usize>,
+    atlas: &Handle<TextureAtlas>,
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for slot in slots {
+                spawn_slot(parent, slot, atlas);
+            }
+        });
+}
+
+pub(super) fn setup_inventory_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut atlases: ResMut<Assets<TextureAtlas>>,
+    breg: Res<BlockRegistry>,
+) {
+    let atlas = atlases.add(TextureAtlas::from_grid(
+        asset_server.load("blocks.png"),
+        Vec2::splat(ATLAS_TILE_SIZE),
+        breg.atlas_size[0] as usize,
+        breg.atlas_size[1] as usize,
+        None,
+        None,
+    ));
+
+    // The hotbar, at the bottom of the screen.
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                bottom: Val::Px(8.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| spawn_row(parent, 0..HOTBAR_SLOTS, &atlas));
+
+    // The rest of the inventory, in the middle of the screen.
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: SCREEN_COLOR.into(),
+                ..default()
+            },
+            InventoryPanel,
+        ))
+        .with_children(|parent| {
+            for row in (HOTBAR_SLOTS..INVENTORY_SLOTS).step_by(HOTBAR_SLOTS) {
+                spawn_row(parent, row..row + HOTBAR_SLOTS, &atlas);
+            }
+        });
+
+    commands.insert_resource(IconAtlas(atlas));
+}
+
+// Open or close the inventory screen. The cursor is let go while the screen is open, so the
+// player can click the slots, and grabbed again when it closes.
+pub(super) fn toggle_inventory_screen(
+    keys: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut screen: ResMut<InventoryScreen>,
+    mut panel: Query<&mut Style, With<InventoryPanel>>,
+    mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if !keys.just_pressed(key_bindings.toggle_inventory) {
+        return;
+    }
+    screen.open = !screen.open;
+    screen.held = None;
+    if let Ok(mut style) = panel.get_single_mut() {
+        style.display = if screen.open {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+    if let Ok(mut window) = primary_window.get_single_mut() {
+        window.cursor.grab_mode = if screen.open {
+            CursorGrabMode::None
+        } else {
+            CursorGrabMode::Confined
+        };
+        window.cursor.visible = screen.open;
+    }
+}
+
+// While the inventory screen is open, clicking a slot picks it up, and clicking another slot puts
+// it down there.
+pub(super) fn click_slots(
+    mut inv: ResMut<Inventory>,
+    mut screen: ResMut<InventoryScreen>,
+    slots: Query<(&Interaction, &SlotUi), Changed<Interaction>>,
+) {
+    if !screen.open {
+        return;
+    }
+    for (interaction, slot) in slots.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match screen.held.take() {
+            Some(held) => inv.swap(held, slot.0),
+            None if inv.slots[slot.0].is_some() => screen.held = Some(slot.0),
+            None => {}
+        }
+    }
+}
+
+// Show the contents of the inventory in the slots.
+pub(super) fn update_slots(
+    inv: Res<Inventory>,
+    screen: Res<InventoryScreen>,
+    breg: Res<BlockRegistry>,
+    mut slots: Query<(&SlotUi, &mut BorderColor)>,
+    mut icons: Query<(&SlotIcon, &mut UiTextureAtlasImage, &mut Visibility)>,
+    mut counts: Query<(&SlotCount, &mut Text)>,
+) {
+    if !inv.is_changed() && !screen.is_changed() && !breg.is_changed() {
+        return;
+    }
+    for (slot, mut border) in slots.iter_mut() {
+        border.0 = if screen.held == Some(slot.0) {
+            HELD_BORDER_COLOR
+        } else if inv.selected == slot.0 {
+            SELECTED_BORDER_COLOR
+        } else {
+            BORDER_COLOR
+        };
+    }
+    for (slot, mut image, mut visibility) in icons.iter_mut() {
+        match inv.slots[slot.0].and_then(|stack| breg.get(stack.block)) {
+            Some(registered) => {
+                image.index =
+                    (registered.icon[1] * breg.atlas_size[0] + registered.icon[0]) as usize;
+                *visibility = Visibility::Inherited;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+    for (slot, mut text) in counts.iter_mut() {
+        text.sections[0].value = match inv.slots[slot.0] {
+            Some(stack) if stack.count > 1 => stack.count.to_string(),
+            _ => String::new(),
+        };
+    }
+}
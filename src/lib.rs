@@ -0,0 +1,53 @@
+#![allow(dead_code, unused_variables, unused_imports)]
+// The modules glob import each other through the crate root, the names they share are never used
+// from it.
+#![allow(hidden_glob_reexports, ambiguous_glob_reexports)]
+mod add_break_blocks;
+mod block_reg;
+mod chunk;
+mod client;
+mod console;
+mod debug_3d;
+mod edit_journal;
+mod fluid;
+mod game_mode;
+mod inventory;
+mod light;
+mod net;
+mod player;
+mod raycast;
+mod selection;
+mod server;
+mod settings;
+mod simulation;
+mod sky;
+mod utils;
+mod voxel_world;
+mod world_edit;
+
+pub use add_break_blocks::*;
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_meshem::prelude::*;
+pub use block_reg::*;
+pub use chunk::*;
+pub use client::*;
+pub use console::*;
+#[allow(unused_imports)]
+pub use debug_3d::*;
+pub use edit_journal::*;
+pub use fluid::*;
+pub use game_mode::*;
+pub use inventory::*;
+pub use light::*;
+pub use net::*;
+pub use player::*;
+pub use raycast::*;
+pub use selection::*;
+pub use server::*;
+pub use settings::*;
+pub use simulation::*;
+pub use sky::*;
+use std::sync::Arc;
+pub use utils::*;
+pub use voxel_world::*;
+pub use world_edit::*;
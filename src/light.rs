@@ -0,0 +1,735 @@
+use crate::*;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::utils::HashSet;
+use std::collections::VecDeque;
+
+pub const MAX_LIGHT: u8 = 15;
+// How much darker each light level is than the one above it.
+const LIGHT_FALLOFF: f32 = 0.8;
+// Even blocks with no light at all are a bit visible.
+const MIN_LIGHT_FACTOR: f32 = 0.08;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightChannel {
+    Sky,
+    Block,
+}
+
+const CHANNELS: [LightChannel; 2] = [LightChannel::Sky, LightChannel::Block];
+
+// The light level of every block in a chunk, the sky light is stored in the high 4 bits and the
+// block light in the low 4 bits.
+#[derive(Clone)]
+pub struct LightGrid(pub [u8; CHUNK_LEN]);
+
+impl Default for LightGrid {
+    fn default() -> Self {
+        LightGrid([0; CHUNK_LEN])
+    }
+}
+
+impl LightGrid {
+    pub fn get(&self, index: usize, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Sky => self.0[index] >> 4,
+            LightChannel::Block => self.0[index] & 0xF,
+        }
+    }
+
+    pub fn set(&mut self, index: usize, channel: LightChannel, level: u8) {
+        self.0[index] = match channel {
+            LightChannel::Sky => (self.0[index] & 0xF) | (level << 4),
+            LightChannel::Block => (self.0[index] & 0xF0) | level,
+        };
+    }
+}
+
+// Anything that blocks and light levels can be read from and written to, in world coordinates.
+// Blocks that aren't loaded are None, light doesn't spread into them.
+pub trait LightWorld {
+    fn block(&self, pos: [i32; 3]) -> Option<Block>;
+    fn light(&self, pos: [i32; 3], channel: LightChannel) -> u8;
+    fn set_light(&mut self, pos: [i32; 3], channel: LightChannel, level: u8);
+}
+
+// The level light has after spreading from a block with `level` in the direction of `face`. Sky
+// light at full strength goes straight down without getting weaker.
+fn spread_level(level: u8, channel: LightChannel, face: Face) -> u8 {
+    if channel == LightChannel::Sky && face == Bottom && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+// Flood fill light outwards from the blocks in the queue.
+pub fn propagate_light(
+    world: &mut impl LightWorld,
+    breg: &BlockRegistry,
+    channel: LightChannel,
+    mut queue: VecDeque<[i32; 3]>,
+) {
+    while let Some(pos) = queue.pop_front() {
+        let level = world.light(pos, channel);
+        if level <= 1 {
+            continue;
+        }
+        for i in 0..6 {
+            let face = Face::from(i);
+            let neighbor = adjacent_cords(pos, face);
+            match world.block(neighbor) {
+                Some(block) if !breg.is_opaque(block) => {}
+                _ => continue,
+            }
+            let new_level = spread_level(level, channel, face);
+            if world.light(neighbor, channel) < new_level {
+                world.set_light(neighbor, channel, new_level);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+}
+
+// Remove the light that came from the block at `pos`, and return the blocks around the darkened
+// area that are lit from somewhere else, light has to be spread from them again to fill it.
+pub fn remove_light(
+    world: &mut impl LightWorld,
+    channel: LightChannel,
+    pos: [i32; 3],
+) -> VecDeque<[i32; 3]> {
+    let mut refill = VecDeque::new();
+    let mut queue = VecDeque::from([(pos, world.light(pos, channel))]);
+    world.set_light(pos, channel, 0);
+    while let Some((pos, level)) = queue.pop_front() {
+        for i in 0..6 {
+            let face = Face::from(i);
+            let neighbor = adjacent_cords(pos, face);
+            if world.block(neighbor).is_none() {
+                continue;
+            }
+            let neighbor_level = world.light(neighbor, channel);
+            if neighbor_level == 0 {
+                continue;
+            }
+            // The neighbor was lit by this block if its light is weaker, or if it's sky light
+            // coming straight down.
+            if neighbor_level < level || spread_level(level, channel, face) == neighbor_level {
+                world.set_light(neighbor, channel, 0);
+                queue.push_back((neighbor, neighbor_level));
+            } else {
+                refill.push_back(neighbor);
+            }
+        }
+    }
+    refill
+}
+
+// Update the light around a block that was just changed to `new`.
+pub fn update_light_at(
+    world: &mut impl LightWorld,
+    breg: &BlockRegistry,
+    pos: [i32; 3],
+    new: Block,
+) {
+    for channel in CHANNELS {
+        let mut refill = remove_light(world, channel, pos);
+        if channel == LightChannel::Block && breg.light_emission(new) > 0 {
+            world.set_light(pos, channel, breg.light_emission(new));
+            refill.push_back(pos);
+        }
+        if !breg.is_opaque(new) {
+            // Let the light around the block spread into it.
+            for i in 0..6 {
+                let neighbor = adjacent_cords(pos, Face::from(i));
+                if world.light(neighbor, channel) > 0 {
+                    refill.push_back(neighbor);
+                }
+            }
+        }
+        propagate_light(world, breg, channel, refill);
+    }
+}
+
+// A single chunk on its own, used to light a chunk before it is spawned.
+struct LoneChunk<'a> {
+    cords: [i32; 3],
+    grid: &'a ChunkStorage,
+    light: &'a mut LightGrid,
+}
+
+impl LoneChunk<'_> {
+    fn index(&self, pos: [i32; 3]) -> Option<usize> {
+        let (chunk, local) = block_to_chunk_position(pos);
+        (chunk == self.cords).then(|| one_d_cords(local, CHUNK_DIMS))
+    }
+}
+
+impl LightWorld for LoneChunk<'_> {
+    fn block(&self, pos: [i32; 3]) -> Option<Block> {
+        Some(self.grid.get(self.index(pos)?))
+    }
+
+    fn light(&self, pos: [i32; 3], channel: LightChannel) -> u8 {
+        self.index(pos).map_or(0, |i| self.light.get(i, channel))
+    }
+
+    fn set_light(&mut self, pos: [i32; 3], channel: LightChannel, level: u8) {
+        if let Some(i) = self.index(pos) {
+            self.light.set(i, channel, level);
+        }
+    }
+}
+
+// The light of a chunk from the blocks in it alone. Sky light enters from the top of the columns
+// that are open to the sky (`sky_exposed`, indexed by x + z * WIDTH). The light from the
+// neighbouring chunks is spread into it after it is spawned.
+pub fn light_chunk(
+    cords: [i32; 3],
+    grid: &ChunkStorage,
+    sky_exposed: &[bool; WIDTH * LENGTH],
+    breg: &BlockRegistry,
+) -> LightGrid {
+    let mut light = LightGrid::default();
+    let mut sky_queue = VecDeque::new();
+    let mut block_queue = VecDeque::new();
+    for z in 0..LENGTH {
+        for x in 0..WIDTH {
+            let mut sky = sky_exposed[x + z * WIDTH];
+            for y in (0..HEIGHT).rev() {
+                let index = one_d_cords([x, y, z], CHUNK_DIMS);
+                let block = grid.get(index);
+                sky &= !breg.is_opaque(block);
+                let pos = chunk_position_to_block(cords, [x, y, z]);
+                if sky {
+                    light.set(index, LightChannel::Sky, MAX_LIGHT);
+                    sky_queue.push_back(pos);
+                }
+                if breg.light_emission(block) > 0 {
+                    light.set(index, LightChannel::Block, breg.light_emission(block));
+                    block_queue.push_back(pos);
+                }
+            }
+        }
+    }
+    let mut world = LoneChunk {
+        cords,
+        grid,
+        light: &mut light,
+    };
+    propagate_light(&mut world, breg, LightChannel::Sky, sky_queue);
+    propagate_light(&mut world, breg, LightChannel::Block, block_queue);
+    light
+}
+
+// How bright a block with the given light levels looks.
+pub fn light_factor(sky: u8, block: u8) -> f32 {
+    let level = sky.max(block);
+    LIGHT_FALLOFF
+        .powi((MAX_LIGHT - level) as i32)
+        .max(MIN_LIGHT_FACTOR)
+}
+
+// Multiply the vertex colors of a chunk's mesh by the light of the blocks in front of each face.
+// Every vertex is lit by the average of the (up to) 4 blocks in front of its face that touch it,
+// which makes the light smooth across faces. `light_at` takes world coordinates and returns
+// None for opaque blocks.
+pub fn bake_light(
+    mesh: &mut Mesh,
+    cords: [i32; 3],
+    light_at: impl Fn([i32; 3]) -> Option<(u8, u8)>,
+) {
+    let (
+        Some(VertexAttributeValues::Float32x3(positions)),
+        Some(VertexAttributeValues::Float32x3(normals)),
+    ) = (
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+        mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+    )
+    else {
+        return;
+    };
+    let origin = chunk_position_to_block(cords, [0, 0, 0]);
+    let factors: Vec<f32> = positions
+        .iter()
+        .zip(normals.iter())
+        .map(|(pos, normal)| {
+            let (mut total, mut samples) = (0.0, 0);
+            // The point in front of the face, at the height of the centers of the blocks there.
+            let front = Vec3::from(*pos) + Vec3::from(*normal) * 0.5;
+            let normal_axis = (0..3)
+                .max_by(|a, b| normal[*a].abs().total_cmp(&normal[*b].abs()))
+                .unwrap_or(0);
+            for i in 0..4 {
+                let mut sample = front;
+                let mut tangent = 0;
+                for axis in 0..3 {
+                    if axis == normal_axis {
+                        continue;
+                    }
+                    sample[axis] += if (i >> tangent) & 1 == 0 { -0.5 } else { 0.5 };
+                    tangent += 1;
+                }
+                let block = [
+                    origin[0] + sample.x.round() as i32,
+                    origin[1] + sample.y.round() as i32,
+                    origin[2] + sample.z.round() as i32,
+                ];
+                if let Some((sky, block)) = light_at(block) {
+                    total += light_factor(sky, block);
+                    samples += 1;
+                }
+            }
+            if samples == 0 {
+                MIN_LIGHT_FACTOR
+            } else {
+                total / samples as f32
+            }
+        })
+        .collect();
+    if let Some(VertexAttributeValues::Float32x4(colors)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_COLOR)
+    {
+        for (color, factor) in colors.iter_mut().zip(factors) {
+            color[0] *= factor;
+            color[1] *= factor;
+            color[2] *= factor;
+        }
+    }
+}
+
+// Which columns of the chunk at `cords` the sky light comes down into, from the bottom of the chunk
+// above it (indexed by x + z * WIDTH). None if the chunk above isn't loaded.
+pub fn sky_from_above(
+    chunks: &Query<&Chunk>,
+    chunk_map: &ChunkMap,
+    cords: [i32; 3],
+) -> Option<[bool; WIDTH * LENGTH]> {
+    if cords[1] == WORLD_HEIGHT_CHUNKS - 1 {
+        return Some([true; WIDTH * LENGTH]);
+    }
+    let above = chunks
+        .get(chunk_map.get_ent(adjacent_cords(cords, Top))?)
+        .ok()?;
+    let mut sky = [false; WIDTH * LENGTH];
+    for z in 0..LENGTH {
+        for x in 0..WIDTH {
+            let index = one_d_cords([x, 0, z], CHUNK_DIMS);
+            sky[x + z * WIDTH] = above.light.get(index, LightChannel::Sky) == MAX_LIGHT;
+        }
+    }
+    Some(sky)
+}
+
+// Light a chunk that was just generated or loaded, and bake the light into its mesh if it has one
+// (chunks that aren't rendered don't). Which columns
+// are open to the sky is known from the chunk above when it's loaded (`sky_from_above`), otherwise
+// the generator is used to guess it. The generator also guesses how lit the blocks right outside of
+// the chunk are.
+pub fn light_new_chunk(
+    cords: [i32; 3],
+    grid: &ChunkStorage,
+    generator: &TerrainGenerator,
+    breg: &BlockRegistry,
+    mesh: Option<&mut Mesh>,
+    sky_from_above: Option<&[bool; WIDTH * LENGTH]>,
+) -> LightGrid {
+    let min = chunk_position_to_block(cords, [0, 0, 0]);
+    let max = chunk_position_to_block(cords, [WIDTH - 1, HEIGHT - 1, LENGTH - 1]);
+    // The surface of the columns of the chunk, and of the columns right around it.
+    let margin_width = WIDTH + 2;
+    let mut surface = vec![0; margin_width * (LENGTH + 2)];
+    for z in 0..LENGTH + 2 {
+        for x in 0..margin_width {
+            surface[x + z * margin_width] =
+                generator.surface_height(min[0] + x as i32 - 1, min[2] + z as i32 - 1);
+        }
+    }
+    let surface_at = |x: i32, z: i32| {
+        surface[(x - min[0] + 1) as usize + (z - min[2] + 1) as usize * margin_width]
+    };
+
+    let sky_exposed = sky_from_above.copied().unwrap_or_else(|| {
+        let mut sky_exposed = [false; WIDTH * LENGTH];
+        for z in 0..LENGTH {
+            for x in 0..WIDTH {
+                sky_exposed[x + z * WIDTH] =
+                    surface_at(min[0] + x as i32, min[2] + z as i32) <= max[1];
+            }
+        }
+        sky_exposed
+    });
+    let light = light_chunk(cords, grid, &sky_exposed, breg);
+    let Some(mesh) = mesh else {
+        return light;
+    };
+    bake_light(mesh, cords, |pos| {
+        let (chunk, local) = block_to_chunk_position(pos);
+        if chunk == cords {
+            let index = one_d_cords(local, CHUNK_DIMS);
+            (!breg.is_opaque(grid.get(index))).then(|| {
+                (
+                    light.get(index, LightChannel::Sky),
+                    light.get(index, LightChannel::Block),
+                )
+            })
+        } else {
+            // Everything under the surface is assumed to be solid.
+            (pos[1] > surface_at(pos[0], pos[2])).then_some((MAX_LIGHT, 0))
+        }
+    });
+    light
+}
+
+// The (sky, block) light of a block in the loaded chunks, or None if the block is opaque. Used to
+// bake the light into the meshes of chunks that are already spawned.
+pub fn light_of_block(
+    chunks: &Query<&Chunk>,
+    chunk_map: &ChunkMap,
+    breg: &BlockRegistry,
+    pos: [i32; 3],
+) -> Option<(u8, u8)> {
+    let (chunk_cords, local) = block_to_chunk_position(pos);
+    if chunk_cords[1] >= WORLD_HEIGHT_CHUNKS {
+        return Some((MAX_LIGHT, 0));
+    }
+    let ent = chunk_map.get_ent(chunk_cords)?;
+    let chunk = chunks.get(ent).ok()?;
+    let index = one_d_cords(local, CHUNK_DIMS);
+    (!breg.is_opaque(chunk.grid.get(index))).then(|| {
+        (
+            chunk.light.get(index, LightChannel::Sky),
+            chunk.light.get(index, LightChannel::Block),
+        )
+    })
+}
+
+// All the loaded chunks, as one world for the light engine. Remembers which chunks had their light
+// changed so they can be remeshed.
+pub struct LoadedWorld<'a, 'w, 's, 'c> {
+    pub chunks: &'a mut Query<'w, 's, &'c mut Chunk>,
+    pub chunk_map: &'a ChunkMap,
+    pub changed: HashSet<[i32; 3]>,
+}
+
+impl LoadedWorld<'_, '_, '_, '_> {
+    fn chunk_ent(&self, chunk: [i32; 3]) -> Option<Entity> {
+        self.chunk_map.get_ent(chunk)
+    }
+}
+
+impl LightWorld for LoadedWorld<'_, '_, '_, '_> {
+    fn block(&self, pos: [i32; 3]) -> Option<Block> {
+        let (chunk, local) = block_to_chunk_position(pos);
+        let chunk = self.chunks.get(self.chunk_ent(chunk)?).ok()?;
+        Some(chunk.grid.get(one_d_cords(local, CHUNK_DIMS)))
+    }
+
+    fn light(&self, pos: [i32; 3], channel: LightChannel) -> u8 {
+        let (chunk_cords, local) = block_to_chunk_position(pos);
+        // Above the top of the world there is nothing but sky.
+        if chunk_cords[1] >= WORLD_HEIGHT_CHUNKS {
+            return if channel == LightChannel::Sky {
+                MAX_LIGHT
+            } else {
+                0
+            };
+        }
+        self.chunk_ent(chunk_cords)
+            .and_then(|ent| self.chunks.get(ent).ok())
+            .map_or(0, |chunk| {
+                chunk.light.get(one_d_cords(local, CHUNK_DIMS), channel)
+            })
+    }
+
+    fn set_light(&mut self, pos: [i32; 3], channel: LightChannel, level: u8) {
+        let (chunk_cords, local) = block_to_chunk_position(pos);
+        let Some(ent) = self.chunk_ent(chunk_cords) else {
+            return;
+        };
+        if let Ok(mut chunk) = self.chunks.get_mut(ent) {
+            let index = one_d_cords(local, CHUNK_DIMS);
+            if chunk.light.get(index, channel) != level {
+                chunk.light.set(index, channel, level);
+                self.changed.insert(chunk_cords);
+            }
+        }
+    }
+}
+
+pub struct LightPlugin;
+
+impl Plugin for LightPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(Update, spread_light_to_new_chunks);
+        app.add_systems(PostUpdate, update_light.after(handle_block_break_place));
+
+        // Resources
+        app.init_resource::<LightUpdates>();
+    }
+}
+
+// Blocks that were changed this frame, their light is updated by `update_light`.
+#[derive(Resource, Default)]
+pub struct LightUpdates(pub Vec<([i32; 3], Block)>);
+
+// Chunks whose light was changed by the chunks that were spawned around them. Their meshes are
+// baked again by `remesh_relit_chunks`.
+#[derive(Component)]
+pub struct LightChanged;
+
+// When a chunk is spawned, spread the light between it and the chunks around it.
+pub(crate) fn spread_light_to_new_chunks(
+    mut chunks: Query<&mut Chunk>,
+    chunk_map: Res<ChunkMap>,
+    breg: Res<BlockRegistry>,
+    mut commands: Commands,
+) {
+    // A separate `Added<Chunk>` query would conflict with the mutable one.
+    let new_chunks: Vec<[i32; 3]> = chunks
+        .iter_mut()
+        .filter(|chunk| chunk.is_added())
+        .map(|chunk| chunk.cords)
+        .collect();
+    if new_chunks.is_empty() {
+        return;
+    }
+    let mut world = LoadedWorld {
+        chunks: &mut chunks,
+        chunk_map: &chunk_map,
+        changed: HashSet::new(),
+    };
+    for cords in new_chunks {
+        // The chunk below might have been lit before this one was loaded, with a guess of which of
+        // its columns are open to the sky. The sky light that this chunk doesn't let through is
+        // taken out of it again.
+        let mut refill = VecDeque::new();
+        for z in 0..LENGTH {
+            for x in 0..WIDTH {
+                let pos = chunk_position_to_block(cords, [x, 0, z]);
+                let below = adjacent_cords(pos, Bottom);
+                if world.light(below, LightChannel::Sky) == MAX_LIGHT
+                    && world.light(pos, LightChannel::Sky) < MAX_LIGHT
+                {
+                    refill.extend(remove_light(&mut world, LightChannel::Sky, below));
+                }
+            }
+        }
+        propagate_light(&mut world, &breg, LightChannel::Sky, refill);
+        for i in 0..6 {
+            let face = Face::from(i);
+            for svox in iter_faces_of_chunk(CHUNK_DIMS, face) {
+                let pos = chunk_position_to_block(cords, three_d_cords(svox, CHUNK_DIMS));
+                let neighbor = adjacent_cords(pos, face);
+                for channel in CHANNELS {
+                    propagate_light(&mut world, &breg, channel, VecDeque::from([pos, neighbor]));
+                }
+            }
+        }
+    }
+    for cords in world.changed {
+        if let Some(ent) = chunk_map.get_ent(cords) {
+            commands.entity(ent).insert(LightChanged);
+        }
+    }
+}
+
+// Bake the light into the meshes of the chunks whose light was changed by their new neighbours.
+// A chunk is only meshed again once none of the chunks around it are loading anymore, so it isn't
+// meshed again for each of them, and only a few chunks are meshed every frame, so loading doesn't
+// stall.
+pub(crate) fn remesh_relit_chunks(
+    relit: Query<(Entity, &Chunk), With<LightChanged>>,
+    chunk_map: Res<ChunkMap>,
+    budget: Res<ChunkLoadBudget>,
+    mut commands: Commands,
+) {
+    let loading = |cords| {
+        matches!(
+            chunk_map.state(cords),
+            Some(ChunkState::Queued | ChunkState::Generating | ChunkState::Meshing)
+        )
+    };
+    let settled = relit
+        .iter()
+        .filter(|(_, chunk)| (0..6).all(|i| !loading(adjacent_cords(chunk.cords, Face::from(i)))));
+    for (ent, _) in settled.take(budget.spawns_per_frame) {
+        commands
+            .entity(ent)
+            .remove::<LightChanged>()
+            .insert(ToRemesh);
+    }
+}
+
+// Update the light around the blocks that were changed, and remesh the chunks that were changed or
+// had their light changed.
+pub(crate) fn update_light(
+    mut light_updates: ResMut<LightUpdates>,
+    mut chunks: Query<&mut Chunk>,
+    chunk_map: Res<ChunkMap>,
+    breg: Res<BlockRegistry>,
+    mut commands: Commands,
+) {
+    if light_updates.0.is_empty() {
+        return;
+    }
+    let mut world = LoadedWorld {
+        chunks: &mut chunks,
+        chunk_map: &chunk_map,
+        changed: HashSet::new(),
+    };
+    for (pos, new) in light_updates.0.drain(..) {
+        update_light_at(&mut world, &breg, pos, new);
+        // The faces around the changed block are new, and were meshed without light.
+        let (chunk, _) = block_to_chunk_position(pos);
+        world.changed.insert(chunk);
+        for i in 0..6 {
+            world
+                .changed
+                .insert(block_to_chunk_position(adjacent_cords(pos, Face::from(i))).0);
+        }
+    }
+    for cords in world.changed {
+        if let Some(ent) = chunk_map.get_ent(cords) {
+            commands.entity(ent).insert(ToRemesh);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::utils::HashMap;
+
+    // A few chunks, without an ECS world around them.
+    struct TestWorld {
+        chunks: HashMap<[i32; 3], (ChunkStorage, LightGrid)>,
+        breg: BlockRegistry,
+    }
+
+    impl TestWorld {
+        // Chunks of air at `cords`, lit by the sky from above.
+        fn new(cords: &[[i32; 3]]) -> Self {
+            let breg = BlockRegistry::default();
+            let grid = ChunkStorage::uniform(AIR);
+            let chunks = cords
+                .iter()
+                .map(|cords| {
+                    let light = light_chunk(*cords, &grid, &[true; WIDTH * LENGTH], &breg);
+                    (*cords, (grid.clone(), light))
+                })
+                .collect();
+            TestWorld { chunks, breg }
+        }
+
+        fn set_block(&mut self, pos: [i32; 3], block: Block) {
+            let (cords, local) = block_to_chunk_position(pos);
+            let (grid, _) = self.chunks.get_mut(&cords).expect("The chunk is loaded");
+            grid.set(one_d_cords(local, CHUNK_DIMS), block);
+            let breg = self.breg.clone();
+            update_light_at(self, &breg, pos, block);
+        }
+    }
+
+    impl LightWorld for TestWorld {
+        fn block(&self, pos: [i32; 3]) -> Option<Block> {
+            let (cords, local) = block_to_chunk_position(pos);
+            let (grid, _) = self.chunks.get(&cords)?;
+            Some(grid.get(one_d_cords(local, CHUNK_DIMS)))
+        }
+
+        fn light(&self, pos: [i32; 3], channel: LightChannel) -> u8 {
+            let (cords, local) = block_to_chunk_position(pos);
+            self.chunks.get(&cords).map_or(0, |(_, light)| {
+                light.get(one_d_cords(local, CHUNK_DIMS), channel)
+            })
+        }
+
+        fn set_light(&mut self, pos: [i32; 3], channel: LightChannel, level: u8) {
+            let (cords, local) = block_to_chunk_position(pos);
+            if let Some((_, light)) = self.chunks.get_mut(&cords) {
+                light.set(one_d_cords(local, CHUNK_DIMS), channel, level);
+            }
+        }
+    }
+
+    #[test]
+    fn placing_and_removing_a_light_source() {
+        let mut world = TestWorld::new(&[[0, 0, 0]]);
+        let emission = world.breg.light_emission(GLOWSTONE);
+        assert!(emission > 3);
+        let block_light = |world: &TestWorld, pos| world.light(pos, LightChannel::Block);
+        world.set_block([8, 8, 8], GLOWSTONE);
+        assert_eq!(block_light(&world, [8, 8, 8]), emission);
+        assert_eq!(block_light(&world, [9, 8, 8]), emission - 1);
+        assert_eq!(block_light(&world, [8, 5, 8]), emission - 3);
+        // The light goes around corners, a block away diagonally is two steps away.
+        assert_eq!(block_light(&world, [9, 9, 8]), emission - 2);
+
+        // A wall next to the light stops it, the blocks behind it are lit around the wall.
+        world.set_block([10, 8, 8], STONE);
+        assert_eq!(block_light(&world, [10, 8, 8]), 0);
+        assert_eq!(block_light(&world, [11, 8, 8]), emission - 5);
+
+        world.set_block([8, 8, 8], AIR);
+        for (cords, (_, light)) in world.chunks.iter() {
+            for index in 0..CHUNK_LEN {
+                assert_eq!(light.get(index, LightChannel::Block), 0, "{:?}", cords);
+            }
+        }
+    }
+
+    #[test]
+    fn sky_light_under_a_new_block() {
+        let mut world = TestWorld::new(&[[0, 0, 0]]);
+        let sky_light = |world: &TestWorld, pos| world.light(pos, LightChannel::Sky);
+        assert_eq!(sky_light(&world, [8, 0, 8]), MAX_LIGHT);
+
+        // Under a single block, the light comes in from the sides.
+        world.set_block([8, 10, 8], STONE);
+        assert_eq!(sky_light(&world, [8, 10, 8]), 0);
+        assert_eq!(sky_light(&world, [8, 9, 8]), MAX_LIGHT - 1);
+        assert_eq!(sky_light(&world, [8, 0, 8]), MAX_LIGHT - 1);
+
+        // Under a roof, the light gets darker the further it is from the edge.
+        for x in 6..=10 {
+            for z in 6..=10 {
+                world.set_block([x, 10, z], STONE);
+            }
+        }
+        assert_eq!(sky_light(&world, [8, 9, 8]), MAX_LIGHT - 3);
+        assert_eq!(sky_light(&world, [7, 9, 8]), MAX_LIGHT - 2);
+        assert_eq!(sky_light(&world, [11, 9, 8]), MAX_LIGHT);
+
+        // Taking the roof away lets the sky in again.
+        for x in 6..=10 {
+            for z in 6..=10 {
+                world.set_block([x, 10, z], AIR);
+            }
+        }
+        assert_eq!(sky_light(&world, [8, 9, 8]), MAX_LIGHT);
+        assert_eq!(sky_light(&world, [8, 0, 8]), MAX_LIGHT);
+    }
+
+    #[test]
+    fn light_crosses_chunk_borders() {
+        let mut world = TestWorld::new(&[[0, 0, 0], [1, 0, 0], [0, 0, -1], [0, -1, 0]]);
+        let emission = world.breg.light_emission(GLOWSTONE);
+        let block_light = |world: &TestWorld, pos| world.light(pos, LightChannel::Block);
+        world.set_block([15, 8, 0], GLOWSTONE);
+        assert_eq!(block_light(&world, [16, 8, 0]), emission - 1);
+        assert_eq!(block_light(&world, [18, 8, 0]), emission - 3);
+        assert_eq!(block_light(&world, [15, 8, -1]), emission - 1);
+        assert_eq!(block_light(&world, [15, -2, 0]), emission - 10);
+        // Light doesn't spread into chunks that aren't loaded.
+        assert_eq!(block_light(&world, [14, 8, 16]), 0);
+
+        world.set_block([15, 8, 0], AIR);
+        assert_eq!(block_light(&world, [16, 8, 0]), 0);
+        assert_eq!(block_light(&world, [15, 8, -1]), 0);
+
+        // The sky light going down a chunk stops under a block in the chunk above.
+        world.set_block([3, 0, 3], STONE);
+        assert_eq!(world.light([3, -1, 3], LightChannel::Sky), MAX_LIGHT - 1);
+    }
+}
@@ -0,0 +1,514 @@
+use super::*;
+use bevy::app::AppExit;
+use bevy::utils::{Duration, HashMap, HashSet, Instant};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+// How long to wait for the server to accept us when joining.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const AVATAR_COLOR: Color = Color::rgb(0.85, 0.35, 0.25);
+
+// The connection to the server whose world is played. While it exists the world isn't simulated
+// locally, the server sends the chunks and the blocks that change in them.
+#[derive(Resource)]
+pub struct ServerConnection {
+    connection: Connection,
+    backlog: Vec<Vec<u8>>,
+    socket: UdpSocket,
+    pub id: PlayerId,
+    token: u64,
+    // The chunks the server told us to load, the ones that aren't here anymore are not spawned.
+    pub loaded_chunks: HashSet<[i32; 3]>,
+    // Edits that were sent to the server and weren't answered yet.
+    pending_edits: HashSet<[i32; 3]>,
+    // Blocks that changed in chunks that are still being spawned.
+    deferred_blocks: Vec<([i32; 3], Block)>,
+    position_timer: Timer,
+}
+
+impl ServerConnection {
+    // Join the server at `addr`, and wait for it to let us in. Returns the settings of its world.
+    pub fn connect(addr: &str, name: &str) -> Result<(Self, WorldSettings), String> {
+        let server_addr = resolve(addr)?;
+        let stream = TcpStream::connect_timeout(&server_addr, HANDSHAKE_TIMEOUT)
+            .map_err(|e| format!("Couldn't connect to {}: {}", addr, e))?;
+        let mut connection = Connection::new(stream).map_err(|e| e.to_string())?;
+        connection.send(
+            &ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+                name: name.to_string(),
+            }
+            .encode(),
+        );
+
+        let start = Instant::now();
+        let mut frames = loop {
+            connection.flush().map_err(|e| e.to_string())?;
+            let frames = connection.receive().map_err(|e| e.to_string())?;
+            if !frames.is_empty() {
+                break frames;
+            }
+            if start.elapsed() > HANDSHAKE_TIMEOUT {
+                return Err(format!("{} didn't answer", addr));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        // The messages that came right after the welcome are handled once the game starts.
+        let backlog = frames.split_off(1);
+        let (id, token, world_settings) = match ServerMessage::decode(&frames[0]) {
+            Some(ServerMessage::Welcome {
+                id,
+                token,
+                world_settings,
+            }) => (id, token, world_settings),
+            Some(ServerMessage::Disconnect { reason }) => {
+                return Err(format!("The server refused to let us in: {}", reason))
+            }
+            _ => return Err(String::from("The server sent an invalid message")),
+        };
+        let world_settings = ron::from_str(&world_settings)
+            .map_err(|e| format!("The server sent invalid world settings: {}", e))?;
+
+        let local_addr: SocketAddr = match server_addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local_addr).map_err(|e| e.to_string())?;
+        socket.connect(server_addr).map_err(|e| e.to_string())?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        Ok((
+            ServerConnection {
+                connection,
+                backlog,
+                socket,
+                id,
+                token,
+                loaded_chunks: HashSet::new(),
+                pending_edits: HashSet::new(),
+                deferred_blocks: Vec::new(),
+                position_timer: Timer::from_seconds(
+                    1.0 / POSITION_UPDATES_PER_SECOND,
+                    TimerMode::Repeating,
+                ),
+            },
+            world_settings,
+        ))
+    }
+
+    // Whether an edit of the block at `pos` was sent, and the server didn't answer yet.
+    pub fn awaits_reply(&self, pos: [i32; 3]) -> bool {
+        self.pending_edits.contains(&pos)
+    }
+}
+
+// The server listens on the default port, unless the address says otherwise.
+fn resolve(addr: &str) -> Result<SocketAddr, String> {
+    let with_port = match addr.parse::<SocketAddr>() {
+        Ok(addr) => return Ok(addr),
+        Err(_) if addr.contains(':') => addr.to_string(),
+        Err(_) => format!("{}:{}", addr, DEFAULT_PORT),
+    };
+    with_port
+        .to_socket_addrs()
+        .map_err(|e| format!("Couldn't resolve {}: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format!("Couldn't resolve {}", addr))
+}
+
+// Run condition for the systems that simulate the world, a client connected to a server leaves
+// that to the server.
+pub fn world_is_local(connection: Option<Res<ServerConnection>>) -> bool {
+    connection.is_none()
+}
+
+// The other players, as boxes the size of their hitbox.
+#[derive(Resource, Default)]
+struct Avatars(HashMap<PlayerId, Entity>);
+
+#[derive(Resource)]
+struct AvatarAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+// Plays the world of a server. The `ServerConnection` resource has to be inserted, since the world
+// settings come from the server before the app starts.
+pub struct NetClientPlugin;
+
+impl Plugin for NetClientPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(Startup, setup_avatars)
+            .add_systems(
+                Update,
+                (receive_from_server, apply_received_blocks, receive_players).chain(),
+            )
+            .add_systems(
+                PostUpdate,
+                (forward_edits, forward_chat, send_position, flush_connection).chain(),
+            );
+
+        // Resources
+        app.init_resource::<Avatars>();
+    }
+}
+
+fn setup_avatars(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(AvatarAssets {
+        mesh: meshes.add(Mesh::from(shape::Box::new(
+            PLAYER_WIDTH,
+            PLAYER_HEIGHT,
+            PLAYER_WIDTH,
+        ))),
+        material: materials.add(StandardMaterial {
+            base_color: AVATAR_COLOR,
+            perceptual_roughness: 0.9,
+            ..default()
+        }),
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn receive_from_server(
+    mut connection: ResMut<ServerConnection>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    mut chunk_map: ResMut<ChunkMap>,
+    mut avatars: ResMut<Avatars>,
+    mut commands: Commands,
+    mut log: ResMut<ConsoleLog>,
+    mut next_mode: ResMut<NextState<GameMode>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let mut frames = std::mem::take(&mut connection.backlog);
+    match connection.connection.receive() {
+        Ok(received) => frames.extend(received),
+        Err(e) => {
+            error!(
+                "\nIn-Game Error: \nLost the connection to the server: {}",
+                e
+            );
+            exit.send(AppExit);
+            return;
+        }
+    };
+    for frame in frames {
+        match ServerMessage::decode(&frame) {
+            Some(ServerMessage::Chunk { cords, data }) => {
+                let Some(grid) = decode_grid(&data) else {
+                    warn!("The server sent a corrupted chunk {:?}", cords);
+                    continue;
+                };
+                connection.loaded_chunks.insert(cords);
+                chunk_queue.queue_received(&mut chunk_map, cords, grid);
+            }
+            Some(ServerMessage::UnloadChunk { cords }) => {
+                connection.loaded_chunks.remove(&cords);
+                chunk_queue.queue_despawn(&mut chunk_map, cords);
+            }
+            Some(ServerMessage::SetBlock { pos, block }) => {
+                connection.deferred_blocks.push((pos, block));
+            }
+            Some(ServerMessage::PlayerLeft { id }) => {
+                if let Some(avatar) = avatars.0.remove(&id) {
+                    commands.entity(avatar).despawn();
+                }
+            }
+            Some(ServerMessage::Chat { text }) => log.print(text),
+            Some(ServerMessage::GameMode { mode }) => next_mode.set(mode),
+            Some(ServerMessage::Disconnect { reason }) => {
+                error!("\nIn-Game Error: \nDisconnected by the server: {}", reason);
+                exit.send(AppExit);
+                return;
+            }
+            Some(ServerMessage::Welcome { .. }) | None => {
+                warn!("The server sent an invalid message");
+            }
+        }
+    }
+}
+
+// Write the blocks the server changed into the chunks. The blocks of chunks that are still being
+// spawned wait until they are.
+fn apply_received_blocks(
+    mut connection: ResMut<ServerConnection>,
+    mut world: VoxelWorldMut,
+    mut light_updates: ResMut<LightUpdates>,
+    mut edits: EventWriter<BlockEdited>,
+) {
+    let connection = connection.as_mut();
+    let mut deferred = vec![];
+    for (pos, block) in connection.deferred_blocks.drain(..) {
+        if !connection
+            .loaded_chunks
+            .contains(&block_to_chunk_position(pos).0)
+        {
+            continue;
+        }
+        let Some(old) = world.set_block(pos.into(), block) else {
+            deferred.push((pos, block));
+            continue;
+        };
+        // The server answers every edit we asked for, if the block changed the edit was made.
+        let by_player = connection.pending_edits.remove(&pos);
+        if old != block {
+            light_updates.0.push((pos, block));
+            edits.send(BlockEdited {
+                pos,
+                old,
+                new: block,
+                by_player,
+            });
+        }
+    }
+    connection.deferred_blocks = deferred;
+}
+
+fn receive_players(
+    connection: Res<ServerConnection>,
+    mut avatars: ResMut<Avatars>,
+    assets: Res<AvatarAssets>,
+    mut transforms: Query<&mut Transform>,
+    mut commands: Commands,
+) {
+    let mut buf = [0; 1500];
+    // Only the latest positions matter.
+    let mut players = None;
+    loop {
+        match connection.socket.recv(&mut buf) {
+            Ok(len) => {
+                if let Some(Datagram::Players(latest)) = Datagram::decode(&buf[..len]) {
+                    players = Some(latest);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(_) => continue,
+        }
+    }
+    for (id, pos, yaw) in players.into_iter().flatten() {
+        if id == connection.id {
+            continue;
+        }
+        // The position is of the eyes, the box is centered on the middle of the body.
+        let transform =
+            Transform::from_translation(pos - Vec3::Y * (EYE_HEIGHT - PLAYER_HEIGHT / 2.0))
+                .with_rotation(Quat::from_rotation_y(yaw));
+        match avatars.0.get(&id).map(|avatar| transforms.get_mut(*avatar)) {
+            Some(Ok(mut avatar)) => *avatar = transform,
+            _ => {
+                let avatar = commands
+                    .spawn(PbrBundle {
+                        mesh: assets.mesh.clone(),
+                        material: assets.material.clone(),
+                        transform,
+                        ..default()
+                    })
+                    .id();
+                avatars.0.insert(id, avatar);
+            }
+        }
+    }
+}
+
+// The blocks the player breaks and places are sent to the server, which makes the change if it's
+// allowed to.
+fn forward_edits(
+    mut connection: ResMut<ServerConnection>,
+    mut block_change: EventReader<BlockChange>,
+) {
+    for event in block_change.read() {
+        for &(chunk, block, onto) in event.blocks.iter() {
+            let pos = chunk_position_to_block(chunk, three_d_cords(block, CHUNK_DIMS));
+            let onto = onto.map(|(onto_chunk, onto)| {
+                chunk_position_to_block(onto_chunk, three_d_cords(onto, CHUNK_DIMS))
+            });
+            connection.pending_edits.insert(pos);
+            connection.connection.send(
+                &ClientMessage::Edit {
+                    pos,
+                    block: event.block,
+                    onto,
+                }
+                .encode(),
+            );
+        }
+    }
+}
+
+// The chat is sent to the server, which sends it to everyone, us included.
+fn forward_chat(mut connection: ResMut<ServerConnection>, mut chat: EventReader<ChatMessage>) {
+    for message in chat.read() {
+        connection.connection.send(
+            &ClientMessage::Chat {
+                text: message.text.clone(),
+            }
+            .encode(),
+        );
+    }
+}
+
+fn send_position(
+    mut connection: ResMut<ServerConnection>,
+    time: Res<Time>,
+    player: Query<&Transform, With<FlyCam>>,
+) {
+    if !connection.position_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Ok(transform) = player.get_single() else {
+        return;
+    };
+    let (yaw, _, _) = transform.rotation.to_euler(EulerRot::YXZ);
+    let datagram = Datagram::Position {
+        id: connection.id,
+        token: connection.token,
+        pos: transform.translation,
+        yaw,
+    };
+    // Positions that are lost are replaced by the next ones.
+    let _ = connection.socket.send(&datagram.encode());
+}
+
+fn flush_connection(mut connection: ResMut<ServerConnection>, mut exit: EventWriter<AppExit>) {
+    if let Err(e) = connection.connection.flush() {
+        error!(
+            "\nIn-Game Error: \nLost the connection to the server: {}",
+            e
+        );
+        exit.send(AppExit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run the server and read what it sends to `connection` until `done` is true for a message.
+    fn receive_until(
+        server: &mut App,
+        connection: &mut ServerConnection,
+        mut done: impl FnMut(ServerMessage) -> bool,
+    ) {
+        for _ in 0..2000 {
+            server.update();
+            connection.connection.flush().unwrap();
+            let frames = std::mem::take(&mut connection.backlog)
+                .into_iter()
+                .chain(connection.connection.receive().unwrap());
+            for frame in frames {
+                let message =
+                    ServerMessage::decode(&frame).expect("The server sends valid messages");
+                if done(message) {
+                    return;
+                }
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("The server never sent what was expected");
+    }
+
+    // Ask the server to break the block at `pos`, and return what it says the block is now.
+    fn break_block(server: &mut App, connection: &mut ServerConnection, pos: [i32; 3]) -> Block {
+        connection.connection.send(
+            &ClientMessage::Edit {
+                pos,
+                block: AIR,
+                onto: None,
+            }
+            .encode(),
+        );
+        let mut answer = None;
+        receive_until(server, connection, |message| match message {
+            ServerMessage::SetBlock { pos: at, block } if at == pos => {
+                answer = Some(block);
+                true
+            }
+            _ => false,
+        });
+        answer.unwrap()
+    }
+
+    // Switch the player's game mode from the console of the server, and wait until it's told.
+    fn set_game_mode(server: &mut App, connection: &mut ServerConnection, mode: GameMode) {
+        server.world.send_event(ConsoleInput {
+            line: format!("/gamemode {} Tester", format!("{:?}", mode).to_lowercase()),
+            source: CommandSource::Server,
+        });
+        receive_until(server, connection, |message| {
+            message == ServerMessage::GameMode { mode }
+        });
+    }
+
+    // A server and a client in the same process, talking over the loopback interface.
+    #[test]
+    fn joining_and_editing_over_loopback() {
+        let server = NetServer::bind(0).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let save_dir = std::env::temp_dir().join("minecraft_bevy_loopback");
+        let _ = std::fs::remove_dir_all(&save_dir);
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, SimulationPlugin, NetServerPlugin))
+            .insert_resource(server)
+            .insert_resource(WorldSettings {
+                seed: 7,
+                render_distance: 1,
+                ..default()
+            })
+            .insert_resource(WorldSave { dir: save_dir });
+
+        // Joining waits for the server to answer, so the server runs in the meantime.
+        let joining = std::thread::spawn(move || {
+            ServerConnection::connect(&format!("127.0.0.1:{}", port), "Tester")
+        });
+        while !joining.is_finished() {
+            app.update();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let (mut connection, world_settings) = joining.join().unwrap().unwrap();
+        assert_eq!(connection.id, 0);
+        assert_eq!(world_settings.seed, 7);
+
+        // The player spawns right above the surface, the blocks under it are streamed to it.
+        let surface = spawn_point(&world_settings).y.round() as i32 - 2;
+        let (below, under_below) = ([0, surface, 0], [0, surface - 1, 0]);
+        let mut grids = HashMap::new();
+        receive_until(&mut app, &mut connection, |message| {
+            if let ServerMessage::Chunk { cords, data } = message {
+                grids.insert(cords, decode_grid(&data).unwrap());
+            }
+            [below, under_below]
+                .iter()
+                .all(|pos| grids.contains_key(&block_to_chunk_position(*pos).0))
+        });
+        let block_at = |pos: [i32; 3]| {
+            let (cords, local) = block_to_chunk_position(pos);
+            grids[&cords].get(one_d_cords(local, CHUNK_DIMS))
+        };
+        assert_ne!(block_at(below), AIR);
+        assert_ne!(block_at(under_below), AIR);
+
+        // The chunks around the player can be edited once they are all loaded, until then the
+        // edits are rejected.
+        let mut broken = false;
+        for _ in 0..100 {
+            if break_block(&mut app, &mut connection, below) == AIR {
+                broken = true;
+                break;
+            }
+        }
+        assert!(broken);
+
+        // The server decides the game mode, and tells the player. Spectators can't edit, the block
+        // stays what it was.
+        set_game_mode(&mut app, &mut connection, GameMode::Spectator);
+        assert_eq!(
+            break_block(&mut app, &mut connection, under_below),
+            block_at(under_below)
+        );
+        set_game_mode(&mut app, &mut connection, GameMode::Creative);
+        assert_eq!(break_block(&mut app, &mut connection, under_below), AIR);
+    }
+}
@@ -0,0 +1,465 @@
+pub mod client;
+pub mod server;
+use crate::*;
+pub use client::*;
+pub use server::*;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+// The server listens for connections (TCP) and positions (UDP) on the same port.
+pub const DEFAULT_PORT: u16 = 25566;
+// Clients and servers only talk to each other if they speak the same version of the protocol.
+pub const PROTOCOL_VERSION: u32 = 4;
+// Frames bigger than this are treated as a broken connection.
+const MAX_FRAME_LEN: usize = 1 << 20;
+// Positions are sent this many times a second.
+pub const POSITION_UPDATES_PER_SECOND: f32 = 20.0;
+
+pub type PlayerId = u32;
+
+// Messages from the client to the server, over TCP.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    Hello {
+        version: u32,
+        name: String,
+    },
+    // Break the block at `pos` (when `block` is AIR), or place `block` there against `onto`.
+    Edit {
+        pos: [i32; 3],
+        block: Block,
+        onto: Option<[i32; 3]>,
+    },
+    Chat {
+        text: String,
+    },
+}
+
+// Messages from the server to the client, over TCP.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    // The world settings are sent as RON, like they are saved.
+    Welcome {
+        id: PlayerId,
+        token: u64,
+        world_settings: String,
+    },
+    Disconnect {
+        reason: String,
+    },
+    // The blocks of a chunk, run-length encoded like in the region files.
+    Chunk {
+        cords: [i32; 3],
+        data: Vec<u8>,
+    },
+    UnloadChunk {
+        cords: [i32; 3],
+    },
+    SetBlock {
+        pos: [i32; 3],
+        block: Block,
+    },
+    PlayerLeft {
+        id: PlayerId,
+    },
+    // A line of chat, with who sent it.
+    Chat {
+        text: String,
+    },
+    // The server changed the game mode of the player, it only lets the player do what the mode
+    // allows.
+    GameMode {
+        mode: GameMode,
+    },
+}
+
+// Positions are sent over UDP, a lost one is replaced by the next one anyway.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Datagram {
+    // The token proves the datagram comes from the player with that id.
+    Position {
+        id: PlayerId,
+        token: u64,
+        pos: Vec3,
+        yaw: f32,
+    },
+    Players(Vec<(PlayerId, Vec3, f32)>),
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+    fn u16(&mut self, v: u16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn u64(&mut self, v: u64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn f32(&mut self, v: f32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn cords(&mut self, v: [i32; 3]) -> &mut Self {
+        for c in v {
+            self.0.extend_from_slice(&c.to_le_bytes());
+        }
+        self
+    }
+    fn vec3(&mut self, v: Vec3) -> &mut Self {
+        self.f32(v.x).f32(v.y).f32(v.z)
+    }
+    fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.u32(v.len() as u32);
+        self.0.extend_from_slice(v);
+        self
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.0.len() < N {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(N);
+        self.0 = tail;
+        head.try_into().ok()
+    }
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take::<1>()?[0])
+    }
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take()?))
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take()?))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take()?))
+    }
+    fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_le_bytes(self.take()?))
+    }
+    fn cords(&mut self) -> Option<[i32; 3]> {
+        let mut cords = [0; 3];
+        for c in cords.iter_mut() {
+            *c = i32::from_le_bytes(self.take()?);
+        }
+        Some(cords)
+    }
+    fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+    fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_vec()).ok()
+    }
+    // Messages have to be read to the end, trailing bytes mean the message is broken.
+    fn finish<T>(self, value: T) -> Option<T> {
+        self.0.is_empty().then_some(value)
+    }
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        match self {
+            ClientMessage::Hello { version, name } => {
+                w.u8(0).u32(*version).bytes(name.as_bytes());
+            }
+            ClientMessage::Edit { pos, block, onto } => {
+                w.u8(1).cords(*pos).u16(*block);
+                match onto {
+                    Some(onto) => w.u8(1).cords(*onto),
+                    None => w.u8(0),
+                };
+            }
+            ClientMessage::Chat { text } => {
+                w.u8(2).bytes(text.as_bytes());
+            }
+        }
+        w.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut r = Reader(bytes);
+        let message = match r.u8()? {
+            0 => ClientMessage::Hello {
+                version: r.u32()?,
+                name: r.string()?,
+            },
+            1 => ClientMessage::Edit {
+                pos: r.cords()?,
+                block: r.u16()?,
+                onto: match r.u8()? {
+                    0 => None,
+                    _ => Some(r.cords()?),
+                },
+            },
+            2 => ClientMessage::Chat { text: r.string()? },
+            _ => return None,
+        };
+        r.finish(message)
+    }
+}
+
+impl ServerMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        match self {
+            ServerMessage::Welcome {
+                id,
+                token,
+                world_settings,
+            } => {
+                w.u8(0)
+                    .u32(*id)
+                    .u64(*token)
+                    .bytes(world_settings.as_bytes());
+            }
+            ServerMessage::Disconnect { reason } => {
+                w.u8(1).bytes(reason.as_bytes());
+            }
+            ServerMessage::Chunk { cords, data } => {
+                w.u8(2).cords(*cords).bytes(data);
+            }
+            ServerMessage::UnloadChunk { cords } => {
+                w.u8(3).cords(*cords);
+            }
+            ServerMessage::SetBlock { pos, block } => {
+                w.u8(4).cords(*pos).u16(*block);
+            }
+            ServerMessage::PlayerLeft { id } => {
+                w.u8(5).u32(*id);
+            }
+            ServerMessage::Chat { text } => {
+                w.u8(6).bytes(text.as_bytes());
+            }
+            ServerMessage::GameMode { mode } => {
+                w.u8(7).u8(match mode {
+                    GameMode::Survival => 0,
+                    GameMode::Creative => 1,
+                    GameMode::Spectator => 2,
+                });
+            }
+        }
+        w.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut r = Reader(bytes);
+        let message = match r.u8()? {
+            0 => ServerMessage::Welcome {
+                id: r.u32()?,
+                token: r.u64()?,
+                world_settings: r.string()?,
+            },
+            1 => ServerMessage::Disconnect {
+                reason: r.string()?,
+            },
+            2 => ServerMessage::Chunk {
+                cords: r.cords()?,
+                data: r.bytes()?.to_vec(),
+            },
+            3 => ServerMessage::UnloadChunk { cords: r.cords()? },
+            4 => ServerMessage::SetBlock {
+                pos: r.cords()?,
+                block: r.u16()?,
+            },
+            5 => ServerMessage::PlayerLeft { id: r.u32()? },
+            6 => ServerMessage::Chat { text: r.string()? },
+            7 => ServerMessage::GameMode {
+                mode: match r.u8()? {
+                    0 => GameMode::Survival,
+                    1 => GameMode::Creative,
+                    2 => GameMode::Spectator,
+                    _ => return None,
+                },
+            },
+            _ => return None,
+        };
+        r.finish(message)
+    }
+}
+
+impl Datagram {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        match self {
+            Datagram::Position {
+                id,
+                token,
+                pos,
+                yaw,
+            } => {
+                w.u8(0).u32(*id).u64(*token).vec3(*pos).f32(*yaw);
+            }
+            Datagram::Players(players) => {
+                w.u8(1).u32(players.len() as u32);
+                for (id, pos, yaw) in players {
+                    w.u32(*id).vec3(*pos).f32(*yaw);
+                }
+            }
+        }
+        w.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut r = Reader(bytes);
+        let datagram = match r.u8()? {
+            0 => Datagram::Position {
+                id: r.u32()?,
+                token: r.u64()?,
+                pos: r.vec3()?,
+                yaw: r.f32()?,
+            },
+            1 => {
+                let count = r.u32()?;
+                let mut players = Vec::new();
+                for _ in 0..count {
+                    players.push((r.u32()?, r.vec3()?, r.f32()?));
+                }
+                Datagram::Players(players)
+            }
+            _ => return None,
+        };
+        r.finish(datagram)
+    }
+}
+
+// A TCP connection that never blocks. Every message is sent as a frame, its length followed by
+// the message. Whatever can't be written right away is kept and written on the next `flush`.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    pub fn send(&mut self, message: &[u8]) {
+        self.outgoing
+            .extend_from_slice(&(message.len() as u32).to_le_bytes());
+        self.outgoing.extend_from_slice(message);
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // Read everything that arrived, and return the frames that are complete. An error means the
+    // connection was closed or broken.
+    pub fn receive(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => self.incoming.extend_from_slice(&buf[..read]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        let mut frames = Vec::new();
+        while self.incoming.len() >= 4 {
+            let len = u32::from_le_bytes(self.incoming[..4].try_into().unwrap()) as usize;
+            if len > MAX_FRAME_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Received a frame that is too big",
+                ));
+            }
+            if self.incoming.len() < 4 + len {
+                break;
+            }
+            frames.push(self.incoming[4..4 + len].to_vec());
+            self.incoming.drain(..4 + len);
+        }
+        Ok(frames)
+    }
+}
+
+// Where to host or join a game, taken out of the command line arguments.
+pub struct NetSettings {
+    pub port: u16,
+    // The address of the server to join, if this is None the world is played locally.
+    pub connect: Option<String>,
+    pub name: String,
+}
+
+impl NetSettings {
+    // Take the networking flags out of the arguments, the rest are returned for `WorldSettings`.
+    pub fn from_args(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(NetSettings, Vec<String>), String> {
+        let mut settings = NetSettings {
+            port: DEFAULT_PORT,
+            connect: None,
+            name: String::from("Player"),
+        };
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}\n{}", arg, USAGE))
+            };
+            match arg.as_str() {
+                "--port" => {
+                    let port = value()?;
+                    settings.port = port
+                        .parse()
+                        .map_err(|_| format!("Invalid value {} for --port\n{}", port, USAGE))?;
+                }
+                "--connect" => settings.connect = Some(value()?),
+                "--name" => settings.name = value()?,
+                _ => rest.push(arg),
+            }
+        }
+        Ok((settings, rest))
+    }
+}
@@ -0,0 +1,563 @@
+use super::*;
+use bevy::utils::{HashMap, HashSet};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+
+// How many chunks are sent to each player every frame, so a player joining doesn't stall the server.
+const CHUNKS_PER_FRAME: usize = 8;
+// Edits further than this from the player are rejected, with some room for the positions that
+// arrive late.
+const MAX_EDIT_DISTANCE: f32 = REACH_DISTANCE as f32 + 2.0;
+
+struct Client {
+    connection: Connection,
+    id: PlayerId,
+    token: u64,
+    // These are only known after the client says hello.
+    name: String,
+    player: Option<Entity>,
+    // The game mode of the player, it starts in the one of the world and is only changed by the
+    // server.
+    game_mode: GameMode,
+    // Where the positions of the other players are sent, it's known once the client sends its own.
+    udp_addr: Option<SocketAddr>,
+    // The chunks the client has, it's sent the blocks that change in them.
+    sent_chunks: HashSet<[i32; 3]>,
+}
+
+// The other end of every connected client. The server owns the world, the clients only ask it to
+// change blocks, and are told what changed.
+#[derive(Resource)]
+pub struct NetServer {
+    listener: TcpListener,
+    socket: UdpSocket,
+    clients: HashMap<PlayerId, Client>,
+    next_id: PlayerId,
+    // Edits that were asked for this frame. The player that asked is always told how the block
+    // ended up, even if the edit was rejected.
+    pending_edits: Vec<(PlayerId, [i32; 3])>,
+    position_timer: Timer,
+}
+
+impl NetServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_nonblocking(true)?;
+        Ok(NetServer {
+            listener,
+            socket,
+            clients: HashMap::new(),
+            next_id: 0,
+            pending_edits: Vec::new(),
+            position_timer: Timer::from_seconds(
+                1.0 / POSITION_UPDATES_PER_SECOND,
+                TimerMode::Repeating,
+            ),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn disconnect(&mut self, id: PlayerId, reason: &str, commands: &mut Commands) {
+        let Some(mut client) = self.clients.remove(&id) else {
+            return;
+        };
+        client.connection.send(
+            &ServerMessage::Disconnect {
+                reason: reason.to_string(),
+            }
+            .encode(),
+        );
+        let _ = client.connection.flush();
+        if let Some(player) = client.player {
+            commands.entity(player).despawn();
+            info!("\nIn-Game Log:\n{} left the game ({})", client.name, reason);
+            for other in self.clients.values_mut() {
+                other
+                    .connection
+                    .send(&ServerMessage::PlayerLeft { id }.encode());
+            }
+        }
+    }
+}
+
+// A player that is connected to the server. Chunks are loaded around it like around the player of
+// a client, because it has a `CurrentChunk`.
+#[derive(Component)]
+pub struct RemotePlayer {
+    pub id: PlayerId,
+    pub yaw: f32,
+}
+
+// Lets players join the world over the network. The `NetServer` resource has to be inserted, so
+// the port it binds to is known to be free before the app starts.
+pub struct NetServerPlugin;
+
+impl Plugin for NetServerPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(
+            Update,
+            (
+                accept_clients,
+                receive_messages,
+                receive_positions,
+                stream_chunks,
+            )
+                .chain(),
+        )
+        .add_systems(Update, gamemode_command)
+        .add_systems(
+            PostUpdate,
+            (
+                broadcast_edits.after(handle_block_break_place),
+                broadcast_chat,
+                send_positions,
+                flush_clients,
+            )
+                .chain(),
+        );
+
+        // Commands
+        app.register_command(CommandSpec {
+            name: "gamemode",
+            params: vec![
+                Param::required("mode", ArgKind::Word),
+                Param::required("player", ArgKind::Word),
+            ],
+            help: "Switch a player to survival, creative or spectator",
+        });
+    }
+}
+
+fn accept_clients(mut server: ResMut<NetServer>) {
+    loop {
+        let stream = match server.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+            Err(e) => {
+                warn!("Couldn't accept a connection: {}", e);
+                return;
+            }
+        };
+        let connection = match Connection::new(stream) {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Couldn't set up a connection: {}", e);
+                continue;
+            }
+        };
+        let id = server.next_id;
+        server.next_id += 1;
+        server.clients.insert(
+            id,
+            Client {
+                connection,
+                id,
+                token: RandomState::new().hash_one(id),
+                name: String::new(),
+                player: None,
+                game_mode: GameMode::default(),
+                udp_addr: None,
+                sent_chunks: HashSet::new(),
+            },
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn receive_messages(
+    mut server: ResMut<NetServer>,
+    mut commands: Commands,
+    world_settings: Res<WorldSettings>,
+    players: Query<(&Transform, &RemotePlayer)>,
+    close_chunks: Query<(), With<ChunkCloseToPlayer>>,
+    world: VoxelWorld,
+    breg: Res<BlockRegistry>,
+    mut block_change: EventWriter<BlockChange>,
+    mut chat: EventWriter<ChatMessage>,
+) {
+    let server = server.as_mut();
+    let mut dropped = vec![];
+    for client in server.clients.values_mut() {
+        let frames = match client.connection.receive() {
+            Ok(frames) => frames,
+            Err(e) => {
+                dropped.push((client.id, e.to_string()));
+                continue;
+            }
+        };
+        for frame in frames {
+            match ClientMessage::decode(&frame) {
+                Some(ClientMessage::Hello { version, name }) if client.player.is_none() => {
+                    if version != PROTOCOL_VERSION {
+                        dropped.push((
+                            client.id,
+                            format!(
+                                "The server speaks version {} of the protocol, not {}",
+                                PROTOCOL_VERSION, version
+                            ),
+                        ));
+                        break;
+                    }
+                    let spawn_point = spawn_point(&world_settings);
+                    client.name = name;
+                    client.game_mode = world_settings.game_mode;
+                    client.player = Some(
+                        commands
+                            .spawn((
+                                TransformBundle::from_transform(Transform::from_translation(
+                                    spawn_point,
+                                )),
+                                CurrentChunk(position_to_chunk_cords(spawn_point)),
+                                RemotePlayer {
+                                    id: client.id,
+                                    yaw: 0.0,
+                                },
+                            ))
+                            .id(),
+                    );
+                    client.connection.send(
+                        &ServerMessage::Welcome {
+                            id: client.id,
+                            token: client.token,
+                            world_settings: ron::to_string(world_settings.as_ref())
+                                .expect("World settings can always be serialized"),
+                        }
+                        .encode(),
+                    );
+                    info!("\nIn-Game Log:\n{} joined the game", client.name);
+                }
+                Some(ClientMessage::Edit { pos, block, onto }) if client.player.is_some() => {
+                    server.pending_edits.push((client.id, pos));
+                    let Ok((eye, _)) = players.get(client.player.unwrap()) else {
+                        continue;
+                    };
+                    let hitboxes: Vec<Hitbox> = players
+                        .iter()
+                        .map(|(transform, _)| Hitbox::player(transform.translation))
+                        .collect();
+                    let is_ready = |pos: [i32; 3]| {
+                        let (chunk, _) = block_to_chunk_position(pos);
+                        !is_chunk_in_world(chunk)
+                            || world
+                                .chunk_map()
+                                .get_ent(chunk)
+                                .is_some_and(|ent| close_chunks.contains(ent))
+                    };
+                    if let Some(change) = validate_edit(
+                        pos,
+                        block,
+                        onto,
+                        client.game_mode,
+                        eye.translation,
+                        &hitboxes,
+                        &breg,
+                        is_ready,
+                        |pos| world.get_block(pos.into()),
+                    ) {
+                        block_change.send(change);
+                    }
+                }
+                Some(ClientMessage::Chat { text }) if client.player.is_some() => {
+                    chat.send(ChatMessage {
+                        sender: client.name.clone(),
+                        text,
+                    });
+                }
+                _ => {
+                    dropped.push((client.id, String::from("Received an invalid message")));
+                    break;
+                }
+            }
+        }
+    }
+    for (id, reason) in dropped {
+        server.disconnect(id, &reason, &mut commands);
+    }
+}
+
+// Turn an edit a player asked for into a `BlockChange`, if the player is allowed to make it. The
+// player's game mode has to let it edit, and the block, the blocks around it and the chunks they
+// are in have to be loaded, and have to be close to the player.
+#[allow(clippy::too_many_arguments)]
+fn validate_edit(
+    pos: [i32; 3],
+    block: Block,
+    onto: Option<[i32; 3]>,
+    game_mode: GameMode,
+    eye: Vec3,
+    players: &[Hitbox],
+    breg: &BlockRegistry,
+    is_ready: impl Fn([i32; 3]) -> bool,
+    block_at: impl Fn([i32; 3]) -> Option<Block>,
+) -> Option<BlockChange> {
+    let center = Vec3::new(pos[0] as f32, pos[1] as f32, pos[2] as f32);
+    if !game_mode.can_interact()
+        || center.distance(eye) > MAX_EDIT_DISTANCE
+        || !is_chunk_in_world(block_to_chunk_position(pos).0)
+        || !is_ready(pos)
+        || !(0..6).all(|i| is_ready(adjacent_cords(pos, Face::from(i))))
+    {
+        return None;
+    }
+    block_at(pos)?;
+    let (chunk, local) = block_to_chunk_position(pos);
+    let index = one_d_cords(local, CHUNK_DIMS);
+    match onto {
+        None if block == AIR => Some(BlockChange {
+            blocks: vec![(chunk, index, None)],
+            change: VoxelChange::Broken,
+            block: AIR,
+        }),
+        Some(onto) if block != AIR && breg.get(block).is_some() => {
+            // Blocks are placed against the face of another block.
+            if (0..3)
+                .map(|axis| (pos[axis] - onto[axis]).abs())
+                .sum::<i32>()
+                != 1
+            {
+                return None;
+            }
+            if players.iter().any(|player| {
+                (0..3).all(|axis| {
+                    pos[axis] as f32 - 0.5 < player.max[axis]
+                        && pos[axis] as f32 + 0.5 > player.min[axis]
+                })
+            }) {
+                return None;
+            }
+            let (onto_chunk, onto_local) = block_to_chunk_position(onto);
+            Some(BlockChange {
+                blocks: vec![(
+                    chunk,
+                    index,
+                    Some((onto_chunk, one_d_cords(onto_local, CHUNK_DIMS))),
+                )],
+                change: VoxelChange::Added,
+                block,
+            })
+        }
+        _ => None,
+    }
+}
+
+// The server decides the game mode of every player, and tells the player when it changes.
+fn gamemode_command(
+    mut server: ResMut<NetServer>,
+    mut commands: EventReader<ConsoleCommand>,
+    mut log: ResMut<ConsoleLog>,
+) {
+    for command in commands.read().filter(|command| command.name == "gamemode") {
+        let (Some(mode), Some(name)) = (
+            command.word(0).and_then(GameMode::from_name),
+            command.word(1),
+        ) else {
+            log.print("Usage: /gamemode <survival|creative|spectator> <player>");
+            continue;
+        };
+        let Some(client) = server
+            .clients
+            .values_mut()
+            .find(|client| client.player.is_some() && client.name == name)
+        else {
+            log.print(format!("{} isn't in the game", name));
+            continue;
+        };
+        client.game_mode = mode;
+        client
+            .connection
+            .send(&ServerMessage::GameMode { mode }.encode());
+        log.print(format!("{} is now in {:?}", name, mode));
+    }
+}
+
+fn receive_positions(
+    mut server: ResMut<NetServer>,
+    mut players: Query<(&mut Transform, &mut CurrentChunk, &mut RemotePlayer)>,
+) {
+    let server = server.as_mut();
+    let mut buf = [0; 1500];
+    loop {
+        let (len, addr) = match server.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+            // A client that went away makes the next receive fail on some platforms.
+            Err(_) => continue,
+        };
+        let Some(Datagram::Position {
+            id,
+            token,
+            pos,
+            yaw,
+        }) = Datagram::decode(&buf[..len])
+        else {
+            continue;
+        };
+        let Some(client) = server.clients.get_mut(&id) else {
+            continue;
+        };
+        let Some(player) = client.player.filter(|_| client.token == token) else {
+            continue;
+        };
+        client.udp_addr = Some(addr);
+        if let Ok((mut transform, mut current_chunk, mut remote)) = players.get_mut(player) {
+            transform.translation = pos;
+            transform.rotation = Quat::from_rotation_y(yaw);
+            remote.yaw = yaw;
+            // Changing the current chunk loads the chunks around it, so it's only set when it
+            // really changed.
+            let cords = position_to_chunk_cords(pos);
+            if current_chunk.0 != cords {
+                current_chunk.0 = cords;
+            }
+        }
+    }
+}
+
+// Send every player the chunks around it, closest first, and tell it to unload the ones it left.
+fn stream_chunks(
+    mut server: ResMut<NetServer>,
+    players: Query<&CurrentChunk>,
+    chunks: Query<&Chunk>,
+    chunk_map: Res<ChunkMap>,
+    world_settings: Res<WorldSettings>,
+) {
+    let render_distance = world_settings.render_distance;
+    for client in server.clients.values_mut() {
+        let Some(Ok(viewer)) = client.player.map(|player| players.get(player)) else {
+            continue;
+        };
+        let viewer = viewer.0;
+        let left: Vec<[i32; 3]> = client
+            .sent_chunks
+            .iter()
+            .filter(|cords| !in_render_distance(**cords, viewer, render_distance))
+            .copied()
+            .collect();
+        for cords in left {
+            client.sent_chunks.remove(&cords);
+            client
+                .connection
+                .send(&ServerMessage::UnloadChunk { cords }.encode());
+        }
+
+        let mut to_send: Vec<([i32; 3], Entity)> = chunk_map
+            .iter()
+            .filter(|(cords, _)| {
+                in_render_distance(*cords, viewer, render_distance)
+                    && !client.sent_chunks.contains(cords)
+            })
+            .collect();
+        to_send.sort_by_key(|(cords, _)| {
+            (0..3)
+                .map(|axis| (cords[axis] - viewer[axis]).pow(2))
+                .sum::<i32>()
+        });
+        for (cords, ent) in to_send.into_iter().take(CHUNKS_PER_FRAME) {
+            let Ok(chunk) = chunks.get(ent) else {
+                continue;
+            };
+            client.sent_chunks.insert(cords);
+            client.connection.send(
+                &ServerMessage::Chunk {
+                    cords,
+                    data: encode_grid(&chunk.grid),
+                }
+                .encode(),
+            );
+        }
+    }
+}
+
+// Tell the players about the blocks that changed in the chunks they have, and tell the players
+// whose edits were rejected that the block stayed the same.
+fn broadcast_edits(
+    mut server: ResMut<NetServer>,
+    mut edits: EventReader<BlockEdited>,
+    world: VoxelWorld,
+) {
+    let server = server.as_mut();
+    let mut edited = HashSet::new();
+    for edit in edits.read() {
+        edited.insert(edit.pos);
+        let (chunk, _) = block_to_chunk_position(edit.pos);
+        let message = ServerMessage::SetBlock {
+            pos: edit.pos,
+            block: edit.new,
+        }
+        .encode();
+        for client in server.clients.values_mut() {
+            if client.sent_chunks.contains(&chunk) {
+                client.connection.send(&message);
+            }
+        }
+    }
+    for (id, pos) in server.pending_edits.drain(..) {
+        if edited.contains(&pos) {
+            continue;
+        }
+        let Some(client) = server.clients.get_mut(&id) else {
+            continue;
+        };
+        let Some(block) = world.get_block(pos.into()) else {
+            continue;
+        };
+        if client.sent_chunks.contains(&block_to_chunk_position(pos).0) {
+            client
+                .connection
+                .send(&ServerMessage::SetBlock { pos, block }.encode());
+        }
+    }
+}
+
+fn broadcast_chat(mut server: ResMut<NetServer>, mut chat: EventReader<ChatMessage>) {
+    for message in chat.read() {
+        let message = ServerMessage::Chat {
+            text: format!("<{}> {}", message.sender, message.text),
+        }
+        .encode();
+        for client in server.clients.values_mut() {
+            if client.player.is_some() {
+                client.connection.send(&message);
+            }
+        }
+    }
+}
+
+fn send_positions(
+    mut server: ResMut<NetServer>,
+    time: Res<Time>,
+    players: Query<(&Transform, &RemotePlayer)>,
+) {
+    if !server.position_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let datagram = Datagram::Players(
+        players
+            .iter()
+            .map(|(transform, remote)| (remote.id, transform.translation, remote.yaw))
+            .collect(),
+    )
+    .encode();
+    for addr in server.clients.values().filter_map(|client| client.udp_addr) {
+        let _ = server.socket.send_to(&datagram, addr);
+    }
+}
+
+fn flush_clients(mut server: ResMut<NetServer>, mut commands: Commands) {
+    let mut dropped = vec![];
+    for client in server.clients.values_mut() {
+        if let Err(e) = client.connection.flush() {
+            dropped.push((client.id, e.to_string()));
+        }
+    }
+    for (id, reason) in dropped {
+        server.disconnect(id, &reason, &mut commands);
+    }
+}
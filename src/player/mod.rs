@@ -1,4 +1,5 @@
 pub mod movement;
+pub mod physics;
 #[allow(unused_imports)]
 use crate::*;
 use bevy::ecs::event::{Events, ManualEventReader};
@@ -14,12 +15,12 @@ use bevy::{
     render::camera::TemporalJitter,
 };
 use movement::*;
+pub use physics::*;
 // ALWAYS ODD!
 pub(crate) const CAGE_SIZE: usize = 7;
 pub(crate) const HALF_CAGE_I: i32 = (CAGE_SIZE / 2) as i32;
 pub const CAGE_LEN: usize = CAGE_SIZE * CAGE_SIZE * CAGE_SIZE;
 pub(crate) const CAGE_DIMS: (usize, usize, usize) = (CAGE_SIZE, CAGE_SIZE, CAGE_SIZE);
-// pub(crate) const GRAVITY: f32 = 0.005;
 
 pub mod prelude {
     pub use crate::*;
@@ -35,15 +36,13 @@ struct InputState {
 #[derive(Resource)]
 pub struct MovementSettings {
     pub sensitivity: f32,
+    // The speed when flying.
     pub speed: f32,
-}
-
-#[derive(Component)]
-#[allow(non_snake_case)]
-pub struct VelocityVectors {
-    pub yV: Vec3,
-    pub xV: Vec3,
-    pub zV: Vec3,
+    pub walk_speed: f32,
+    // The upwards speed at the start of a jump.
+    pub jump_speed: f32,
+    pub gravity: f32,
+    pub max_fall_speed: f32,
 }
 
 impl Default for MovementSettings {
@@ -51,10 +50,28 @@ impl Default for MovementSettings {
         Self {
             sensitivity: 0.00008,
             speed: 10.,
+            walk_speed: 4.5,
+            jump_speed: 8.4,
+            gravity: 28.,
+            max_fall_speed: 50.,
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MovementMode {
+    Walk,
+    Fly,
+}
+
+/// The velocity of the player, and what it's standing on
+#[derive(Component)]
+pub struct PlayerPhysics {
+    pub velocity: Vec3,
+    pub on_ground: bool,
+    pub mode: MovementMode,
+}
+
 /// Key configuration
 #[derive(Resource)]
 pub struct KeyBindings {
@@ -65,6 +82,15 @@ pub struct KeyBindings {
     pub move_ascend: KeyCode,
     pub move_descend: KeyCode,
     pub toggle_grab_cursor: KeyCode,
+    pub toggle_fly: KeyCode,
+    pub toggle_inventory: KeyCode,
+    // Only with cheats on.
+    pub cycle_game_mode: KeyCode,
+    pub open_chat: KeyCode,
+    pub open_command: KeyCode,
+    // Undo and redo are pressed together with Control.
+    pub undo_edit: KeyCode,
+    pub redo_edit: KeyCode,
 }
 
 impl Default for KeyBindings {
@@ -77,6 +103,13 @@ impl Default for KeyBindings {
             move_ascend: KeyCode::Space,
             move_descend: KeyCode::ShiftLeft,
             toggle_grab_cursor: KeyCode::Escape,
+            toggle_fly: KeyCode::F,
+            toggle_inventory: KeyCode::E,
+            cycle_game_mode: KeyCode::G,
+            open_chat: KeyCode::T,
+            open_command: KeyCode::Slash,
+            undo_edit: KeyCode::Z,
+            redo_edit: KeyCode::Y,
         }
     }
 }
@@ -87,48 +120,39 @@ impl Default for KeyBindings {
 pub struct FlyCam;
 
 #[derive(Component)]
-// Keeps track of the blocks surrounding the player for physics
+// Keeps track of the blocks surrounding the player, the player collides with the world itself
 pub struct Cage {
     pub blocks: [Block; CAGE_LEN],
+    // The block in the middle of the cage.
+    pub center: [i32; 3],
+}
+
+// Whether things collide with the block, everything moves through air and water.
+pub fn blocks_movement(block: Block) -> bool {
+    block != AIR && water_level(block).is_none()
 }
 
 #[derive(Component)]
-pub struct CurrentChunk(pub [i32; 2]);
+pub struct CurrentChunk(pub [i32; 3]);
 
 fn update_cage(
-    chunk_query: Query<&Chunk, With<ChunkCloseToPlayer>>,
+    world: VoxelWorld,
     mut player_query: Query<(&mut Cage, &Transform), Changed<Transform>>,
 ) {
     if let Ok((mut cage, tran)) = player_query.get_single_mut() {
         let pos = tran.translation;
         let current_block: Vec3 = [pos.x.round(), pos.y.round(), pos.z.round()].into();
-        for x in -HALF_CAGE_I..=HALF_CAGE_I {
-            for z in -HALF_CAGE_I..=HALF_CAGE_I {
-                for y in -HALF_CAGE_I..=HALF_CAGE_I {
-                    let cage_index_3d = [
-                        (x + HALF_CAGE_I) as usize,
-                        (y + HALF_CAGE_I) as usize,
-                        (z + HALF_CAGE_I) as usize,
-                    ];
-                    let cage_index_1d = one_d_cords(cage_index_3d, CAGE_DIMS);
-                    let block_pos = current_block + Vec3::new(x as f32, y as f32, z as f32);
-                    let (chunk_pos, block_pos, flag) =
-                        position_to_chunk_position(block_pos, CHUNK_DIMS);
-
-                    let block = if !flag {
-                        AIR
-                    } else {
-                        let mut r = AIR;
-                        for chunk in chunk_query.iter() {
-                            if chunk.cords == chunk_pos {
-                                r = chunk.grid[one_d_cords(block_pos, CHUNK_DIMS)];
-                            }
-                        }
-                        r
-                    };
-                    cage.blocks[cage_index_1d] = block;
-                }
-            }
+        cage.center = position_to_block(current_block);
+        let min = IVec3::from(cage.center) - IVec3::splat(HALF_CAGE_I);
+        let max = IVec3::from(cage.center) + IVec3::splat(HALF_CAGE_I);
+        // The blocks of the chunks that aren't loaded, and outside of the world, are air.
+        for (block_pos, block) in world.region(min, max) {
+            let offset = (block_pos - min).as_uvec3();
+            let cage_index = one_d_cords(
+                [offset.x as usize, offset.y as usize, offset.z as usize],
+                CAGE_DIMS,
+            );
+            cage.blocks[cage_index] = block.unwrap_or(AIR);
         }
     }
 }
@@ -175,6 +199,16 @@ impl Plugin for PlayerPlugin {
                 Update,
                 (player_move, player_look, cursor_grab, update_cage)
                     .run_if(in_state(InitialChunkLoadState::Complete)),
-            );
+            )
+            .add_systems(Update, tp_command)
+            .register_command(CommandSpec {
+                name: "tp",
+                params: vec![
+                    Param::required("x", ArgKind::Number),
+                    Param::required("y", ArgKind::Number),
+                    Param::required("z", ArgKind::Number),
+                ],
+                help: "Teleport to a position",
+            });
     }
 }
@@ -19,28 +19,27 @@ pub(super) fn initial_grab_cursor(mut primary_window: Query<&mut Window, With<Pr
 }
 
 /// Spawns the `Camera3dBundle` to be controlled
-pub(super) fn setup_player(mut commands: Commands) {
+pub(super) fn setup_player(mut commands: Commands, world_settings: Res<WorldSettings>) {
+    // Spawn right above the surface, the player falls onto it once the blocks around it are known.
+    let spawn_point = spawn_point(&world_settings);
     commands
         .spawn((
             Camera3dBundle {
-                transform: Transform::from_xyz(
-                    // -RENDER_DISTANCE as f32 * WIDTH as f32,
-                    0.0,
-                    HEIGHT as f32 * 2.0, // -RENDER_DISTANCE as f32 * LENGTH as f32,
-                    0.0,
-                )
-                .looking_to(Vec3::new(5.0, -1.0, 5.0), Vec3::Y),
+                transform: Transform::from_translation(spawn_point)
+                    .looking_to(Vec3::new(5.0, -1.0, 5.0), Vec3::Y),
                 ..Default::default()
             },
             Cage {
                 blocks: [AIR; CAGE_LEN],
+                center: position_to_block(spawn_point),
             },
             FlyCam,
-            CurrentChunk([0, 0]),
-            VelocityVectors {
-                xV: Vec3::ZERO,
-                yV: Vec3::ZERO,
-                zV: Vec3::ZERO,
+            EditJournal::default(),
+            CurrentChunk(position_to_chunk_cords(spawn_point)),
+            PlayerPhysics {
+                velocity: Vec3::ZERO,
+                on_ground: false,
+                mode: MovementMode::Walk,
             },
         ))
         .insert(ScreenSpaceAmbientOcclusionBundle {
@@ -51,25 +50,31 @@ pub(super) fn setup_player(mut commands: Commands) {
         });
 }
 
+// Longer frames are simulated as if they were this long, so a hitch doesn't throw the player across
+// the world in a single step.
+const MAX_FRAME_TIME: f32 = 0.1;
+
 /// Handles keyboard input and movement
+#[allow(clippy::too_many_arguments)]
 pub(super) fn player_move(
     keys: Res<Input<KeyCode>>,
     time: Res<Time>,
     primary_window: Query<&Window, With<PrimaryWindow>>,
     settings: Res<MovementSettings>,
     key_bindings: Res<KeyBindings>,
+    game_mode: Res<State<GameMode>>,
+    world: VoxelWorld,
     mut query: Query<(
         &FlyCam,
         &mut Transform,
         &mut CurrentChunk,
-        &Cage,
-        &mut VelocityVectors,
+        &mut PlayerPhysics,
     )>,
-    //    mut query: Query<&mut Transform, With<FlyCam>>,
 ) {
     if let Ok(window) = primary_window.get_single() {
-        for (_camera, mut transform, mut chunk, cage, mut _vv) in query.iter_mut() {
+        for (_camera, mut transform, mut chunk, mut physics) in query.iter_mut() {
             let mut direction = Vec3::ZERO;
+            let mut jump = false;
             let local_z = transform.local_z();
             let forward = -Vec3::new(local_z.x, 0., local_z.z);
             let right = Vec3::new(local_z.z, 0., -local_z.x);
@@ -91,81 +96,65 @@ pub(super) fn player_move(
                     }
                     if keys.pressed(key_bindings.move_ascend) {
                         direction += Vec3::Y;
+                        jump = true;
                     }
                     if keys.pressed(key_bindings.move_descend) {
                         direction -= Vec3::Y;
                     }
+                    if keys.just_pressed(key_bindings.toggle_fly)
+                        && game_mode.get() == &GameMode::Creative
+                    {
+                        physics.mode = match physics.mode {
+                            MovementMode::Walk => MovementMode::Fly,
+                            MovementMode::Fly => MovementMode::Walk,
+                        };
+                        physics.velocity = Vec3::ZERO;
+                    }
                 }
             }
-            direction = direction.normalize_or_zero();
-            // Calculate if we collided with a block
-            let velocity = direction * time.delta_seconds() * settings.speed;
-            let new_pos = transform.translation + velocity;
-            let tmp = transform.translation + direction;
-            let pos = transform.translation;
-            let current_block: Vec3 = [pos.x.round(), pos.y.round(), pos.z.round()].into();
-            let tmp_block: Vec3 = [tmp.x.round(), tmp.y.round(), tmp.z.round()].into();
-            let Vec3 {
-                x: dx,
-                y: dy,
-                z: dz,
-            } = tmp_block - current_block;
-            let xblock_cords = [
-                (HALF_CAGE_I + dx as i32) as usize,
-                (HALF_CAGE_I as i32) as usize,
-                (HALF_CAGE_I as i32) as usize,
-            ];
-            let yblock_cords = [
-                (HALF_CAGE_I as i32) as usize,
-                (HALF_CAGE_I + dy as i32) as usize,
-                (HALF_CAGE_I as i32) as usize,
-            ];
-            let zblock_cords = [
-                (HALF_CAGE_I as i32) as usize,
-                (HALF_CAGE_I as i32) as usize,
-                (HALF_CAGE_I + dz as i32) as usize,
-            ];
-            let xzblock_cords = [
-                (HALF_CAGE_I + dx as i32) as usize,
-                (HALF_CAGE_I as i32) as usize,
-                (HALF_CAGE_I + dz as i32) as usize,
-            ];
-
-            let xblock = one_d_cords(xblock_cords, CAGE_DIMS);
-            let yblock = one_d_cords(yblock_cords, CAGE_DIMS);
-            let zblock = one_d_cords(zblock_cords, CAGE_DIMS);
-            let xzblock = one_d_cords(xzblock_cords, CAGE_DIMS);
-            let xblock = cage.blocks[xblock];
-            let yblock = cage.blocks[yblock];
-            let zblock = cage.blocks[zblock];
-            let xzblock = cage.blocks[xzblock];
-
-            if xblock == AIR {
-                transform.translation.x = new_pos.x;
-            } else if (pos.x - pos.x.round() + 0.5 * dx.signum() * -1.0).abs() > 0.5 {
-                transform.translation.x +=
-                    velocity.x * (pos.x - pos.x.round() + 0.5 * dx.signum() * -1.0).powi(2);
-            }
-            if yblock == AIR {
-                transform.translation.y = new_pos.y;
-            } else if (pos.y - pos.y.round() + 0.5 * dy.signum() * -1.0).abs() > 0.5 {
-                transform.translation.y +=
-                    velocity.y * (pos.y - pos.y.round() + 0.5 * dy.signum() * -1.0).powi(2);
-            }
-            if zblock == AIR && xzblock == AIR {
-                transform.translation.z = new_pos.z;
-            } else if (pos.z - pos.z.round() + 0.5 * dz.signum() * -1.0).abs() > 0.5 {
-                transform.translation.z +=
-                    velocity.z * (pos.z - pos.z.round() + 0.5 * dz.signum() * -1.0).powi(2);
+
+            let dt = time.delta_seconds().min(MAX_FRAME_TIME);
+            let step_height = match physics.mode {
+                MovementMode::Fly => {
+                    physics.velocity = direction.normalize_or_zero() * settings.speed;
+                    0.0
+                }
+                MovementMode::Walk => {
+                    let horizontal = Vec3::new(direction.x, 0.0, direction.z).normalize_or_zero();
+                    physics.velocity.x = horizontal.x * settings.walk_speed;
+                    physics.velocity.z = horizontal.z * settings.walk_speed;
+                    physics.velocity.y = if jump && physics.on_ground {
+                        settings.jump_speed
+                    } else {
+                        (physics.velocity.y - settings.gravity * dt).max(-settings.max_fall_speed)
+                    };
+                    STEP_HEIGHT
+                }
+            };
+
+            let (motion, collision) = move_hitbox(
+                Hitbox::player(transform.translation),
+                physics.velocity * dt,
+                step_height,
+                // The blocks are swept all the way along the move, so the player can't go through
+                // them however far it moves. The blocks that aren't loaded are air.
+                |pos| {
+                    game_mode.get().collides()
+                        && world.get_block(pos.into()).is_some_and(blocks_movement)
+                },
+            );
+            transform.translation += motion;
+            physics.on_ground = collision.on_ground;
+            if collision.on_ground || collision.hit_ceiling {
+                physics.velocity.y = 0.0;
             }
 
             let t = transform.translation;
             // find the current chunk we are in
-            let tmp = position_to_chunk(t, CHUNK_DIMS);
+            let tmp = position_to_chunk_cords(t);
             if tmp != chunk.0 {
                 chunk.0 = tmp;
             }
-            // look_transform.eye = transform.translation;
         }
     } else {
         warn!("Primary window not found for `player_move`!");
@@ -205,3 +194,70 @@ pub(super) fn player_look(
         warn!("Primary window not found for `player_look`!");
     }
 }
+
+// How far from the middle of the world the player can teleport. Further out the coordinates of the
+// blocks get too big, and positions too coarse to move smoothly.
+const MAX_TP_DISTANCE: f32 = 100_000.0;
+
+// Where a teleport to `target` ends up: no further out than `MAX_TP_DISTANCE`, and no more than a
+// chunk below or above the world.
+pub(super) fn clamp_tp_target(target: Vec3) -> Vec3 {
+    target.clamp(
+        Vec3::new(-MAX_TP_DISTANCE, -(HEIGHT as f32), -MAX_TP_DISTANCE),
+        Vec3::new(
+            MAX_TP_DISTANCE,
+            (WORLD_HEIGHT + HEIGHT) as f32,
+            MAX_TP_DISTANCE,
+        ),
+    )
+}
+
+pub(super) fn tp_command(
+    mut commands: EventReader<ConsoleCommand>,
+    mut player: Query<(&mut Transform, &mut PlayerPhysics), With<FlyCam>>,
+    mut log: ResMut<ConsoleLog>,
+) {
+    for command in commands.read().filter(|command| command.name == "tp") {
+        let (Some(x), Some(y), Some(z)) = (command.number(0), command.number(1), command.number(2))
+        else {
+            continue;
+        };
+        let Ok((mut transform, mut physics)) = player.get_single_mut() else {
+            log.print("There is no player to teleport");
+            continue;
+        };
+        let target = clamp_tp_target(Vec3::new(x, y, z));
+        transform.translation = target;
+        physics.velocity = Vec3::ZERO;
+        log.print(format!(
+            "Teleported to {} {} {}",
+            target.x, target.y, target.z
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn teleports_stay_in_the_world() {
+        let inside = Vec3::new(-1234.5, 70.0, 99_000.0);
+        assert_eq!(clamp_tp_target(inside), inside);
+        let far = clamp_tp_target(Vec3::new(1e30, 1e30, -1e30));
+        assert_eq!(
+            far,
+            Vec3::new(
+                MAX_TP_DISTANCE,
+                (WORLD_HEIGHT + HEIGHT) as f32,
+                -MAX_TP_DISTANCE
+            )
+        );
+        let cords = position_to_chunk_cords(far);
+        assert_eq!([cords[0], cords[2]], [6250, -6250]);
+        assert_eq!(
+            clamp_tp_target(Vec3::new(0.0, -500.0, 0.0)).y,
+            -(HEIGHT as f32)
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use bevy::prelude::*;
+
+// The size of the player's box, and how high above the bottom of it the camera is.
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+pub const EYE_HEIGHT: f32 = 1.62;
+// How high a ledge the player can walk onto without jumping, every solid block is a full block so
+// this lets the player walk up a single block.
+pub const STEP_HEIGHT: f32 = 1.0;
+// Boxes that touch are not overlapping, this keeps the player from getting stuck on the faces of
+// blocks it's standing on or walking along.
+const EPSILON: f32 = 0.001;
+
+// An axis aligned box in world coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hitbox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Hitbox {
+    // The player's box, when the camera is at `eye`.
+    pub fn player(eye: Vec3) -> Self {
+        let half_width = PLAYER_WIDTH / 2.0;
+        Hitbox {
+            min: eye - Vec3::new(half_width, EYE_HEIGHT, half_width),
+            max: eye + Vec3::new(half_width, PLAYER_HEIGHT - EYE_HEIGHT, half_width),
+        }
+    }
+
+    fn moved(self, axis: usize, distance: f32) -> Self {
+        let mut offset = Vec3::ZERO;
+        offset[axis] = distance;
+        Hitbox {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+}
+
+// What the box ran into while moving.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Collision {
+    pub on_ground: bool,
+    pub hit_ceiling: bool,
+    pub hit_wall: bool,
+}
+
+// The blocks (which are centered on whole coordinates) that overlap the range min..max on one axis.
+fn blocks_between(min: f32, max: f32) -> std::ops::RangeInclusive<i32> {
+    (min - 0.5 + EPSILON).floor() as i32 + 1..=(max + 0.5 - EPSILON).ceil() as i32 - 1
+}
+
+// How far the box can move along one axis (up to `distance`) before it hits a solid block.
+fn sweep_axis(
+    hitbox: Hitbox,
+    axis: usize,
+    distance: f32,
+    is_solid: &impl Fn([i32; 3]) -> bool,
+) -> f32 {
+    if distance == 0.0 {
+        return 0.0;
+    }
+    // The area the box passes through on the way.
+    let swept = if distance > 0.0 {
+        (hitbox.max[axis], hitbox.max[axis] + distance)
+    } else {
+        (hitbox.min[axis] + distance, hitbox.min[axis])
+    };
+    let ranges: [std::ops::RangeInclusive<i32>; 3] = std::array::from_fn(|a| {
+        if a == axis {
+            blocks_between(swept.0, swept.1)
+        } else {
+            blocks_between(hitbox.min[a], hitbox.max[a])
+        }
+    });
+    let mut allowed = distance;
+    for x in ranges[0].clone() {
+        for y in ranges[1].clone() {
+            for z in ranges[2].clone() {
+                if !is_solid([x, y, z]) {
+                    continue;
+                }
+                let block = [x, y, z][axis] as f32;
+                // Only the blocks in front of the box stop it, so a box that is already inside of a
+                // block can still get out of it.
+                if distance > 0.0 && block - 0.5 >= hitbox.max[axis] - EPSILON {
+                    allowed = allowed.min(block - 0.5 - hitbox.max[axis]);
+                } else if distance < 0.0 && block + 0.5 <= hitbox.min[axis] + EPSILON {
+                    allowed = allowed.max(block + 0.5 - hitbox.min[axis]);
+                }
+            }
+        }
+    }
+    if distance > 0.0 {
+        allowed.max(0.0)
+    } else {
+        allowed.min(0.0)
+    }
+}
+
+// Move the box along the horizontal axes, x before z. Every axis is swept with the box where the
+// previous one left it, so the box can't cut through the corner between two blocks.
+fn move_horizontally(
+    hitbox: Hitbox,
+    motion: Vec3,
+    is_solid: &impl Fn([i32; 3]) -> bool,
+) -> (Hitbox, bool) {
+    let mut hitbox = hitbox;
+    let mut hit_wall = false;
+    for axis in [0, 2] {
+        let allowed = sweep_axis(hitbox, axis, motion[axis], is_solid);
+        hit_wall |= allowed != motion[axis];
+        hitbox = hitbox.moved(axis, allowed);
+    }
+    (hitbox, hit_wall)
+}
+
+// Move the box by `motion`, stopping at solid blocks. A box on the ground that walks into a block
+// no higher than `step_height` steps up onto it. Returns how far the box actually moved.
+pub fn move_hitbox(
+    hitbox: Hitbox,
+    motion: Vec3,
+    step_height: f32,
+    is_solid: impl Fn([i32; 3]) -> bool,
+) -> (Vec3, Collision) {
+    let mut collision = Collision::default();
+    let dy = sweep_axis(hitbox, 1, motion.y, &is_solid);
+    collision.on_ground = motion.y < 0.0 && dy > motion.y;
+    collision.hit_ceiling = motion.y > 0.0 && dy < motion.y;
+    let start = hitbox.moved(1, dy);
+
+    let (mut end, hit_wall) = move_horizontally(start, motion, &is_solid);
+    collision.hit_wall = hit_wall;
+    if hit_wall && collision.on_ground && step_height > 0.0 {
+        // Try the same move from higher up, and then go back down onto whatever is there.
+        let up = sweep_axis(start, 1, step_height, &is_solid);
+        let (stepped, stepped_hit_wall) = move_horizontally(start.moved(1, up), motion, &is_solid);
+        let down = sweep_axis(stepped, 1, -up, &is_solid);
+        let stepped = stepped.moved(1, down);
+        let horizontal = |b: Hitbox| (b.min - start.min).xz().length();
+        if horizontal(stepped) > horizontal(end) + EPSILON {
+            end = stepped;
+            collision.hit_wall = stepped_hit_wall;
+        }
+    }
+    (end.min - hitbox.min, collision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The player's box standing with its feet at `feet`.
+    fn standing_at(feet: Vec3) -> Hitbox {
+        Hitbox::player(feet + Vec3::Y * EYE_HEIGHT)
+    }
+
+    fn floor(pos: [i32; 3]) -> bool {
+        pos[1] <= 0
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 0.01, "{} isn't {}", a, b);
+    }
+
+    #[test]
+    fn landing_on_the_ground() {
+        let hitbox = standing_at(Vec3::new(0.0, 2.0, 0.0));
+        let (moved, collision) = move_hitbox(hitbox, Vec3::new(0.0, -5.0, 0.0), 0.0, floor);
+        // The top of the floor is at 0.5.
+        assert_near(moved, Vec3::new(0.0, -1.5, 0.0));
+        assert!(collision.on_ground && !collision.hit_ceiling && !collision.hit_wall);
+
+        // Falling less than the distance to the ground isn't landing.
+        let (moved, collision) = move_hitbox(hitbox, Vec3::new(0.0, -1.0, 0.0), 0.0, floor);
+        assert_near(moved, Vec3::new(0.0, -1.0, 0.0));
+        assert!(!collision.on_ground);
+    }
+
+    #[test]
+    fn falling_fast_onto_a_thin_floor() {
+        // A floor one block thick, far below a box that falls faster than a block per step.
+        let thin_floor = |pos: [i32; 3]| pos[1] == 0;
+        let hitbox = standing_at(Vec3::new(0.0, 20.0, 0.0));
+        let (moved, collision) = move_hitbox(hitbox, Vec3::new(0.0, -50.0, 0.0), 0.0, thin_floor);
+        assert_near(moved, Vec3::new(0.0, -19.5, 0.0));
+        assert!(collision.on_ground);
+    }
+
+    #[test]
+    fn hitting_the_ceiling() {
+        let ceiling = |pos: [i32; 3]| pos[1] >= 4;
+        // The top of the box is at 3.0, the bottom of the ceiling at 3.5.
+        let hitbox = standing_at(Vec3::new(0.0, 3.0 - PLAYER_HEIGHT, 0.0));
+        let (moved, collision) = move_hitbox(hitbox, Vec3::new(0.0, 2.0, 0.0), 0.0, ceiling);
+        assert_near(moved, Vec3::new(0.0, 0.5, 0.0));
+        assert!(collision.hit_ceiling && !collision.on_ground);
+    }
+
+    #[test]
+    fn walking_into_a_corner() {
+        // Walls along x = 3 and z = 3, their faces are at 2.5.
+        let walls = |pos: [i32; 3]| floor(pos) || pos[0] >= 3 || pos[2] >= 3;
+        let hitbox = standing_at(Vec3::new(1.0, 0.5, 1.0));
+        let (moved, collision) = move_hitbox(hitbox, Vec3::new(3.0, 0.0, 3.0), STEP_HEIGHT, walls);
+        let stop = 2.5 - PLAYER_WIDTH / 2.0 - 1.0;
+        assert_near(moved, Vec3::new(stop, 0.0, stop));
+        assert!(collision.hit_wall);
+
+        // Moving diagonally past the corner of a single block doesn't cut through it.
+        let pillar = |pos: [i32; 3]| floor(pos) || (pos[0] == 2 && pos[2] == 2 && pos[1] < 5);
+        let (moved, collision) = move_hitbox(hitbox, Vec3::new(1.0, 0.0, 1.0), 0.0, pillar);
+        let end = Hitbox {
+            min: hitbox.min + moved,
+            max: hitbox.max + moved,
+        };
+        assert!(collision.hit_wall);
+        assert!(end.max.x <= 1.5 + EPSILON || end.max.z <= 1.5 + EPSILON);
+    }
+
+    #[test]
+    fn stepping_up_onto_a_ledge() {
+        // A ledge one block high starting at x = 2, its top is at 1.5.
+        let ledge = |pos: [i32; 3]| floor(pos) || (pos[0] >= 2 && pos[1] == 1);
+        let hitbox = standing_at(Vec3::new(1.0, 0.5, 0.0));
+        let walk = Vec3::new(1.0, -0.1, 0.0);
+        let (moved, collision) = move_hitbox(hitbox, walk, STEP_HEIGHT, ledge);
+        assert_near(moved, Vec3::new(1.0, 1.0, 0.0));
+        assert!(!collision.hit_wall);
+
+        // Without stepping, the ledge is a wall.
+        let (moved, collision) = move_hitbox(hitbox, walk, 0.0, ledge);
+        assert_near(moved, Vec3::new(1.5 - PLAYER_WIDTH / 2.0 - 1.0, 0.0, 0.0));
+        assert!(collision.hit_wall);
+
+        // A wall two blocks high can't be stepped onto.
+        let wall = |pos: [i32; 3]| floor(pos) || (pos[0] >= 2 && pos[1] <= 2);
+        let (moved, collision) = move_hitbox(hitbox, walk, STEP_HEIGHT, wall);
+        assert_near(moved, Vec3::new(1.5 - PLAYER_WIDTH / 2.0 - 1.0, 0.0, 0.0));
+        assert!(collision.hit_wall);
+
+        // In the air, the player doesn't step up.
+        let jumping = standing_at(Vec3::new(1.0, 0.6, 0.0));
+        let (moved, _) = move_hitbox(jumping, Vec3::new(1.0, 0.0, 0.0), STEP_HEIGHT, ledge);
+        assert_near(moved, Vec3::new(1.5 - PLAYER_WIDTH / 2.0 - 1.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+use bevy_meshem::prelude::Face::{self, *};
+
+// The block a ray hit, the face of the block it entered through, where it hit it, and how far from
+// the origin of the ray that is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+    pub block: [i32; 3],
+    pub face: Face,
+    pub point: Vec3,
+    pub distance: f32,
+}
+
+// The face a ray moving along `axis` in the direction of `step` enters a block through.
+fn entry_face(axis: usize, step: i32) -> Face {
+    match (axis, step > 0) {
+        (0, true) => Left,
+        (0, false) => Right,
+        (1, true) => Bottom,
+        (1, false) => Top,
+        (2, true) => Forward,
+        _ => Back,
+    }
+}
+
+// Walk along the ray block by block (Amanatides & Woo's voxel traversal), and return the first
+// block `is_hit` returns true for, up to `max_distance` away from `origin`. Every block the ray
+// passes through is visited exactly once, even when it only clips the corner of a block.
+// The block the ray starts in is checked too, it's hit through the face that looks back at the
+// origin of the ray.
+pub fn raycast_voxels(
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    mut is_hit: impl FnMut([i32; 3]) -> bool,
+) -> Option<RaycastHit> {
+    let direction = direction.try_normalize()?;
+    // Blocks are centered on whole coordinates, moving everything by half a block puts the block
+    // `b` between b and b + 1, which makes the math simpler.
+    let start = origin + Vec3::splat(0.5);
+    let mut block = [
+        start.x.floor() as i32,
+        start.y.floor() as i32,
+        start.z.floor() as i32,
+    ];
+    let mut step = [0; 3];
+    // How far along the ray the next border between blocks is on each axis, and how far apart
+    // the borders are.
+    let mut next_border = [f32::INFINITY; 3];
+    let mut border_distance = [f32::INFINITY; 3];
+    for axis in 0..3 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            next_border[axis] = (block[axis] as f32 + 1.0 - start[axis]) / direction[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            next_border[axis] = (block[axis] as f32 - start[axis]) / direction[axis];
+        } else {
+            continue;
+        }
+        border_distance[axis] = 1.0 / direction[axis].abs();
+    }
+
+    if is_hit(block) {
+        let axis = (0..3)
+            .max_by(|a, b| direction[*a].abs().total_cmp(&direction[*b].abs()))
+            .unwrap_or(0);
+        return Some(RaycastHit {
+            block,
+            face: entry_face(axis, step[axis]),
+            point: origin,
+            distance: 0.0,
+        });
+    }
+
+    loop {
+        let axis = (0..3)
+            .min_by(|a, b| next_border[*a].total_cmp(&next_border[*b]))
+            .unwrap_or(0);
+        let distance = next_border[axis];
+        if distance > max_distance {
+            return None;
+        }
+        block[axis] += step[axis];
+        next_border[axis] += border_distance[axis];
+        if is_hit(block) {
+            return Some(RaycastHit {
+                block,
+                face: entry_face(axis, step[axis]),
+                point: origin + direction * distance,
+                distance,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_hit(hit: Option<RaycastHit>, block: [i32; 3], face: Face, distance: f32) {
+        let hit = hit.expect("The ray hits a block");
+        assert_eq!((hit.block, hit.face), (block, face));
+        assert!((hit.distance - distance).abs() < 1e-4, "{:?}", hit);
+    }
+
+    // The blocks the ray goes through, in order.
+    fn visited(origin: Vec3, direction: Vec3, max_distance: f32) -> Vec<[i32; 3]> {
+        let mut blocks = vec![];
+        raycast_voxels(origin, direction, max_distance, |block| {
+            blocks.push(block);
+            false
+        });
+        blocks
+    }
+
+    #[test]
+    fn axis_aligned_rays() {
+        let wall = |block: [i32; 3]| block[0] >= 5;
+        let hit = raycast_voxels(Vec3::ZERO, Vec3::X, 10.0, wall);
+        assert_hit(hit, [5, 0, 0], Left, 4.5);
+        assert_eq!(hit.unwrap().point, Vec3::new(4.5, 0.0, 0.0));
+        // The wall is further than the ray reaches.
+        assert_eq!(raycast_voxels(Vec3::ZERO, Vec3::X, 4.0, wall), None);
+
+        let ground = |block: [i32; 3]| block[1] <= -3;
+        let hit = raycast_voxels(Vec3::new(0.3, 0.2, -0.4), Vec3::NEG_Y, 10.0, ground);
+        assert_hit(hit, [0, -3, 0], Top, 2.7);
+        assert_eq!(
+            visited(Vec3::ZERO, Vec3::Z, 2.6),
+            vec![[0, 0, 0], [0, 0, 1], [0, 0, 2], [0, 0, 3]]
+        );
+    }
+
+    #[test]
+    fn diagonal_rays_cross_every_block_on_the_way() {
+        // Every block the ray visits shares a face with the one before it, even when the ray goes
+        // exactly through the corners between blocks.
+        for direction in [
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(0.3, -0.7, 0.9),
+        ] {
+            let blocks = visited(Vec3::ZERO, direction, 20.0);
+            assert!(blocks.len() > 20);
+            for pair in blocks.windows(2) {
+                let steps: i32 = (0..3).map(|i| (pair[1][i] - pair[0][i]).abs()).sum();
+                assert_eq!(steps, 1, "{:?} after {:?}", pair[1], pair[0]);
+            }
+        }
+        let target = |block: [i32; 3]| block == [3, 3, 3];
+        let hit = raycast_voxels(Vec3::ZERO, Vec3::ONE, 10.0, target);
+        assert_eq!(hit.map(|hit| hit.block), Some([3, 3, 3]));
+        assert!((hit.unwrap().distance - 2.5 * 3f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn negative_directions_and_chunk_borders() {
+        // The blocks around the border at 0 between two chunks, which is where the rounding of
+        // negative coordinates goes wrong.
+        let hit = raycast_voxels(Vec3::new(0.2, 0.0, 0.0), Vec3::NEG_X, 5.0, |block| {
+            block[0] == -1
+        });
+        assert_hit(hit, [-1, 0, 0], Right, 0.7);
+        let hit = raycast_voxels(Vec3::new(-0.6, 0.0, 0.0), Vec3::NEG_X, 5.0, |block| {
+            block[0] == -1
+        });
+        assert_hit(hit, [-1, 0, 0], Right, 0.0);
+        let hit = raycast_voxels(Vec3::new(0.0, 0.0, 14.0), Vec3::Z, 5.0, |block| {
+            block[2] >= 16
+        });
+        assert_hit(hit, [0, 0, 16], Forward, 1.5);
+
+        let direction = Vec3::new(-1.0, 0.0, -0.5);
+        let hit = raycast_voxels(Vec3::ZERO, direction, 10.0, |block| block[0] <= -4);
+        let distance = 3.5 * direction.length();
+        assert_hit(hit, [-4, 0, -2], Right, distance);
+        let hit = raycast_voxels(Vec3::ZERO, Vec3::new(0.0, -1.0, -1.0), 10.0, |block| {
+            block[2] <= -2
+        });
+        assert_hit(hit, [0, -2, -2], Back, 1.5 * 2f32.sqrt());
+    }
+
+    #[test]
+    fn starting_inside_a_block() {
+        let hit = raycast_voxels(Vec3::new(0.2, 0.1, 0.0), Vec3::X, 5.0, |_| true);
+        assert_hit(hit, [0, 0, 0], Left, 0.0);
+        assert_eq!(hit.unwrap().point, Vec3::new(0.2, 0.1, 0.0));
+        // It's hit through the face along the axis the ray mostly goes along.
+        let hit = raycast_voxels(Vec3::ZERO, Vec3::new(0.2, -1.0, 0.3), 5.0, |_| true);
+        assert_hit(hit, [0, 0, 0], Top, 0.0);
+        // Without a direction, nothing is hit.
+        assert_eq!(raycast_voxels(Vec3::ZERO, Vec3::ZERO, 5.0, |_| true), None);
+    }
+}
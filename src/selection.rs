@@ -0,0 +1,190 @@
+use crate::*;
+use bevy::pbr::NotShadowCaster;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+// How many steps the cracks on a block that is being broken grow in.
+const CRACK_STAGES: usize = 10;
+const CRACK_TEXTURE_SIZE: usize = 16;
+// How many cracks grow out of the middle of the block, and how long they get.
+const CRACKS: usize = 5;
+const CRACK_LENGTH: usize = 12;
+// The outline and the overlay are drawn a bit outside of the block, so they aren't hidden by it.
+const OUTLINE_SCALE: f32 = 1.004;
+const OUTLINE_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.85);
+const FACE_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.6);
+
+// The materials of the crack overlay, one for each stage.
+#[derive(Resource)]
+struct CrackMaterials(Vec<Handle<StandardMaterial>>);
+
+// Marks the cube that is drawn on top of the block that is being broken.
+#[derive(Component)]
+struct CrackOverlay;
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(Startup, setup_crack_overlay).add_systems(
+            Update,
+            (draw_selection, update_crack_overlay).after(add_break_detector),
+        );
+    }
+}
+
+// The cracks are random walks out of the middle of the texture, every pixel remembers how far into
+// its walk it is, so the cracks can grow from the middle outwards stage by stage.
+fn crack_pattern() -> [Option<f32>; CRACK_TEXTURE_SIZE * CRACK_TEXTURE_SIZE] {
+    let mut pattern = [None; CRACK_TEXTURE_SIZE * CRACK_TEXTURE_SIZE];
+    let mut random: u32 = 0x9E37_79B9;
+    let mut next = move || {
+        random ^= random << 13;
+        random ^= random >> 17;
+        random ^= random << 5;
+        random
+    };
+    let directions = [
+        (1, 0),
+        (1, 1),
+        (0, 1),
+        (-1, 1),
+        (-1, 0),
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+    ];
+    for crack in 0..CRACKS {
+        let (mut x, mut y) = (CRACK_TEXTURE_SIZE as i32 / 2, CRACK_TEXTURE_SIZE as i32 / 2);
+        // Spread the cracks out evenly, and let each one wander a little.
+        let mut direction = crack * directions.len() / CRACKS;
+        for step in 0..CRACK_LENGTH {
+            let index = x as usize + y as usize * CRACK_TEXTURE_SIZE;
+            let order = step as f32 / CRACK_LENGTH as f32;
+            pattern[index] = Some(pattern[index].map_or(order, |o: f32| o.min(order)));
+            direction = match next() % 4 {
+                0 => (direction + 1) % directions.len(),
+                1 => (direction + directions.len() - 1) % directions.len(),
+                _ => direction,
+            };
+            let (dx, dy) = directions[direction];
+            if !(0..CRACK_TEXTURE_SIZE as i32).contains(&(x + dx))
+                || !(0..CRACK_TEXTURE_SIZE as i32).contains(&(y + dy))
+            {
+                break;
+            }
+            (x, y) = (x + dx, y + dy);
+        }
+    }
+    pattern
+}
+
+fn crack_image(
+    pattern: &[Option<f32>; CRACK_TEXTURE_SIZE * CRACK_TEXTURE_SIZE],
+    stage: usize,
+) -> Image {
+    let grown = (stage + 1) as f32 / CRACK_STAGES as f32;
+    let data = pattern
+        .iter()
+        .flat_map(|order| match order {
+            Some(order) if *order < grown => [0, 0, 0, 170],
+            _ => [0, 0, 0, 0],
+        })
+        .collect();
+    Image::new(
+        Extent3d {
+            width: CRACK_TEXTURE_SIZE as u32,
+            height: CRACK_TEXTURE_SIZE as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn setup_crack_overlay(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let pattern = crack_pattern();
+    let crack_materials: Vec<Handle<StandardMaterial>> = (0..CRACK_STAGES)
+        .map(|stage| {
+            materials.add(StandardMaterial {
+                base_color_texture: Some(images.add(crack_image(&pattern, stage))),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            })
+        })
+        .collect();
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+            material: crack_materials[0].clone(),
+            transform: Transform::from_scale(Vec3::splat(OUTLINE_SCALE)),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        NotShadowCaster,
+        CrackOverlay,
+    ));
+    commands.insert_resource(CrackMaterials(crack_materials));
+}
+
+// Outline the block the player is looking at, and the face of it the player is looking at.
+fn draw_selection(target: Res<TargetedBlock>, mut gizmos: Gizmos) {
+    let Some(hit) = target.0 else {
+        return;
+    };
+    let center = Vec3::new(
+        hit.block[0] as f32,
+        hit.block[1] as f32,
+        hit.block[2] as f32,
+    );
+    gizmos.cuboid(
+        Transform::from_translation(center).with_scale(Vec3::splat(OUTLINE_SCALE)),
+        OUTLINE_COLOR,
+    );
+    let neighbor = adjacent_cords(hit.block, hit.face);
+    let normal = Vec3::new(
+        (neighbor[0] - hit.block[0]) as f32,
+        (neighbor[1] - hit.block[1]) as f32,
+        (neighbor[2] - hit.block[2]) as f32,
+    );
+    gizmos.rect(
+        center + normal * OUTLINE_SCALE / 2.0,
+        Quat::from_rotation_arc(Vec3::Z, normal),
+        Vec2::splat(0.7),
+        FACE_COLOR,
+    );
+}
+
+fn update_crack_overlay(
+    progress: Res<BreakProgress>,
+    crack_materials: Res<CrackMaterials>,
+    mut overlay: Query<
+        (
+            &mut Transform,
+            &mut Visibility,
+            &mut Handle<StandardMaterial>,
+        ),
+        With<CrackOverlay>,
+    >,
+) {
+    let Ok((mut transform, mut visibility, mut material)) = overlay.get_single_mut() else {
+        return;
+    };
+    match progress.block {
+        Some(block) if progress.fraction() > 0.0 => {
+            let stage =
+                ((progress.fraction() * CRACK_STAGES as f32) as usize).min(CRACK_STAGES - 1);
+            transform.translation = Vec3::new(block[0] as f32, block[1] as f32, block[2] as f32);
+            *material = crack_materials.0[stage].clone();
+            *visibility = Visibility::Visible;
+        }
+        _ => *visibility = Visibility::Hidden,
+    }
+}
@@ -0,0 +1,120 @@
+use crate::*;
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+
+pub const SERVER_TICKS_PER_SECOND: f64 = 60.0;
+
+// Runs the world without a window. There is no player, so the chunks around the spawn point are
+// kept loaded instead.
+pub struct ServerPlugin;
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(Startup, spawn_viewer)
+            .add_systems(Update, read_stdin);
+
+        // Resources
+        app.insert_resource(StdinLines(Mutex::new(spawn_stdin_reader())));
+    }
+}
+
+// The lines typed into the terminal of the server are its console.
+#[derive(Resource)]
+struct StdinLines(Mutex<Receiver<String>>);
+
+// Reading the terminal blocks, so it's done on its own thread.
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if sender.send(line).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+fn read_stdin(lines: Res<StdinLines>, mut input: EventWriter<ConsoleInput>) {
+    let lines = lines.0.lock().expect("Stdin reader shouldn't panic");
+    while let Ok(line) = lines.try_recv() {
+        input.send(ConsoleInput {
+            line,
+            source: CommandSource::Server,
+        });
+    }
+}
+
+// The chunks are loaded around the entities with a `CurrentChunk`.
+#[derive(Component)]
+pub struct Viewer;
+
+fn spawn_viewer(mut commands: Commands, world_settings: Res<WorldSettings>) {
+    let spawn_point = spawn_point(&world_settings);
+    commands.spawn((
+        TransformBundle::from_transform(Transform::from_translation(spawn_point)),
+        CurrentChunk(position_to_chunk_cords(spawn_point)),
+        Viewer,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The world runs on top of the `MinimalPlugins` like on a headless server: the chunks around
+    // the spawn point are loaded and lit, but never meshed.
+    #[test]
+    fn headless_chunks_are_lit_but_not_meshed() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, SimulationPlugin))
+            .add_systems(Startup, spawn_viewer)
+            .insert_resource(WorldSettings {
+                render_distance: 1,
+                ..default()
+            })
+            .insert_resource(WorldSave {
+                dir: std::env::temp_dir().join("minecraft_bevy_headless"),
+            });
+        for _ in 0..2000 {
+            app.update();
+            if app.world.resource::<State<InitialChunkLoadState>>().get()
+                == &InitialChunkLoadState::Complete
+            {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(
+            app.world.resource::<State<InitialChunkLoadState>>().get(),
+            &InitialChunkLoadState::Complete
+        );
+
+        let chunk_map = app.world.resource::<ChunkMap>();
+        assert_eq!(chunk_map.states().count(), 9 * WORLD_HEIGHT_CHUNKS as usize);
+        assert!(chunk_map
+            .states()
+            .all(|(_, state)| state == ChunkState::Ready));
+        let meshes = app
+            .world
+            .query_filtered::<(), Or<(With<NewChunkMesh>, With<ToCull>)>>()
+            .iter(&app.world)
+            .count();
+        assert_eq!(meshes, 0);
+        // The top of the world is lit by the sky.
+        let top = app
+            .world
+            .query::<&Chunk>()
+            .iter(&app.world)
+            .find(|chunk| chunk.cords[1] == WORLD_HEIGHT_CHUNKS - 1)
+            .expect("The chunks at the top of the world are loaded");
+        assert!(top.meta_data.is_none());
+        let index = one_d_cords([0, HEIGHT - 1, 0], CHUNK_DIMS);
+        assert_eq!(top.light.get(index, LightChannel::Sky), MAX_LIGHT);
+    }
+}
@@ -0,0 +1,406 @@
+use crate::{GameMode, TerrainSettings, WorldSave, SAVE_DIR};
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The settings of a world are stored in this file inside the world's save directory, so the world
+// is always generated the same way when it's loaded again.
+pub const WORLD_SETTINGS_FILE: &str = "world.ron";
+pub const USAGE: &str = "Usage: minecraft_bevy [--world <dir>] [--config <file.ron>] \
+                     [--seed <u32>] [--render-distance <chunks>] \
+                     [--preset <default|caveless|smooth|large_biomes>] \
+                     [--game-mode <survival|creative|spectator>] [--cheats] [--meshing <culling|greedy>] \
+                     [--lod-distance <chunks>] [--headless] [--port <port>] [--connect <host:port>] [--name <name>]";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GeneratorPreset {
+    #[default]
+    Default,
+    Caveless,
+    Smooth,
+    LargeBiomes,
+}
+
+impl GeneratorPreset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(GeneratorPreset::Default),
+            "caveless" => Some(GeneratorPreset::Caveless),
+            "smooth" => Some(GeneratorPreset::Smooth),
+            "large_biomes" => Some(GeneratorPreset::LargeBiomes),
+            _ => None,
+        }
+    }
+
+    // The terrain settings the preset starts from.
+    pub fn terrain(self) -> TerrainSettings {
+        let default = TerrainSettings::default();
+        match self {
+            GeneratorPreset::Default => default,
+            GeneratorPreset::Caveless => TerrainSettings {
+                cave_radius: 0.0,
+                ..default
+            },
+            GeneratorPreset::Smooth => TerrainSettings {
+                overhang_strength: 0.0,
+                ..default
+            },
+            GeneratorPreset::LargeBiomes => TerrainSettings {
+                climate_frequency: default.climate_frequency / 4.0,
+                continentalness_frequency: default.continentalness_frequency / 4.0,
+                ..default
+            },
+        }
+    }
+}
+
+// How the chunks are turned into meshes. Culling makes a quad for every face that can be seen,
+// greedy meshing merges the faces that lie next to each other into bigger quads, so the meshes
+// have fewer vertices but take longer to make.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MeshingMode {
+    #[default]
+    Culling,
+    Greedy,
+}
+
+impl MeshingMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "culling" => Some(MeshingMode::Culling),
+            "greedy" => Some(MeshingMode::Greedy),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct WorldSettings {
+    pub seed: u32,
+    // Render distance should be above 1.
+    pub render_distance: i32,
+    pub preset: GeneratorPreset,
+    // If this is None, the terrain settings of the preset are used.
+    #[serde(default)]
+    pub terrain: Option<TerrainSettings>,
+    #[serde(default)]
+    pub game_mode: GameMode,
+    // Whether the player can switch game modes while playing.
+    #[serde(default)]
+    pub cheats: bool,
+    #[serde(default)]
+    pub meshing: MeshingMode,
+    // Past the render distance and up to this many chunks away, the terrain is shown as a height map
+    // that gets coarser with the distance. It's off when it isn't above the render distance.
+    #[serde(default = "default_lod_distance")]
+    pub lod_distance: i32,
+}
+
+fn default_lod_distance() -> i32 {
+    24
+}
+
+impl Default for WorldSettings {
+    fn default() -> Self {
+        WorldSettings {
+            seed: 5,
+            render_distance: 6,
+            preset: GeneratorPreset::Default,
+            terrain: None,
+            game_mode: GameMode::Survival,
+            cheats: false,
+            meshing: MeshingMode::Culling,
+            lod_distance: default_lod_distance(),
+        }
+    }
+}
+
+impl WorldSettings {
+    pub fn terrain(&self) -> TerrainSettings {
+        self.terrain.unwrap_or_else(|| self.preset.terrain())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let ron = fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        ron::from_str(&ron).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let ron = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{:?}: {}", parent, e))?;
+        }
+        fs::write(path, ron).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    // Build the settings from the command line arguments (without the program name). The settings
+    // are taken from, in order of priority: the flags, the `--config` file, and the defaults.
+    // If the world was saved before, the generation settings it was saved with always win over
+    // the others, and only the settings of the view, the game mode and cheats can be changed.
+    pub fn from_args(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(WorldSettings, WorldSave), String> {
+        let mut world_dir = PathBuf::from(SAVE_DIR);
+        let mut config = None;
+        let mut seed = None;
+        let mut render_distance = None;
+        let mut preset = None;
+        let mut game_mode = None;
+        let mut cheats = false;
+        let mut meshing = None;
+        let mut lod_distance = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}\n{}", arg, USAGE))
+            };
+            match arg.as_str() {
+                "--world" => world_dir = PathBuf::from(value()?),
+                "--config" => config = Some(PathBuf::from(value()?)),
+                "--seed" => seed = Some(parse(&arg, &value()?)?),
+                "--render-distance" => render_distance = Some(parse(&arg, &value()?)?),
+                "--lod-distance" => lod_distance = Some(parse(&arg, &value()?)?),
+                "--preset" => {
+                    let name = value()?;
+                    preset = Some(
+                        GeneratorPreset::from_name(&name)
+                            .ok_or_else(|| format!("Unknown preset {}\n{}", name, USAGE))?,
+                    );
+                }
+                "--game-mode" => {
+                    let name = value()?;
+                    game_mode = Some(
+                        GameMode::from_name(&name)
+                            .ok_or_else(|| format!("Unknown game mode {}\n{}", name, USAGE))?,
+                    );
+                }
+                "--cheats" => cheats = true,
+                "--meshing" => {
+                    let name = value()?;
+                    meshing = Some(
+                        MeshingMode::from_name(&name)
+                            .ok_or_else(|| format!("Unknown meshing {}\n{}", name, USAGE))?,
+                    );
+                }
+                _ => return Err(format!("Unknown argument {}\n{}", arg, USAGE)),
+            }
+        }
+
+        let explicit = config.is_some() || seed.is_some() || preset.is_some();
+        let has_config = config.is_some();
+        let mut settings = match config {
+            Some(path) => WorldSettings::load(&path)?,
+            None => WorldSettings::default(),
+        };
+        settings.seed = seed.unwrap_or(settings.seed);
+        settings.preset = preset.unwrap_or(settings.preset);
+        settings.render_distance = render_distance.unwrap_or(settings.render_distance);
+        settings.game_mode = game_mode.unwrap_or(settings.game_mode);
+        settings.cheats |= cheats;
+        settings.meshing = meshing.unwrap_or(settings.meshing);
+        settings.lod_distance = lod_distance.unwrap_or(settings.lod_distance);
+        if settings.render_distance < 2 {
+            return Err(format!(
+                "Render distance should be above 1, got {}",
+                settings.render_distance
+            ));
+        }
+
+        let settings_path = world_dir.join(WORLD_SETTINGS_FILE);
+        if settings_path.exists() {
+            let saved = WorldSettings::load(&settings_path)?;
+            if explicit && (saved.seed != settings.seed || saved.preset != settings.preset) {
+                eprintln!(
+                    "The world in {:?} was created with seed {} and preset {:?}, using them",
+                    world_dir, saved.seed, saved.preset
+                );
+            }
+            if has_config && settings.terrain.is_some() && saved.terrain != settings.terrain {
+                eprintln!(
+                    "The world in {:?} was created with other terrain settings, using them",
+                    world_dir
+                );
+            }
+            settings = WorldSettings {
+                render_distance: settings.render_distance,
+                game_mode: settings.game_mode,
+                cheats: settings.cheats,
+                meshing: settings.meshing,
+                lod_distance: settings.lod_distance,
+                ..saved
+            };
+        } else {
+            settings.save(&settings_path)?;
+        }
+        Ok((settings, WorldSave { dir: world_dir }))
+    }
+}
+
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {} for {}\n{}", value, arg, USAGE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::default;
+
+    // A world directory of its own for every test run, removed when the test is done.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "minecraft_bevy_settings_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn from_args(dir: &Path, args: &[&str]) -> Result<WorldSettings, String> {
+        let world = ["--world", dir.to_str().unwrap()];
+        let args = world.iter().chain(args).map(|arg| arg.to_string());
+        let (settings, save) = WorldSettings::from_args(args)?;
+        assert_eq!(save.dir, dir);
+        Ok(settings)
+    }
+
+    #[test]
+    fn flags() {
+        let dir = test_dir("flags");
+        let settings = from_args(
+            &dir,
+            &[
+                "--seed",
+                "42",
+                "--render-distance",
+                "3",
+                "--preset",
+                "caveless",
+                "--game-mode",
+                "creative",
+                "--cheats",
+                "--meshing",
+                "greedy",
+                "--lod-distance",
+                "10",
+            ],
+        )
+        .unwrap();
+        assert_eq!(settings.seed, 42);
+        assert_eq!(settings.render_distance, 3);
+        assert_eq!(settings.preset, GeneratorPreset::Caveless);
+        assert_eq!(settings.game_mode, GameMode::Creative);
+        assert!(settings.cheats);
+        assert_eq!(settings.meshing, MeshingMode::Greedy);
+        assert_eq!(settings.lod_distance, 10);
+        // A new world is saved with the settings it was made with.
+        let saved = WorldSettings::load(&dir.join(WORLD_SETTINGS_FILE)).unwrap();
+        assert_eq!((saved.seed, saved.preset), (42, GeneratorPreset::Caveless));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flags_win_over_the_config() {
+        let dir = test_dir("config");
+        let config = dir.join("config.ron");
+        WorldSettings {
+            seed: 7,
+            render_distance: 3,
+            preset: GeneratorPreset::Smooth,
+            ..default()
+        }
+        .save(&config)
+        .unwrap();
+        let settings = from_args(
+            &dir.join("world"),
+            &["--config", config.to_str().unwrap(), "--seed", "8"],
+        )
+        .unwrap();
+        assert_eq!(settings.seed, 8);
+        assert_eq!(settings.render_distance, 3);
+        assert_eq!(settings.preset, GeneratorPreset::Smooth);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_saved_world_keeps_how_it_was_generated() {
+        let dir = test_dir("saved");
+        from_args(&dir, &["--seed", "1", "--preset", "smooth"]).unwrap();
+        let settings = from_args(
+            &dir,
+            &[
+                "--seed",
+                "2",
+                "--preset",
+                "caveless",
+                "--render-distance",
+                "4",
+                "--game-mode",
+                "creative",
+            ],
+        )
+        .unwrap();
+        assert_eq!(settings.seed, 1);
+        assert_eq!(settings.preset, GeneratorPreset::Smooth);
+        // The settings that don't change the terrain can still be changed.
+        assert_eq!(settings.render_distance, 4);
+        assert_eq!(settings.game_mode, GameMode::Creative);
+        let saved = WorldSettings::load(&dir.join(WORLD_SETTINGS_FILE)).unwrap();
+        assert_eq!(saved.game_mode, GameMode::Survival);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_saved_world_keeps_its_terrain() {
+        let dir = test_dir("terrain");
+        let config = dir.join("config.ron");
+        let terrain = TerrainSettings {
+            cave_radius: 0.5,
+            ..default()
+        };
+        WorldSettings {
+            terrain: Some(terrain),
+            ..default()
+        }
+        .save(&config)
+        .unwrap();
+        let world = dir.join("world");
+        from_args(&world, &[]).unwrap();
+        let settings = from_args(&world, &["--config", config.to_str().unwrap()]).unwrap();
+        assert_eq!(settings.terrain, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bad_values() {
+        let dir = test_dir("bad");
+        for args in [
+            &["--seed", "-1"][..],
+            &["--seed", "many"],
+            &["--seed"],
+            &["--render-distance", "far"],
+            &["--render-distance", "1"],
+            &["--preset", "flat"],
+            &["--game-mode", "hardcore"],
+            &["--meshing", "marching_cubes"],
+            &["--config", dir.join("missing.ron").to_str().unwrap()],
+            &["--fly"],
+        ] {
+            assert!(
+                from_args(&dir, args).is_err(),
+                "{:?} should be an error",
+                args
+            );
+        }
+        // Nothing is saved for arguments that aren't right.
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,374 @@
+use crate::*;
+use futures_lite::future;
+
+#[derive(Resource)]
+pub struct GlobalSecondsCounter(u128);
+
+#[derive(Component)]
+struct LoadedChunks(usize);
+
+// Everything that keeps the world running: loading and saving chunks, editing blocks, light and
+// water. It doesn't need a window or a GPU, so it runs the same in the client and in a headless
+// server.
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        // Plugins
+        app.add_plugins((
+            ChunkPlugin,
+            LightPlugin,
+            FluidPlugin,
+            ConsolePlugin,
+            WorldEditPlugin,
+        ));
+
+        // Resources
+        app.init_resource::<BlockRegistry>()
+            .insert_resource(GlobalSecondsCounter(0));
+
+        // Events
+        app.add_event::<BlockChange>().add_event::<BlockEdited>();
+
+        // Systems
+        app.add_systems(Startup, spawn_loaded_chunks_counter)
+            .add_systems(
+                Update,
+                check_if_loaded.run_if(in_state(InitialChunkLoadState::MeshesLoaded)),
+            )
+            .add_systems(
+                Update,
+                // Checks the chunk map after the chunks that left the range were unloaded from it.
+                handle_tasks.after(chunk::systems::frame_chunk_update),
+            )
+            .add_systems(
+                PostUpdate,
+                (
+                    handle_block_break_place.run_if(world_is_local),
+                    update_seconds,
+                ),
+            );
+    }
+}
+
+// Where players start, right above the surface at the middle of the world.
+pub fn spawn_point(world_settings: &WorldSettings) -> Vec3 {
+    let surface =
+        TerrainGenerator::new(world_settings.seed, world_settings.terrain()).surface_height(0, 0);
+    Vec3::new(0.0, surface as f32 + 0.75 + EYE_HEIGHT, 0.0)
+}
+
+fn update_seconds(time: Res<Time>, mut sec: ResMut<GlobalSecondsCounter>) {
+    if time.elapsed_seconds() as u128 != sec.0 {
+        sec.0 = time.elapsed_seconds() as u128;
+    }
+}
+
+fn spawn_loaded_chunks_counter(mut commands: Commands) {
+    commands.spawn(LoadedChunks(0));
+}
+
+// Spawn the chunks whose meshes are done, on the entities of their tasks.
+#[allow(clippy::too_many_arguments)]
+fn handle_tasks(
+    mut commands: Commands,
+    mut transform_tasks: Query<(Entity, &mut ComputeChunk)>,
+    mut chunk_map: ResMut<ChunkMap>,
+    current_state: Res<State<InitialChunkLoadState>>,
+    mut loaded_chunks: Query<(Entity, &mut LoadedChunks)>,
+    mut next_state: ResMut<NextState<InitialChunkLoadState>>,
+    world_settings: Res<WorldSettings>,
+    budget: Res<ChunkLoadBudget>,
+) {
+    let render_distance = world_settings.render_distance;
+    let mut spawned = 0;
+    // Iterate over the tasks.
+    for (entity, mut task) in transform_tasks.iter_mut() {
+        // The chunks that are done past the budget are spawned in the next frames.
+        if spawned == budget.spawns_per_frame {
+            break;
+        }
+        let Some((mesh, grid, light, cords)) = future::block_on(future::poll_once(&mut task.0))
+        else {
+            continue;
+        };
+        // A finished task can't be polled again, it's removed even when its chunk isn't spawned.
+        let mut ent = commands.entity(entity);
+        ent.remove::<ComputeChunk>();
+        // The task of a chunk that was unloaded or replaced while it was meshed is despawned with
+        // it, it only gets here if it was done in the same frame.
+        if !chunk_map.is_current(cords, entity, ChunkState::Meshing) {
+            continue;
+        }
+
+        // // Extract the vertex data for the physics engine.
+        // let pos_vertices = extract_position_vertex_data(&culled_mesh);
+        // // Extract the indices for the physics engine.
+        // let indices = extract_indices_data(&culled_mesh);
+
+        let (mesh, metadata) = mesh.unzip();
+        let metadata = metadata.flatten();
+        let is_culled = metadata.is_some();
+        // The task's entity becomes the chunk's.
+        ent.insert((
+            SpatialBundle::from_transform(Transform::from_xyz(
+                cords[0] as f32 * CHUNK_DIMS.0 as f32,
+                cords[1] as f32 * CHUNK_DIMS.1 as f32,
+                cords[2] as f32 * CHUNK_DIMS.2 as f32,
+            )),
+            Chunk {
+                // compressed_chunk: vec![(0, 0)],
+                grid,
+                cords,
+                meta_data: metadata,
+                light,
+            },
+        ));
+        // A chunk that isn't rendered has no mesh, and is ready as soon as it's spawned. Greedy
+        // meshes keep the faces on the borders of the chunk, they aren't culled.
+        match mesh {
+            None => chunk_map.set_state(cords, ChunkState::Ready),
+            Some(mesh) if is_culled => {
+                ent.insert((NewChunkMesh(Some(mesh)), ToCull::new(cords)));
+                chunk_map.set_state(cords, ChunkState::AwaitingNeighbors);
+            }
+            Some(mesh) => {
+                ent.insert((NewChunkMesh(Some(mesh)), GreedyMeshed::default()));
+                chunk_map.set_state(cords, ChunkState::Ready);
+            }
+        }
+        spawned += 1;
+        if let Ok((counter_ent, mut loaded_chunks)) = loaded_chunks.get_single_mut() {
+            // Update the number of chunks loaded, and wether all the chunks (on startup) have
+            // been loaded initially.
+            match current_state.get() {
+                &InitialChunkLoadState::Loading => {
+                    loaded_chunks.0 += 1;
+                    if loaded_chunks.0
+                        == (render_distance * render_distance * WORLD_HEIGHT_CHUNKS) as usize
+                    {
+                        next_state.set(InitialChunkLoadState::MeshesLoaded);
+                        commands.entity(counter_ent).despawn();
+                        info!("\nInternal Log:\nMeshes have been loaded");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Quick system to check if all the Chunks have been initially loaded.
+fn check_if_loaded(
+    mut next_state: ResMut<NextState<InitialChunkLoadState>>,
+    chunk_map: Res<ChunkMap>,
+) {
+    if chunk_map
+        .states()
+        .any(|(_, state)| !state.is_spawned() && state != ChunkState::Unloading)
+    {
+        return;
+    }
+    next_state.set(InitialChunkLoadState::Complete);
+    info!("\nInternal Log:\nChunk entities have been successfully spawned");
+}
+
+pub(crate) fn handle_block_break_place(
+    mut block_change: EventReader<BlockChange>,
+    mut world: VoxelWorldMut,
+    mut light_updates: ResMut<LightUpdates>,
+    mut fluids: ResMut<FluidSim>,
+    mut edits: EventWriter<BlockEdited>,
+) {
+    for event in block_change.read() {
+        'A: for &(chunk, block, onto) in event.blocks.iter() {
+            let pos = IVec3::from(chunk_position_to_block(
+                chunk,
+                three_d_cords(block, CHUNK_DIMS),
+            ));
+            // The chunk might have been unloaded since the change was asked for.
+            let Some(vox) = world.get_block(pos) else {
+                continue;
+            };
+            let onto = onto.and_then(|(onto_chunk, onto)| {
+                world.get_block(IVec3::from(chunk_position_to_block(
+                    onto_chunk,
+                    three_d_cords(onto, CHUNK_DIMS),
+                )))
+            });
+            if vox == AIR && matches!(event.change, VoxelChange::Broken) {
+                continue 'A;
+            }
+            if (onto == Some(AIR) || vox != AIR) && matches!(event.change, VoxelChange::Added) {
+                if onto != Some(AIR) {
+                    break 'A;
+                }
+                continue 'A;
+            }
+
+            let new = match event.change {
+                VoxelChange::Added => event.block,
+                _ => AIR,
+            };
+            world.set_block(pos, new);
+            let pos = pos.to_array();
+            light_updates.0.push((pos, new));
+            // Water next to the block might start (or stop) flowing.
+            fluids.schedule_around(pos);
+            edits.send(BlockEdited {
+                pos,
+                old: vox,
+                new,
+                by_player: true,
+            });
+            break 'A;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    pub(crate) const RENDER_DISTANCE: i32 = 1;
+
+    // The directory a test world is saved in, it's removed with the app.
+    #[derive(Resource)]
+    struct TestWorldDir(PathBuf);
+
+    impl Drop for TestWorldDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // The world without a window, with one viewer the chunks are loaded around. Every app is saved
+    // in a new directory, so nothing is left of the worlds of other tests, or of the last run.
+    pub(crate) fn world_app(name: &str, rendered: bool) -> (App, Entity) {
+        static APPS: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "minecraft_bevy_{}_{}_{}",
+            name,
+            std::process::id(),
+            APPS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, SimulationPlugin))
+            .insert_resource(WorldSettings {
+                render_distance: RENDER_DISTANCE,
+                ..default()
+            })
+            .insert_resource(WorldSave { dir: dir.clone() })
+            .insert_resource(TestWorldDir(dir));
+        // The chunks get meshes, but nothing culls their faces, they stay AwaitingNeighbors.
+        if rendered {
+            app.insert_resource(RenderChunks);
+        }
+        let viewer = app
+            .world
+            .spawn((TransformBundle::default(), CurrentChunk([0, 0, 0])))
+            .id();
+        (app, viewer)
+    }
+
+    // Update until every chunk around the viewer is spawned, and the ones the viewer moved away
+    // from are gone, or it's taking too long.
+    pub(crate) fn load_chunks(app: &mut App) {
+        let side = (RENDER_DISTANCE * 2 + 1) as usize;
+        let count = side * side * WORLD_HEIGHT_CHUNKS as usize;
+        for _ in 0..2000 {
+            app.update();
+            let chunk_map = app.world.resource::<ChunkMap>();
+            let states: Vec<ChunkState> = chunk_map.states().map(|(_, state)| state).collect();
+            if states.len() == count && states.iter().all(|state| state.is_spawned()) {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+    }
+
+    fn move_viewer(app: &mut App, viewer: Entity, chunk: [i32; 3]) {
+        app.world.get_mut::<CurrentChunk>(viewer).unwrap().0 = chunk;
+        app.update();
+    }
+
+    // Update until every chunk around the viewer is spawned, and check that nothing is left of the
+    // chunks the viewer moved away from.
+    fn assert_settles(app: &mut App, viewer: Entity) {
+        load_chunks(app);
+        let center = app.world.get::<CurrentChunk>(viewer).unwrap().0;
+        let chunk_map = app.world.resource::<ChunkMap>();
+        let states: Vec<([i32; 3], ChunkState)> = chunk_map.states().collect();
+        assert!(
+            states.iter().all(|(_, state)| state.is_spawned()),
+            "Chunks stuck loading: {:?}",
+            states
+                .iter()
+                .filter(|(_, state)| !state.is_spawned())
+                .collect::<Vec<_>>()
+        );
+        assert!(states.iter().all(|(cords, _)| in_render_distance(
+            *cords,
+            center,
+            RENDER_DISTANCE
+        )));
+        let side = (RENDER_DISTANCE * 2 + 1) as usize;
+        assert_eq!(states.len(), side * side * WORLD_HEIGHT_CHUNKS as usize);
+
+        // Every chunk in the map has its entity, and there are no other chunks or tasks.
+        let spawned: Vec<([i32; 3], Entity)> = chunk_map.iter().collect();
+        for (cords, ent) in spawned.iter() {
+            assert_eq!(app.world.get::<Chunk>(*ent).unwrap().cords, *cords);
+        }
+        let chunks = app.world.query::<&Chunk>().iter(&app.world).count();
+        assert_eq!(chunks, spawned.len());
+        let tasks = app
+            .world
+            .query_filtered::<(), Or<(With<GenerateChunk>, With<ComputeChunk>)>>()
+            .iter(&app.world)
+            .count();
+        assert_eq!(tasks, 0);
+    }
+
+    #[test]
+    fn moving_every_frame_leaves_no_stale_chunks() {
+        for rendered in [false, true] {
+            let (mut app, viewer) = world_app("moving", rendered);
+            // Some steps to the next chunk, and some jumps far away and back, a few frames apart
+            // so some chunks are still loading and others done when the viewer leaves them.
+            let mut chunk = [0, 0, 0];
+            for step in 0..80 {
+                chunk = match step % 7 {
+                    0 => [chunk[0] + 40, 0, chunk[2] - 25],
+                    3 => [chunk[0] - 40, 0, chunk[2] + 25],
+                    _ => [chunk[0] + 1, 0, chunk[2] + (step % 3) - 1],
+                };
+                move_viewer(&mut app, viewer, chunk);
+                if step % 5 == 0 {
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                }
+            }
+            assert_settles(&mut app, viewer);
+        }
+    }
+
+    #[test]
+    fn going_back_and_forth_over_a_border() {
+        for rendered in [false, true] {
+            let (mut app, viewer) = world_app("back_and_forth", rendered);
+            // The chunks at the edges are unloaded and wanted again every frame, while they are in
+            // every state of loading.
+            for step in 0..60 {
+                move_viewer(&mut app, viewer, [step % 2, 0, 0]);
+                if step % 4 == 0 {
+                    std::thread::sleep(std::time::Duration::from_millis(3));
+                }
+            }
+            assert_settles(&mut app, viewer);
+            move_viewer(&mut app, viewer, [1, 0, 0]);
+            assert_settles(&mut app, viewer);
+        }
+    }
+}
@@ -50,10 +50,50 @@ pub struct Sun;
 #[derive(Resource)]
 pub struct CycleTimer(pub Timer);
 
-pub fn daylight_cycle(mut query: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>) {
+// The hour of the day, from 0 to 24. The sun is at its highest at noon.
+#[derive(Resource)]
+pub struct TimeOfDay(pub f32);
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        TimeOfDay(12.0)
+    }
+}
+
+const NOON_SUN_DIRECTION: Vec3 = Vec3::new(0.6, -1.0, 0.6);
+const NOON_ILLUMINANCE: f32 = 9000.0;
+
+pub fn daylight_cycle(
+    time_of_day: Res<TimeOfDay>,
+    mut query: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+) {
     if let Ok((mut light_trans, mut directional)) = query.get_single_mut() {
-        let t = Transform::from_xyz(0.0, 0.0, 0.0).looking_to(Vec3::new(0.6, -1.0, 0.6), Vec3::Y);
+        // The sun turns around the axis that is level with the ground, and perpendicular to where
+        // it shines at noon, so it rises and sets on opposite sides.
+        let angle = (time_of_day.0 - 12.0) / 12.0 * std::f32::consts::PI;
+        let axis = Vec3::new(1.0, 0.0, -1.0).normalize();
+        let direction = Quat::from_axis_angle(axis, angle) * NOON_SUN_DIRECTION;
+        let t = Transform::from_xyz(0.0, 0.0, 0.0).looking_to(direction, Vec3::Y);
         light_trans.rotation = t.rotation;
-        directional.illuminance = 9000.0;
+        // The lower the sun, the weaker it shines, and there is no sun at night.
+        let height = direction.normalize().y / NOON_SUN_DIRECTION.normalize().y;
+        directional.illuminance = NOON_ILLUMINANCE * height.max(0.0);
+    }
+}
+
+pub fn time_command(
+    mut commands: EventReader<ConsoleCommand>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    mut log: ResMut<ConsoleLog>,
+) {
+    for command in commands.read().filter(|command| command.name == "time") {
+        match (command.word(0), command.number(1)) {
+            (None, _) => log.print(format!("The time is {:.1}", time_of_day.0)),
+            (Some("set"), Some(hour)) => {
+                time_of_day.0 = hour.rem_euclid(24.0);
+                log.print(format!("Set the time to {:.1}", time_of_day.0));
+            }
+            _ => log.print("Usage: /time [set <hour>]"),
+        }
     }
 }
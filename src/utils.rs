@@ -1,3 +1,58 @@
+use crate::chunk::{HEIGHT, LENGTH, WIDTH, WORLD_HEIGHT_CHUNKS};
 use crate::Face;
 use crate::Face::*;
 use bevy::prelude::Vec3;
+
+// Blocks are centered around whole coordinates, so the block a position is inside of is found by
+// rounding it.
+pub fn position_to_block(pos: Vec3) -> [i32; 3] {
+    [
+        pos.x.round() as i32,
+        pos.y.round() as i32,
+        pos.z.round() as i32,
+    ]
+}
+
+// The chunk a block (in world coordinates) belongs to, and the position of the block inside of it.
+pub fn block_to_chunk_position(block: [i32; 3]) -> ([i32; 3], [usize; 3]) {
+    let dims = [WIDTH as i32, HEIGHT as i32, LENGTH as i32];
+    let mut chunk = [0; 3];
+    let mut local = [0; 3];
+    for i in 0..3 {
+        chunk[i] = block[i].div_euclid(dims[i]);
+        local[i] = block[i].rem_euclid(dims[i]) as usize;
+    }
+    (chunk, local)
+}
+
+// The world coordinates of a block inside of a chunk.
+pub fn chunk_position_to_block(chunk: [i32; 3], local: [usize; 3]) -> [i32; 3] {
+    [
+        chunk[0] * WIDTH as i32 + local[0] as i32,
+        chunk[1] * HEIGHT as i32 + local[1] as i32,
+        chunk[2] * LENGTH as i32 + local[2] as i32,
+    ]
+}
+
+pub fn position_to_chunk_cords(pos: Vec3) -> [i32; 3] {
+    block_to_chunk_position(position_to_block(pos)).0
+}
+
+// The coordinates next to the given ones in the direction of `face`, works for both chunk and
+// block coordinates.
+pub fn adjacent_cords(cords: [i32; 3], face: Face) -> [i32; 3] {
+    let [x, y, z] = cords;
+    match face {
+        Top => [x, y + 1, z],
+        Bottom => [x, y - 1, z],
+        Right => [x + 1, y, z],
+        Left => [x - 1, y, z],
+        Back => [x, y, z + 1],
+        Forward => [x, y, z - 1],
+    }
+}
+
+// Chunks are only stacked between the bottom and the top of the world.
+pub fn is_chunk_in_world(cords: [i32; 3]) -> bool {
+    (0..WORLD_HEIGHT_CHUNKS).contains(&cords[1])
+}
@@ -0,0 +1,367 @@
+use crate::*;
+use bevy::ecs::system::SystemParam;
+use bevy::utils::HashSet;
+
+// The block at `pos` in the loaded chunks, None if the chunk it's in isn't loaded (or is outside of
+// the world). `chunk` reads the chunk of an entity.
+fn block_in<'a>(
+    chunk_map: &ChunkMap,
+    chunk: impl FnOnce(Entity) -> Option<&'a Chunk>,
+    pos: IVec3,
+) -> Option<Block> {
+    let (cords, local) = block_to_chunk_position(pos.to_array());
+    Some(
+        chunk(chunk_map.get_ent(cords)?)?
+            .grid
+            .get(one_d_cords(local, CHUNK_DIMS)),
+    )
+}
+
+// The loaded chunks as one world of blocks, addressed by their position in world coordinates. The
+// borders of the chunks are handled here: reading a block finds the chunk it's in. It only reads,
+// so the systems that use it can run at the same time, the ones that change blocks use
+// `VoxelWorldMut`.
+#[derive(SystemParam)]
+pub struct VoxelWorld<'w, 's> {
+    chunks: Query<'w, 's, &'static Chunk>,
+    chunk_map: Res<'w, ChunkMap>,
+}
+
+impl VoxelWorld<'_, '_> {
+    pub fn chunk_map(&self) -> &ChunkMap {
+        &self.chunk_map
+    }
+
+    // The block at `pos`, None if the chunk it's in isn't loaded (or is outside of the world).
+    pub fn get_block(&self, pos: IVec3) -> Option<Block> {
+        block_in(&self.chunk_map, |ent| self.chunks.get(ent).ok(), pos)
+    }
+
+    // The six blocks around `pos`, even the ones in other chunks.
+    pub fn neighbors(&self, pos: IVec3) -> impl Iterator<Item = (Face, IVec3, Option<Block>)> + '_ {
+        (0..6).map(move |i| {
+            let face = Face::from(i);
+            let neighbor = IVec3::from(adjacent_cords(pos.to_array(), face));
+            (face, neighbor, self.get_block(neighbor))
+        })
+    }
+
+    // The blocks in the box between `min` and `max` (both inside of it), along x, then z, then y.
+    pub fn region(
+        &self,
+        min: IVec3,
+        max: IVec3,
+    ) -> impl Iterator<Item = (IVec3, Option<Block>)> + '_ {
+        (min.y..=max.y).flat_map(move |y| {
+            (min.z..=max.z).flat_map(move |z| {
+                (min.x..=max.x).map(move |x| {
+                    let pos = IVec3::new(x, y, z);
+                    (pos, self.get_block(pos))
+                })
+            })
+        })
+    }
+}
+
+// The loaded chunks, for the systems that change blocks. Changing a block saves its chunk, and
+// updates the meshes of the chunks whose faces it covers or uncovers.
+#[derive(SystemParam)]
+pub struct VoxelWorldMut<'w, 's> {
+    chunks: Query<'w, 's, &'static mut Chunk>,
+    chunk_map: Res<'w, ChunkMap>,
+    breg: Res<'w, BlockRegistry>,
+    commands: Commands<'w, 's>,
+}
+
+impl VoxelWorldMut<'_, '_> {
+    pub fn chunk_map(&self) -> &ChunkMap {
+        &self.chunk_map
+    }
+
+    // The block at `pos`, like `VoxelWorld::get_block`.
+    pub fn get_block(&self, pos: IVec3) -> Option<Block> {
+        block_in(&self.chunk_map, |ent| self.chunks.get(ent).ok(), pos)
+    }
+
+    // Put `block` at `pos`, and give back the block that was there. None if the chunk isn't loaded,
+    // then nothing is changed. The chunk is saved, and the meshes of it and of the chunks next to
+    // the block are updated.
+    pub fn set_block(&mut self, pos: IVec3, block: Block) -> Option<Block> {
+        let (cords, local) = block_to_chunk_position(pos.to_array());
+        let ent = self.chunk_map.get_ent(cords)?;
+        let mut chunk = self.chunks.get_mut(ent).ok()?;
+        let index = one_d_cords(local, CHUNK_DIMS);
+        let old = chunk.grid.set(index, block);
+        if old == block {
+            return Some(old);
+        }
+        self.commands.entity(ent).insert(ToSave);
+
+        // Culled meshes are updated in place when a block is added or broken. Greedy meshes, and
+        // blocks replaced by other blocks, are meshed again with the chunks around them.
+        let change = match (old, block) {
+            (AIR, _) => Some(VoxelChange::Added),
+            (_, AIR) => Some(VoxelChange::Broken),
+            _ => None,
+        };
+        let (Some(change), true) = (change, chunk.meta_data.is_some()) else {
+            self.commands.entity(ent).insert(ToRemesh);
+            for i in 0..6 {
+                let (adj_cords, _) =
+                    block_to_chunk_position(adjacent_cords(pos.to_array(), Face::from(i)));
+                if let Some(adj_ent) = self.chunk_map.get_ent(adj_cords).filter(|e| *e != ent) {
+                    self.commands.entity(adj_ent).insert(ToRemesh);
+                }
+            }
+            return Some(old);
+        };
+        let mut neighbors = [None; 6];
+        for (i, neighbor) in neighbors.iter_mut().enumerate() {
+            *neighbor = get_neighbor(index, Face::from(i), CHUNK_DIMS).map(|n| chunk.grid.get(n));
+        }
+        if let Some(meta_data) = chunk.meta_data.as_mut() {
+            let logged = if matches!(change, VoxelChange::Added) {
+                block
+            } else {
+                old
+            };
+            meta_data.log(change, index, logged, neighbors);
+        }
+        self.commands.entity(ent).insert(ToUpdate);
+
+        // The faces of the blocks across the borders of the chunk that touch it are covered, or
+        // uncovered.
+        let border_change = if matches!(change, VoxelChange::Added) {
+            VoxelChange::CullFaces
+        } else {
+            VoxelChange::AddFaces
+        };
+        for (face, neighbor) in get_neigbhors_from_across_chunks(CHUNK_DIMS, index) {
+            let Some(adj_ent) = self.chunk_map.get_ent(adjacent_cords(cords, face)) else {
+                continue;
+            };
+            let Ok(mut adj_chunk) = self.chunks.get_mut(adj_ent) else {
+                continue;
+            };
+            let adj_block = adj_chunk.grid.get(neighbor);
+            if !self.breg.is_covering(&adj_block, face.opposite()) {
+                continue;
+            }
+            if let Some(meta_data) = adj_chunk.meta_data.as_mut() {
+                let mut touching = [None; 6];
+                touching[face.opposite() as usize] = Some(old);
+                meta_data.log(border_change, neighbor, adj_block, touching);
+            }
+            self.commands.entity(adj_ent).insert(ToUpdate);
+        }
+        Some(old)
+    }
+
+    // Put a lot of blocks at once, like a region edit or flowing water does, and give back the ones
+    // that changed as (position, old block, new block). The meshes aren't updated block by block like
+    // with `set_block`: the chunks the blocks are in, and the chunks next to them, are meshed again
+    // from scratch, each only once.
+    pub fn set_blocks(
+        &mut self,
+        blocks: impl IntoIterator<Item = ([i32; 3], Block)>,
+    ) -> Vec<([i32; 3], Block, Block)> {
+        let mut changed = vec![];
+        let mut edited_chunks = HashSet::new();
+        let mut remeshed_chunks = HashSet::new();
+        for (pos, block) in blocks {
+            let (cords, local) = block_to_chunk_position(pos);
+            let Some(ent) = self.chunk_map.get_ent(cords) else {
+                continue;
+            };
+            let Ok(mut chunk) = self.chunks.get_mut(ent) else {
+                continue;
+            };
+            let old = chunk.grid.set(one_d_cords(local, CHUNK_DIMS), block);
+            if old == block {
+                continue;
+            }
+            changed.push((pos, old, block));
+            edited_chunks.insert(ent);
+            // The faces of the blocks around it change too, even across chunk borders.
+            remeshed_chunks.insert(cords);
+            for i in 0..6 {
+                remeshed_chunks
+                    .insert(block_to_chunk_position(adjacent_cords(pos, Face::from(i))).0);
+            }
+        }
+        for ent in edited_chunks {
+            self.commands.entity(ent).insert(ToSave);
+        }
+        for cords in remeshed_chunks {
+            if let Some(ent) = self.chunk_map.get_ent(cords) {
+                self.commands.entity(ent).insert(ToRemesh);
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::{load_chunks, world_app};
+    use bevy::ecs::system::SystemState;
+
+    // The chunks the blocks are edited in, in the middle of the loaded ones.
+    const CHUNK: [i32; 3] = [0, 2, 0];
+
+    // The chunks around the origin. Without rendering them, their meshes are greedy (they have no
+    // meta data), so every change marks the chunks to be meshed again from scratch. Rendered, they
+    // are culled, and adding or breaking a block updates their meshes in place.
+    fn loaded_world(name: &str, rendered: bool) -> App {
+        let (mut app, _) = world_app(name, rendered);
+        load_chunks(&mut app);
+        let chunk_map = app.world.resource::<ChunkMap>();
+        assert!(
+            chunk_map.states().all(|(_, state)| state.is_spawned()),
+            "The chunks never loaded"
+        );
+        app
+    }
+
+    // Which chunks are marked with `C`, the marks are taken off for the next edit.
+    fn take_marked<C: Component>(app: &mut App) -> HashSet<[i32; 3]> {
+        let marked: Vec<(Entity, [i32; 3])> = app
+            .world
+            .query_filtered::<(Entity, &Chunk), With<C>>()
+            .iter(&app.world)
+            .map(|(ent, chunk)| (ent, chunk.cords))
+            .collect();
+        for (ent, _) in marked.iter() {
+            app.world.entity_mut(*ent).remove::<C>();
+        }
+        marked.into_iter().map(|(_, cords)| cords).collect()
+    }
+
+    fn block_of_chunk(app: &App, cords: [i32; 3], local: [usize; 3]) -> Block {
+        let ent = app.world.resource::<ChunkMap>().get_ent(cords).unwrap();
+        let chunk = app.world.get::<Chunk>(ent).unwrap();
+        chunk.grid.get(one_d_cords(local, CHUNK_DIMS))
+    }
+
+    #[test]
+    fn set_block_on_the_borders_of_a_chunk() {
+        let mut app = loaded_world("set_block", false);
+        let mut state = SystemState::<VoxelWorldMut>::new(&mut app.world);
+        for axis in 0..3 {
+            for (local, across) in [(0, -1), (15, 1)] {
+                take_marked::<ToRemesh>(&mut app);
+                let mut local_pos = [5; 3];
+                local_pos[axis] = local;
+                let pos = IVec3::from(chunk_position_to_block(CHUNK, local_pos));
+                let mut world = state.get_mut(&mut app.world);
+                let old = world.get_block(pos).unwrap();
+                assert_eq!(world.set_block(pos, GLASS), Some(old));
+                state.apply(&mut app.world);
+
+                // The block is in the chunk at its local position, and the chunk across the
+                // border it touches is meshed again with it.
+                assert_eq!(block_of_chunk(&app, CHUNK, local_pos), GLASS);
+                let mut neighbor = CHUNK;
+                neighbor[axis] += across;
+                assert_eq!(
+                    take_marked::<ToRemesh>(&mut app),
+                    HashSet::from_iter([CHUNK, neighbor])
+                );
+                let chunk_map = app.world.resource::<ChunkMap>();
+                let (ent, neighbor) = (
+                    chunk_map.get_ent(CHUNK).unwrap(),
+                    chunk_map.get_ent(neighbor).unwrap(),
+                );
+                assert!(app.world.get::<ToSave>(ent).is_some());
+                assert!(app.world.get::<ToSave>(neighbor).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn set_blocks_across_a_corner() {
+        let mut app = loaded_world("set_blocks", false);
+        take_marked::<ToRemesh>(&mut app);
+        let corner = chunk_position_to_block(CHUNK, [15, 15, 15]);
+        let next = [corner[0] + 1, corner[1], corner[2]];
+        let mut state = SystemState::<VoxelWorldMut>::new(&mut app.world);
+        let mut world = state.get_mut(&mut app.world);
+        let old = [corner, next].map(|pos| world.get_block(pos.into()).unwrap());
+        let changed = world.set_blocks([(corner, GLASS), (next, GLASS), (corner, GLASS)]);
+        state.apply(&mut app.world);
+        assert_eq!(
+            changed,
+            vec![(corner, old[0], GLASS), (next, old[1], GLASS)]
+        );
+        assert_eq!(block_of_chunk(&app, CHUNK, [15, 15, 15]), GLASS);
+        assert_eq!(block_of_chunk(&app, [1, 2, 0], [0, 15, 15]), GLASS);
+        // Both chunks, and every chunk the faces around the two blocks are in.
+        assert_eq!(
+            take_marked::<ToRemesh>(&mut app),
+            HashSet::from_iter([CHUNK, [1, 2, 0], [0, 3, 0], [0, 2, 1], [1, 3, 0], [1, 2, 1]])
+        );
+
+        // Reading finds the chunk of blocks with negative coordinates too.
+        let mut state = SystemState::<VoxelWorld>::new(&mut app.world);
+        let world = state.get(&app.world);
+        let below_zero = world.get_block(IVec3::new(-1, 40, -16));
+        assert_eq!(
+            below_zero,
+            Some(block_of_chunk(&app, [-1, 2, -1], [15, 8, 0]))
+        );
+        assert_eq!(world.get_block(IVec3::new(0, -1, 0)), None);
+    }
+
+    #[test]
+    fn breaking_and_placing_updates_culled_meshes_across_borders() {
+        let mut app = loaded_world("culled", true);
+        // Below the surface, where the blocks on the borders are solid.
+        let chunk = [0, 1, 0];
+        let breg = app.world.resource::<BlockRegistry>().clone();
+        let mut state = SystemState::<VoxelWorldMut>::new(&mut app.world);
+        for axis in 0..3 {
+            for (local, across) in [(0, -1), (15, 1)] {
+                let mut neighbor = chunk;
+                neighbor[axis] += across;
+                // A block on the border, away from its edges, with a block across it whose face it
+                // covers.
+                let world = state.get_mut(&mut app.world);
+                let (pos, old) = (0..14 * 14)
+                    .find_map(|i| {
+                        let mut local_pos = [1 + i % 14, 5, 1 + i / 14];
+                        local_pos[axis] = local;
+                        let pos = chunk_position_to_block(chunk, local_pos);
+                        let face = (0..6)
+                            .map(Face::from)
+                            .find(|face| {
+                                block_to_chunk_position(adjacent_cords(pos, *face)).0 == neighbor
+                            })
+                            .unwrap();
+                        let block = world.get_block(pos.into())?;
+                        let across_block = world.get_block(adjacent_cords(pos, face).into())?;
+                        (block != AIR && breg.is_covering(&across_block, face.opposite()))
+                            .then_some((pos, block))
+                    })
+                    .expect("No solid blocks on the border");
+                let ent = app.world.resource::<ChunkMap>().get_ent(chunk).unwrap();
+                assert!(app.world.get::<Chunk>(ent).unwrap().meta_data.is_some());
+
+                // Breaking it uncovers the face across the border, placing it again covers it, both
+                // update the meshes of the two chunks in place.
+                for (new, was) in [(AIR, old), (old, AIR)] {
+                    take_marked::<ToUpdate>(&mut app);
+                    take_marked::<ToRemesh>(&mut app);
+                    let mut world = state.get_mut(&mut app.world);
+                    assert_eq!(world.set_block(pos.into(), new), Some(was));
+                    state.apply(&mut app.world);
+                    assert_eq!(
+                        take_marked::<ToUpdate>(&mut app),
+                        HashSet::from_iter([chunk, neighbor])
+                    );
+                    assert!(take_marked::<ToRemesh>(&mut app).is_empty());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,449 @@
+use crate::*;
+use bevy::ecs::system::SystemParam;
+
+// The most blocks one edit changes, or one copy takes, at once.
+const MAX_EDIT_VOLUME: i64 = 32768;
+// How many edits can be undone.
+const UNDO_HISTORY: usize = 16;
+
+// A box of blocks in world coordinates, both corners are inside of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub min: [i32; 3],
+    pub max: [i32; 3],
+}
+
+impl Region {
+    // The box between two opposite corners, in any order.
+    pub fn new(a: [i32; 3], b: [i32; 3]) -> Self {
+        Region {
+            min: [a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2])],
+            max: [a[0].max(b[0]), a[1].max(b[1]), a[2].max(b[2])],
+        }
+    }
+
+    // In i64, a box can be wider than an i32 can count.
+    pub fn size(&self) -> [i64; 3] {
+        [0, 1, 2].map(|axis| self.max[axis] as i64 - self.min[axis] as i64 + 1)
+    }
+
+    // Saturates, three sides of four billion blocks overflow even an i64.
+    pub fn volume(&self) -> i64 {
+        self.size()
+            .iter()
+            .fold(1, |volume, len| volume.saturating_mul(*len))
+    }
+
+    // The blocks in the box, along x, then y, then z.
+    pub fn iter(&self) -> impl Iterator<Item = [i32; 3]> {
+        let Region { min, max } = *self;
+        (min[0]..=max[0]).flat_map(move |x| {
+            (min[1]..=max[1]).flat_map(move |y| (min[2]..=max[2]).map(move |z| [x, y, z]))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mirror {
+    #[default]
+    None,
+    X,
+    Z,
+}
+
+impl Mirror {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Mirror::None),
+            "x" => Some(Mirror::X),
+            "z" => Some(Mirror::Z),
+            _ => None,
+        }
+    }
+}
+
+// How a clipboard is turned when it's pasted: first mirrored, then turned clockwise (looking down)
+// around the vertical axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PasteOrientation {
+    pub quarter_turns: u8,
+    pub mirror: Mirror,
+}
+
+impl PasteOrientation {
+    // Where the block at `offset` in a box of `size` ends up, and the size of the turned box.
+    pub fn apply(&self, mut offset: [i32; 3], mut size: [i32; 3]) -> ([i32; 3], [i32; 3]) {
+        match self.mirror {
+            Mirror::X => offset[0] = size[0] - 1 - offset[0],
+            Mirror::Z => offset[2] = size[2] - 1 - offset[2],
+            Mirror::None => {}
+        }
+        for _ in 0..self.quarter_turns % 4 {
+            offset = [size[2] - 1 - offset[2], offset[1], offset[0]];
+            size = [size[2], size[1], size[0]];
+        }
+        (offset, size)
+    }
+}
+
+// Blocks that were copied out of the world, to be pasted somewhere else. The blocks that weren't
+// loaded when they were copied are None, and are left as they are when pasting.
+#[derive(Resource, Clone, Default)]
+pub struct Clipboard {
+    size: [i32; 3],
+    blocks: Vec<Option<Block>>,
+}
+
+impl Clipboard {
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn volume(&self) -> i64 {
+        self.blocks.len() as i64
+    }
+
+    // The blocks to place to paste the clipboard with its lowest corner at `at`.
+    pub fn blocks_at(&self, at: [i32; 3], orientation: PasteOrientation) -> Vec<([i32; 3], Block)> {
+        let local = Region::new([0; 3], [0, 1, 2].map(|axis| self.size[axis] - 1));
+        local
+            .iter()
+            .zip(self.blocks.iter())
+            .filter_map(|(offset, block)| {
+                let (offset, _) = orientation.apply(offset, self.size);
+                Some(([0, 1, 2].map(|axis| at[axis] + offset[axis]), (*block)?))
+            })
+            .collect()
+    }
+}
+
+// The blocks that region edits replaced, the newest edit last, so they can be put back.
+#[derive(Resource, Default)]
+pub struct EditHistory(Vec<Vec<([i32; 3], Block)>>);
+
+// Edits whole regions of the loaded world. The blocks are written straight into the grids of the
+// chunks, and each chunk that changed is remeshed once, instead of once for every block.
+#[derive(SystemParam)]
+pub struct RegionEditor<'w, 's> {
+    world: VoxelWorldMut<'w, 's>,
+    light_updates: ResMut<'w, LightUpdates>,
+    fluids: ResMut<'w, FluidSim>,
+    history: ResMut<'w, EditHistory>,
+    edits: EventWriter<'w, BlockEdited>,
+}
+
+impl RegionEditor<'_, '_> {
+    // The block at `pos`, if the chunk it's in is loaded.
+    pub fn block(&self, pos: [i32; 3]) -> Option<Block> {
+        self.world.get_block(pos.into())
+    }
+
+    // All of these return how many blocks were changed, blocks in chunks that aren't loaded are
+    // left as they are.
+    pub fn fill(&mut self, region: Region, block: Block) -> usize {
+        self.apply(region.iter().map(|pos| (pos, block)).collect(), true)
+    }
+
+    pub fn replace(&mut self, region: Region, from: Block, to: Block) -> usize {
+        let changes = region
+            .iter()
+            .filter(|pos| self.block(*pos) == Some(from))
+            .map(|pos| (pos, to))
+            .collect();
+        self.apply(changes, true)
+    }
+
+    pub fn copy(&self, region: Region) -> Clipboard {
+        Clipboard {
+            // Copies are never bigger than MAX_EDIT_VOLUME, so each side fits.
+            size: region.size().map(|len| len as i32),
+            blocks: region.iter().map(|pos| self.block(pos)).collect(),
+        }
+    }
+
+    pub fn paste(
+        &mut self,
+        clipboard: &Clipboard,
+        at: [i32; 3],
+        orientation: PasteOrientation,
+    ) -> usize {
+        self.apply(clipboard.blocks_at(at, orientation), true)
+    }
+
+    // Put back the blocks the last edit replaced, None if there is nothing to undo.
+    pub fn undo(&mut self) -> Option<usize> {
+        let last = self.history.0.pop()?;
+        Some(self.apply(last, false))
+    }
+
+    fn apply(&mut self, changes: Vec<([i32; 3], Block)>, remember: bool) -> usize {
+        let mut replaced = vec![];
+        for (pos, old, new) in self.world.set_blocks(changes) {
+            replaced.push((pos, old));
+            self.light_updates.0.push((pos, new));
+            self.fluids.schedule_around(pos);
+            self.edits.send(BlockEdited {
+                pos,
+                old,
+                new,
+                by_player: false,
+            });
+        }
+        let changed = replaced.len();
+        if remember && changed > 0 {
+            // Undoing puts the blocks back in the opposite order they were changed in.
+            replaced.reverse();
+            self.history.0.push(replaced);
+            if self.history.0.len() > UNDO_HISTORY {
+                self.history.0.remove(0);
+            }
+        }
+        changed
+    }
+}
+
+// The commands to edit regions of the world: /fill, /replace, /copy, /paste and /undo.
+pub struct WorldEditPlugin;
+
+impl Plugin for WorldEditPlugin {
+    fn build(&self, app: &mut App) {
+        // Systems
+        app.add_systems(Update, world_edit_commands);
+
+        // Resources
+        app.init_resource::<Clipboard>()
+            .init_resource::<EditHistory>();
+
+        // Commands
+        let region = [
+            Param::required("x1", ArgKind::Int),
+            Param::required("y1", ArgKind::Int),
+            Param::required("z1", ArgKind::Int),
+            Param::required("x2", ArgKind::Int),
+            Param::required("y2", ArgKind::Int),
+            Param::required("z2", ArgKind::Int),
+        ];
+        app.register_command(CommandSpec {
+            name: "fill",
+            params: [&region[..], &[Param::required("block", ArgKind::Block)]].concat(),
+            help: "Fill a box with a block",
+        })
+        .register_command(CommandSpec {
+            name: "replace",
+            params: [
+                &region[..],
+                &[
+                    Param::required("from", ArgKind::Block),
+                    Param::required("to", ArgKind::Block),
+                ],
+            ]
+            .concat(),
+            help: "Replace one block with another in a box",
+        })
+        .register_command(CommandSpec {
+            name: "copy",
+            params: region.to_vec(),
+            help: "Copy a box to the clipboard",
+        })
+        .register_command(CommandSpec {
+            name: "paste",
+            params: vec![
+                Param::required("x", ArgKind::Int),
+                Param::required("y", ArgKind::Int),
+                Param::required("z", ArgKind::Int),
+                Param::optional("turns", ArgKind::Int),
+                Param::optional("mirror", ArgKind::Word),
+            ],
+            help: "Paste the clipboard with its lowest corner at x y z, turned clockwise and \
+                   mirrored along x or z",
+        })
+        .register_command(CommandSpec {
+            name: "undo",
+            params: vec![],
+            help: "Undo the last fill, replace or paste",
+        });
+    }
+}
+
+fn world_edit_commands(
+    mut commands: EventReader<ConsoleCommand>,
+    mut editor: RegionEditor,
+    mut clipboard: ResMut<Clipboard>,
+    connection: Option<Res<ServerConnection>>,
+    mut log: ResMut<ConsoleLog>,
+) {
+    for command in commands.read() {
+        if !matches!(command.name, "fill" | "replace" | "copy" | "paste" | "undo") {
+            continue;
+        }
+        if connection.is_some() {
+            log.print(format!(
+                "Only the server can edit the world, run /{} in its console",
+                command.name
+            ));
+            continue;
+        }
+        let region = (0..6)
+            .map(|i| command.int(i))
+            .collect::<Option<Vec<i32>>>()
+            .map(|cords| {
+                Region::new(
+                    [cords[0], cords[1], cords[2]],
+                    [cords[3], cords[4], cords[5]],
+                )
+            });
+        let volume = match command.name {
+            "paste" => clipboard.volume(),
+            _ => region.map_or(0, |region| region.volume()),
+        };
+        if volume > MAX_EDIT_VOLUME {
+            log.print(format!(
+                "Can't edit {} blocks, the most is {}",
+                volume, MAX_EDIT_VOLUME
+            ));
+            continue;
+        }
+        match (command.name, region) {
+            ("fill", Some(region)) => {
+                let Some(block) = command.block(6) else {
+                    continue;
+                };
+                log.print(format!("Filled {} blocks", editor.fill(region, block)));
+            }
+            ("replace", Some(region)) => {
+                let (Some(from), Some(to)) = (command.block(6), command.block(7)) else {
+                    continue;
+                };
+                let replaced = editor.replace(region, from, to);
+                log.print(format!("Replaced {} blocks", replaced));
+            }
+            ("copy", Some(region)) => {
+                *clipboard = editor.copy(region);
+                log.print(format!("Copied {} blocks", clipboard.volume()));
+            }
+            ("paste", _) => {
+                if clipboard.is_empty() {
+                    log.print("The clipboard is empty, /copy something first");
+                    continue;
+                }
+                let Some(mirror) = Mirror::from_name(command.word(4).unwrap_or("none")) else {
+                    log.print("The mirror has to be none, x or z");
+                    continue;
+                };
+                let (Some(x), Some(y), Some(z)) = (command.int(0), command.int(1), command.int(2))
+                else {
+                    continue;
+                };
+                let orientation = PasteOrientation {
+                    quarter_turns: command.int(3).unwrap_or(0).rem_euclid(4) as u8,
+                    mirror,
+                };
+                let pasted = editor.paste(&clipboard, [x, y, z], orientation);
+                log.print(format!("Pasted {} blocks", pasted));
+            }
+            ("undo", _) => match editor.undo() {
+                Some(changed) => log.print(format!("Undid the last edit, {} blocks", changed)),
+                None => log.print("There is nothing to undo"),
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turned(quarter_turns: u8, mirror: Mirror, offset: [i32; 3]) -> ([i32; 3], [i32; 3]) {
+        PasteOrientation {
+            quarter_turns,
+            mirror,
+        }
+        .apply(offset, [3, 1, 2])
+    }
+
+    #[test]
+    fn corners_can_be_in_any_order() {
+        let region = Region {
+            min: [-1, 0, 2],
+            max: [3, 5, 4],
+        };
+        assert_eq!(Region::new([-1, 0, 2], [3, 5, 4]), region);
+        assert_eq!(Region::new([3, 5, 4], [-1, 0, 2]), region);
+        assert_eq!(Region::new([-1, 5, 4], [3, 0, 2]), region);
+        assert_eq!(region.size(), [5, 6, 3]);
+        assert_eq!(region.volume(), 90);
+        assert_eq!(region.iter().count(), 90);
+    }
+
+    #[test]
+    fn huge_regions_dont_overflow() {
+        let wide = Region::new([-2000000000, 0, 0], [2000000000, 0, 0]);
+        assert_eq!(wide.size(), [4000000001, 1, 1]);
+        assert_eq!(wide.volume(), 4000000001);
+        let everything = Region::new([i32::MIN; 3], [i32::MAX; 3]);
+        assert_eq!(everything.volume(), i64::MAX);
+    }
+
+    #[test]
+    fn quarter_turns() {
+        // A 3 wide, 2 deep box, the corner at x 0, z 0 goes around clockwise.
+        assert_eq!(turned(0, Mirror::None, [0, 0, 0]), ([0, 0, 0], [3, 1, 2]));
+        assert_eq!(turned(1, Mirror::None, [0, 0, 0]), ([1, 0, 0], [2, 1, 3]));
+        assert_eq!(turned(2, Mirror::None, [0, 0, 0]), ([2, 0, 1], [3, 1, 2]));
+        assert_eq!(turned(3, Mirror::None, [0, 0, 0]), ([0, 0, 2], [2, 1, 3]));
+        assert_eq!(turned(4, Mirror::None, [2, 0, 1]), ([2, 0, 1], [3, 1, 2]));
+    }
+
+    #[test]
+    fn mirrors() {
+        assert_eq!(turned(0, Mirror::X, [0, 0, 1]), ([2, 0, 1], [3, 1, 2]));
+        assert_eq!(turned(0, Mirror::Z, [0, 0, 1]), ([0, 0, 0], [3, 1, 2]));
+    }
+
+    #[test]
+    fn mirroring_comes_before_turning() {
+        // Mirrored along x to [2, 0, 0], then turned a quarter.
+        assert_eq!(turned(1, Mirror::X, [0, 0, 0]), ([1, 0, 2], [2, 1, 3]));
+        // Turned first to [1, 0, 0] and then mirrored, it would have ended up at [0, 0, 0].
+        assert_ne!(turned(1, Mirror::X, [0, 0, 0]).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn pasted_blocks() {
+        let clipboard = Clipboard {
+            size: [2, 1, 1],
+            blocks: vec![Some(STONE), None],
+        };
+        assert_eq!(clipboard.volume(), 2);
+        // Blocks that weren't loaded are left out.
+        assert_eq!(
+            clipboard.blocks_at([10, 20, 30], PasteOrientation::default()),
+            vec![([10, 20, 30], STONE)]
+        );
+        let turned = PasteOrientation {
+            quarter_turns: 1,
+            mirror: Mirror::None,
+        };
+        assert_eq!(
+            clipboard.blocks_at([10, 20, 30], turned),
+            vec![([10, 20, 30], STONE)]
+        );
+        let mirrored = PasteOrientation {
+            quarter_turns: 0,
+            mirror: Mirror::X,
+        };
+        assert_eq!(
+            clipboard.blocks_at([10, 20, 30], mirrored),
+            vec![([11, 20, 30], STONE)]
+        );
+        let both = PasteOrientation {
+            quarter_turns: 1,
+            mirror: Mirror::X,
+        };
+        assert_eq!(
+            clipboard.blocks_at([10, 20, 30], both),
+            vec![([10, 20, 31], STONE)]
+        );
+    }
+}